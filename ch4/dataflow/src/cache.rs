@@ -0,0 +1,278 @@
+use crate::{
+    descriptor::{descriptor::Descriptor, expand::Overrides},
+    download_file, source_is_url,
+};
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use sha2::{Digest, Sha256};
+#[cfg(unix)]
+use std::os::unix::prelude::PermissionsExt;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// 用于指定下载缓存目录的环境变量
+pub const DATAFLOW_CACHE_DIR_ENV: &str = "DATAFLOW_CACHE_DIR";
+
+/// 下载缓存所在的目录
+/// 内容寻址的缓存可以在多个dataflow之间共享，优先使用环境变量，其次是用户的缓存目录
+pub fn cache_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os(DATAFLOW_CACHE_DIR_ENV) {
+        return PathBuf::from(dir);
+    }
+    if let Some(dir) = std::env::var_os("XDG_CACHE_HOME") {
+        return PathBuf::from(dir).join("dataflow");
+    }
+    if let Some(home) = std::env::var_os("HOME") {
+        return PathBuf::from(home).join(".cache").join("dataflow");
+    }
+    std::env::temp_dir().join("dataflow-cache")
+}
+
+/// 内容文件的路径，使用内容的sha256作为文件名
+pub(crate) fn blob_path(digest: &str) -> PathBuf {
+    cache_dir().join("sha256").join(digest)
+}
+
+/// url 索引文件的路径，使用url的sha256作为文件名
+/// 索引文件的内容为 `<digest> <url>`
+fn url_index_path(url: &str) -> PathBuf {
+    cache_dir()
+        .join("urls")
+        .join(hex::encode(Sha256::digest(url.as_bytes())))
+}
+
+/// 下载和写入索引时使用的临时文件路径，与最终文件位于同一个文件系统，保证rename是原子的
+fn temp_path() -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    cache_dir().join("tmp").join(format!(
+        "{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    ))
+}
+
+/// 检查是否是合法的sha256摘要，即64位的16进制字符串
+pub(crate) fn validate_digest(digest: &str) -> Result<()> {
+    if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("`{digest}` is not a valid sha256 digest, expected 64 hex characters");
+    }
+    Ok(())
+}
+
+/// 计算文件内容的sha256
+pub(crate) fn file_digest(path: &Path) -> Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("failed to open `{}`", path.display()))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("failed to read `{}`", path.display()))?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// 校验文件内容与期望的sha256是否一致
+pub(crate) fn verify(path: &Path, expected: &str) -> Result<()> {
+    let actual = file_digest(path)?;
+    if !actual.eq_ignore_ascii_case(expected) {
+        bail!(
+            "sha256 mismatch for `{}`: expected {expected}, got {actual}",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// 读取url索引，得到上一次下载内容的sha256
+fn read_index(url: &str) -> Option<String> {
+    let index = std::fs::read_to_string(url_index_path(url)).ok()?;
+    let (digest, _) = index.split_once(' ')?;
+    Some(digest.to_owned())
+}
+
+/// 原子地写入url索引
+fn write_index(url: &str, digest: &str) -> Result<()> {
+    let index_path = url_index_path(url);
+    let temp = temp_path();
+    std::fs::create_dir_all(index_path.parent().unwrap()).context("failed to create cache dir")?;
+    std::fs::create_dir_all(temp.parent().unwrap()).context("failed to create cache dir")?;
+    std::fs::write(&temp, format!("{digest} {url}")).context("failed to write url index")?;
+    std::fs::rename(&temp, &index_path).context("failed to move url index into cache")?;
+    Ok(())
+}
+
+/// 在缓存中查找url对应的文件，找到时会校验内容
+/// 指定了sha256时直接按内容查找，否则通过url索引查找
+pub(crate) fn cached_path(url: &str, sha256: Option<&str>) -> Option<PathBuf> {
+    let digest = match sha256 {
+        Some(digest) => digest.to_ascii_lowercase(),
+        None => read_index(url)?,
+    };
+    let path = blob_path(&digest);
+    if !path.exists() {
+        return None;
+    }
+    match verify(&path, &digest) {
+        Ok(()) => Some(path),
+        Err(e) => {
+            // 缓存内容被破坏，删除后重新下载
+            warn!("drop corrupted cache entry: {e:#}");
+            let _ = std::fs::remove_file(&path);
+            None
+        }
+    }
+}
+
+/// url 是否已经下载到了缓存中
+pub(crate) fn is_cached(url: &str, sha256: Option<&str>) -> bool {
+    cached_path(url, sha256).is_some()
+}
+
+/// 获取url对应的本地文件，返回的文件都已经过校验
+/// refresh 为 true 且没有指定sha256时，忽略缓存重新下载，使远端的变化能被感知
+/// executable 为 true 时，校验通过后设置可执行权限
+pub(crate) async fn fetch(
+    url: &str,
+    sha256: Option<&str>,
+    refresh: bool,
+    executable: bool,
+) -> Result<PathBuf> {
+    if let Some(digest) = sha256 {
+        validate_digest(digest)?;
+    }
+    if !refresh || sha256.is_some() {
+        if let Some(path) = cached_path(url, sha256) {
+            debug!("Download file: {url} using cache: {}", path.display());
+            return Ok(path);
+        }
+    }
+
+    // 先下载到临时文件，校验通过后再移动到内容对应的位置
+    let temp = temp_path();
+    let digest = match download_file(url, &temp).await {
+        Ok(digest) => digest,
+        Err(e) => {
+            let _ = std::fs::remove_file(&temp);
+            return Err(e);
+        }
+    };
+    if let Some(expected) = sha256 {
+        if !digest.eq_ignore_ascii_case(expected) {
+            let _ = std::fs::remove_file(&temp);
+            bail!("sha256 mismatch for `{url}`: expected {expected}, got {digest}");
+        }
+    }
+    #[cfg(unix)]
+    if executable {
+        std::fs::set_permissions(&temp, std::fs::Permissions::from_mode(0o755))
+            .context("failed to make downloaded file executable")?;
+    }
+    #[cfg(not(unix))]
+    let _ = executable;
+
+    let path = blob_path(&digest);
+    std::fs::create_dir_all(path.parent().unwrap()).context("failed to create cache dir")?;
+    std::fs::rename(&temp, &path).context("failed to move downloaded file into cache")?;
+    write_index(url, &digest)?;
+    info!("Download file: {url} -> sha256:{digest}");
+    Ok(path)
+}
+
+/// 收集描述文件中所有url类型的source，以及它们声明的sha256
+fn collect_urls(descriptor: &Descriptor) -> BTreeMap<String, (Option<String>, bool)> {
+    descriptor
+        .resolve_node_defaults()
+        .iter()
+        .flat_map(|node| node.kind.operators.iter())
+        .filter(|operator| source_is_url(&operator.config.source.to_string()))
+        .map(|operator| {
+            (
+                operator.config.source.to_string(),
+                (
+                    operator.config.sha256.clone(),
+                    operator.config.source.kind() == "exe_target",
+                ),
+            )
+        })
+        .collect()
+}
+
+/// 预先下载描述文件中所有url类型的source到缓存中
+/// 没有指定sha256的url会重新下载，之后的校验和执行都只需要访问本地缓存
+pub async fn prefetch(descriptor: &Descriptor) -> Result<()> {
+    for (url, (sha256, executable)) in collect_urls(descriptor) {
+        info!("Prefetch {url}");
+        fetch(&url, sha256.as_deref(), true, executable)
+            .await
+            .with_context(|| format!("failed to prefetch `{url}`"))?;
+    }
+    Ok(())
+}
+
+/// 读取描述文件，并预先下载其中所有url类型的source
+/// overrides 为命令行选择的profile和覆盖的变量
+pub async fn prefetch_dataflow(dataflow: PathBuf, overrides: &Overrides) -> Result<()> {
+    let descriptor = Descriptor::blocking_read_with_overrides(&dataflow, overrides)
+        .with_context(|| format!("failed to read dataflow at `{}`", dataflow.display()))?;
+    prefetch(&descriptor).await
+}
+
+/// 列出缓存中的所有文件，以及指向它们的url
+pub fn list() -> Result<()> {
+    let dir = cache_dir();
+    let mut urls: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    if let Ok(entries) = std::fs::read_dir(dir.join("urls")) {
+        for entry in entries.flatten() {
+            let index = std::fs::read_to_string(entry.path()).unwrap_or_default();
+            if let Some((digest, url)) = index.split_once(' ') {
+                urls.entry(digest.to_owned())
+                    .or_default()
+                    .push(url.to_owned());
+            }
+        }
+    }
+    let mut blobs = match std::fs::read_dir(dir.join("sha256")) {
+        Ok(entries) => entries.flatten().collect::<Vec<_>>(),
+        Err(_) => vec![],
+    };
+    blobs.sort_by_key(|entry| entry.file_name());
+    println!("cache dir: {}", dir.display());
+    for blob in blobs {
+        let digest = blob.file_name().to_string_lossy().to_string();
+        let size = blob.metadata().map(|m| m.len()).unwrap_or_default();
+        let sources = urls.remove(&digest).unwrap_or_default().join(", ");
+        println!("sha256:{digest}  {size:>10}  {sources}");
+    }
+    Ok(())
+}
+
+/// 清空下载缓存
+pub fn clean() -> Result<()> {
+    let dir = cache_dir();
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)
+            .with_context(|| format!("failed to remove cache dir `{}`", dir.display()))?;
+    }
+    println!("removed cache dir: {}", dir.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let path = std::env::temp_dir().join(format!("dataflow-verify-{}", std::process::id()));
+        std::fs::write(&path, b"hello").unwrap();
+        let digest = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        assert_eq!(file_digest(&path).unwrap(), digest);
+        assert!(verify(&path, digest).is_ok());
+        assert!(verify(&path, &"0".repeat(64)).is_err());
+        assert!(validate_digest(digest).is_ok());
+        assert!(validate_digest("abc").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,3 +1,10 @@
+use crate::{
+    descriptor::{
+        check::DiagnosticFormat, descriptor::HumanDuration, expand::Overrides,
+        visualize::GraphFormat,
+    },
+    launch::logs::LogStream,
+};
 use clap::{ArgAction, Parser, Subcommand};
 use env_logger::Env;
 use log::Level;
@@ -20,17 +27,68 @@ pub struct Args {
 pub enum Command {
     /// 该命令是查看整个dataflow集群的数据流图的
     /// 应该包括静态的和动态的
-    /// Print Graphviz representation of the given descriptor file or Show dataflow file as mermaid graph. Use --open to open browser.
+    /// Print Graphviz (dot/svg), mermaid or json representation of the given descriptor file with --format, or Show dataflow file as html graph. Use --open to open browser, --offline to render without network.
     Show {
         /// yaml file dataflow path
         #[arg(short, long, value_name = "FILE")]
         dataflow: PathBuf,
-        /// 输出 mermaid 内容和 open互斥
-        #[clap(short, long, action, conflicts_with = "open")]
+        /// 输出 mermaid 内容和 open互斥，等同于 --format mermaid
+        #[clap(short, long, action, conflicts_with_all = ["open", "format"])]
         mermaid: bool,
+        /// 输出指定格式的图内容，和 open互斥
+        #[arg(short, long, value_enum, conflicts_with = "open")]
+        format: Option<GraphFormat>,
         /// 打开浏览器查看 mermaid， mermaid互斥
         #[clap(short, long, action, conflicts_with = "mermaid")]
         open: bool,
+        /// 生成的html中嵌入本地graphviz渲染的svg，不需要访问网络
+        #[clap(long, action)]
+        offline: bool,
+        /// 输出展开include、模板、profile和变量之后实际生效的描述文件
+        #[clap(long, action, conflicts_with_all = ["mermaid", "format", "open"])]
+        resolved: bool,
+        #[command(flatten)]
+        overrides: Overrides,
+    },
+    /// 该命令会检查描述文件，一次性输出所有的错误和警告
+    /// Check the given dataflow path and print all diagnostics.
+    Check {
+        /// yaml description file path
+        #[arg(value_name = "FILE")]
+        dataflow: PathBuf,
+        /// 诊断信息的输出格式
+        #[arg(long, value_enum, default_value_t = DiagnosticFormat::Human)]
+        format: DiagnosticFormat,
+        /// 是否会执行build，为true时对可执行文件的检查会放宽
+        #[clap(long, action)]
+        build: bool,
+        /// url类型的source只检查本地的下载缓存，不发起网络请求
+        #[clap(long, action)]
+        offline: bool,
+        #[command(flatten)]
+        overrides: Overrides,
+    },
+    /// 该命令会输出描述文件的JSON Schema，用于编辑器的自动补全
+    /// Print the JSON Schema of the descriptor file.
+    Schema {
+        /// 写入到文件，不指定时输出到标准输出
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// 该命令会预先下载描述文件中所有url类型的source到本地缓存
+    /// Download all url sources of the given dataflow into the local cache.
+    Prefetch {
+        /// yaml description file path
+        #[arg(short, long, value_name = "FILE")]
+        dataflow: PathBuf,
+        #[command(flatten)]
+        overrides: Overrides,
+    },
+    /// 该命令用于管理url类型source的下载缓存
+    /// Manage the content-addressed download cache.
+    Cache {
+        #[clap(subcommand)]
+        command: CacheCommand,
     },
     /// 该命令会启动一个dataflow
     /// Start the given dataflow path.
@@ -42,6 +100,44 @@ pub enum Command {
         /// 是否执行build
         #[clap(long, action)]
         build: bool,
+        /// 不预先下载url类型的source，只使用本地缓存
+        #[clap(long, action)]
+        offline: bool,
+        /// 同时执行的构建命令数量，默认为cpu的数量
+        #[arg(short, long, value_name = "N")]
+        jobs: Option<usize>,
+        /// 节点的输出只写入日志文件，不转发到控制台
+        #[clap(short, long, action)]
+        quiet: bool,
+        #[command(flatten)]
+        overrides: Overrides,
+    },
+    /// 该命令会按时间顺序输出dataflow各个节点的日志
+    /// Print the structured logs of the given dataflow.
+    Logs {
+        /// yaml description file path
+        #[arg(short, long, value_name = "FILE")]
+        dataflow: PathBuf,
+        /// 只输出指定节点的日志
+        #[arg(short, long, value_name = "NodeID")]
+        node: Option<String>,
+        /// 只输出指定operator的日志
+        #[arg(long, value_name = "OperatorID")]
+        operator: Option<String>,
+        /// 只输出指定输出流的日志
+        #[arg(long, value_enum)]
+        stream: Option<LogStream>,
+        /// 只输出不低于该级别的日志，如 warn
+        #[arg(short, long)]
+        level: Option<Level>,
+        /// 只输出最后的n条日志
+        #[arg(short, long, value_name = "N")]
+        tail: Option<usize>,
+        /// 以json格式输出日志记录
+        #[clap(long, action)]
+        json: bool,
+        #[command(flatten)]
+        overrides: Overrides,
     },
     /// 该命令会启动指定dataflow中的一个节点
     /// Start one Node of a given dataflow path and given NodeId.
@@ -55,7 +151,35 @@ pub enum Command {
         /// 是否执行build
         #[clap(long, action)]
         build: bool,
+        /// url类型的source只检查本地的下载缓存，不发起网络请求
+        #[clap(long, action)]
+        offline: bool,
+        #[command(flatten)]
+        overrides: Overrides,
     },
+    /// 该命令会推进使用模拟时钟的dataflow的时间，输出推进之后的模拟时间
+    /// Advance the simulated clock of a running dataflow with `deploy.clock: simulated`.
+    Clock {
+        /// yaml description file path
+        #[arg(short, long, value_name = "FILE")]
+        dataflow: PathBuf,
+        /// 推进的时长，如 100ms、5s
+        #[arg(short, long, value_name = "DURATION")]
+        advance: HumanDuration,
+        #[command(flatten)]
+        overrides: Overrides,
+    },
+}
+
+/// 下载缓存的子命令
+#[derive(Debug, Subcommand)]
+pub enum CacheCommand {
+    /// 列出缓存中的文件及其来源url
+    /// List cached files with their sha256, size and source urls.
+    Ls,
+    /// 清空下载缓存
+    /// Remove all cached files.
+    Clean,
 }
 
 impl Args {
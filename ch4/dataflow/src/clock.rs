@@ -0,0 +1,394 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    communication::{
+        pub_sub::ZenohCommunicationLayer, Backend, PubSubCommunicationLayer, Publisher,
+    },
+    descriptor::{
+        descriptor::{ClockMode, Descriptor, HumanDuration, InputMapping},
+        expand::Overrides,
+    },
+};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{watch, OwnedMutexGuard};
+use uuid::Uuid;
+
+/// 模拟时钟的起点 2000-01-01T00:00:00Z，每次运行得到的时间戳都相同
+pub const SIMULATED_EPOCH: Duration = Duration::from_secs(946_684_800);
+/// 测试驱动请求推进模拟时钟的topic
+pub const ADVANCE_TOPIC: &str = "dataflow/clock/advance";
+/// 定时器节点发布模拟时间的output，topic为 `dataflow/timer/clock`
+pub const CLOCK_OUTPUT: &str = "clock";
+pub const CLOCK_TOPIC: &str = "dataflow/timer/clock";
+
+/// 开启模拟时钟时的当前时间，没有开启时为空
+static SIMULATED: Lazy<RwLock<Option<SystemTime>>> = Lazy::new(|| RwLock::new(None));
+/// 模拟时间每次变化时通知 `sleep_until` 的等待者
+static UPDATES: Lazy<watch::Sender<()>> = Lazy::new(|| watch::channel(()).0);
+/// 模拟时钟是进程级别的，进程内同时只能运行一个使用模拟时钟的数据流
+static EXCLUSIVE: Lazy<Arc<tokio::sync::Mutex<()>>> = Lazy::new(Default::default);
+
+/// 当前进程使用模拟时钟，时间从 SIMULATED_EPOCH 开始，只在被推进时前进
+pub fn simulate() {
+    let mut simulated = SIMULATED.write().unwrap();
+    if simulated.is_none() {
+        *simulated = Some(UNIX_EPOCH + SIMULATED_EPOCH);
+    }
+}
+
+/// 进程内运行使用模拟时钟的数据流期间持有，之后的数据流等待它结束
+pub(crate) async fn exclusive() -> OwnedMutexGuard<()> {
+    EXCLUSIVE.clone().lock_owned().await
+}
+
+/// 恢复使用系统时间，进程内的测试工具停止模拟时钟的数据流时调用
+pub(crate) fn reset() {
+    *SIMULATED.write().unwrap() = None;
+    UPDATES.send_replace(());
+}
+
+pub fn is_simulated() -> bool {
+    SIMULATED.read().unwrap().is_some()
+}
+
+/// 当前时间，开启模拟时钟时为模拟时间
+pub fn now() -> SystemTime {
+    SIMULATED.read().unwrap().unwrap_or_else(SystemTime::now)
+}
+
+/// 设置模拟时间，模拟时间只会前进
+pub(crate) fn set(now: SystemTime) {
+    if let Some(simulated) = SIMULATED.write().unwrap().as_mut() {
+        *simulated = (*simulated).max(now);
+    }
+    UPDATES.send_replace(());
+}
+
+/// 等待 `now()` 到达 deadline，开启模拟时钟时只在模拟时间被推进之后返回
+pub(crate) async fn sleep_until(deadline: SystemTime) {
+    // 先订阅再读取时间，之间的推进不会被错过
+    let mut updates = UPDATES.subscribe();
+    loop {
+        let now = now();
+        if now >= deadline {
+            return;
+        }
+        if !is_simulated() {
+            tokio::time::sleep(deadline.duration_since(now).unwrap_or_default()).await;
+            return;
+        }
+        // 发送者是静态的，不会被关闭
+        let _ = updates.changed().await;
+    }
+}
+
+/// 推进模拟时钟的请求，重发的请求使用相同的id
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct AdvanceRequest {
+    pub id: String,
+    pub by: HumanDuration,
+}
+
+/// 定时器节点发布的模拟时间，处理完推进请求之后带上请求的id
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ClockUpdate {
+    pub now: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ack: Option<String>,
+}
+
+impl ClockUpdate {
+    pub(crate) fn new(now: SystemTime, ack: Option<String>) -> Self {
+        Self {
+            now: humantime::format_rfc3339_micros(now).to_string(),
+            ack,
+        }
+    }
+
+    fn time(&self) -> Result<SystemTime> {
+        humantime::parse_rfc3339(&self.now).with_context(|| format!("invalid time `{}`", self.now))
+    }
+}
+
+/// 订阅定时器节点发布的模拟时间，返回每次更新的接收端
+/// 订阅者是阻塞的，在独立的线程中接收
+fn subscribe_clock(
+    communication: &mut dyn PubSubCommunicationLayer,
+) -> Result<flume::Receiver<ClockUpdate>> {
+    let mut subscriber = communication
+        .subscribe(CLOCK_TOPIC)
+        .map_err(|e| anyhow!("failed to subscribe topic:{CLOCK_TOPIC}: {e}"))?;
+    let (tx, rx) = flume::unbounded();
+    std::thread::spawn(move || {
+        while let Ok(Some(data)) = subscriber.recv() {
+            match serde_json::from_slice(&data) {
+                Ok(update) => {
+                    if tx.send(update).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => warn!("invalid clock update: {e}"),
+            }
+        }
+    });
+    Ok(rx)
+}
+
+/// 节点进程跟随定时器节点发布的模拟时间
+pub(crate) fn follow(endpoints: Vec<String>, node_id: &str) -> Result<()> {
+    simulate();
+    let mut communication = ZenohCommunicationLayer::init(
+        endpoints,
+        "peer".to_string(),
+        format!("dataflow/clock/{node_id}"),
+    )?;
+    let updates = subscribe_clock(&mut communication)?;
+    std::thread::spawn(move || {
+        let _communication = communication;
+        for update in updates.iter() {
+            match update.time() {
+                Ok(now) => set(now),
+                Err(e) => warn!("{e:#}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// 测试驱动，推进 `deploy.clock: simulated` 的dataflow的模拟时钟并检查节点的输出
+/// 进程内的测试工具通过 `RunningDataflow::advance` 推进，不需要直接使用
+/// ```no_run
+/// # use dataflow::{clock::ClockDriver, descriptor::descriptor::Descriptor};
+/// # use std::time::Duration;
+/// # fn test(descriptor: &Descriptor) -> anyhow::Result<()> {
+/// let mut driver = ClockDriver::new(descriptor)?;
+/// driver.watch("counter/count")?;
+/// driver.advance(Duration::from_secs(5))?;
+/// assert_eq!(driver.expect("counter/count")?, b"50");
+/// # Ok(())
+/// # }
+/// ```
+pub struct ClockDriver {
+    communication: Box<dyn PubSubCommunicationLayer>,
+    advance: Box<dyn Publisher>,
+    clock: flume::Receiver<ClockUpdate>,
+    descriptor: Descriptor,
+    outputs: BTreeMap<String, flume::Receiver<Vec<u8>>>,
+    timeout: Duration,
+}
+
+impl ClockDriver {
+    /// 等待定时器节点响应和节点输出的默认超时时间
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+    /// 没有收到响应时重发推进请求的间隔
+    const RETRY_INTERVAL: Duration = Duration::from_millis(200);
+
+    /// 连接到通过launch启动的dataflow，dataflow需要设置 `deploy.clock: simulated`
+    pub fn new(descriptor: &Descriptor) -> Result<Self> {
+        let endpoints = descriptor
+            .deploy
+            .endpoints
+            .clone()
+            .ok_or_else(|| anyhow!("dataflow has no endpoints defined"))?;
+        let backend = Backend::Zenoh {
+            endpoints,
+            mode: "peer".to_string(),
+        };
+        Self::connect(descriptor, &backend)
+    }
+
+    /// 通过指定的通信后端连接到dataflow，如进程内测试使用的内存总线
+    pub fn connect(descriptor: &Descriptor, backend: &Backend) -> Result<Self> {
+        if descriptor.deploy.clock != Some(ClockMode::Simulated) {
+            bail!("dataflow does not use a simulated clock, set `deploy.clock: simulated`");
+        }
+        let mut communication = backend.connect("dataflow/clock")?;
+        let advance = communication
+            .publisher("advance")
+            .map_err(|e| anyhow!("failed to publish topic:{ADVANCE_TOPIC}: {e}"))?;
+        let clock = subscribe_clock(communication.as_mut())?;
+        Ok(Self {
+            communication,
+            advance,
+            clock,
+            descriptor: descriptor.clone(),
+            outputs: BTreeMap::new(),
+            timeout: Self::DEFAULT_TIMEOUT,
+        })
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 订阅一个输出，写法与输入映射相同，如 `node/output` 或 `node/operator/output`
+    /// 也可以是 `dataflow/timer/...` 这样的定时器
+    /// 需要在推进时钟之前订阅，之后产生的消息才能被收到
+    pub fn watch(&mut self, output: &str) -> Result<()> {
+        let topic = match output.starts_with("dataflow/") {
+            true => output
+                .parse::<InputMapping>()
+                .map_err(|e| anyhow!("invalid output `{output}`: {e}"))?
+                .topic(),
+            false => self.descriptor.output_topic(output)?,
+        };
+        let mut subscriber = self
+            .communication
+            .subscribe(&topic)
+            .map_err(|e| anyhow!("failed to subscribe topic:{topic}: {e}"))?;
+        let (tx, rx) = flume::unbounded();
+        std::thread::spawn(move || {
+            while let Ok(Some(data)) = subscriber.recv() {
+                if tx.send(data).is_err() {
+                    break;
+                }
+            }
+        });
+        self.outputs.insert(output.to_owned(), rx);
+        Ok(())
+    }
+
+    /// 推进模拟时钟，定时器节点按时间顺序发布其间所有的tick后返回推进之后的模拟时间
+    /// 请求在定时器节点响应之前会被重发，定时器节点只处理一次
+    pub fn advance(&mut self, by: Duration) -> Result<SystemTime> {
+        let request = AdvanceRequest {
+            id: Uuid::new_v4().to_string(),
+            by: HumanDuration(by),
+        };
+        let data = serde_json::to_vec(&request)?;
+        let deadline = std::time::Instant::now() + self.timeout;
+        loop {
+            self.advance
+                .publish(&data)
+                .map_err(|e| anyhow!("failed to publish clock advance request: {e}"))?;
+            let retry = std::time::Instant::now() + Self::RETRY_INTERVAL;
+            while let Ok(update) = self.clock.recv_deadline(retry.min(deadline)) {
+                if update.ack.as_ref() == Some(&request.id) {
+                    let now = update.time()?;
+                    debug!(
+                        "simulated clock advanced by {} to {}",
+                        request.by, update.now
+                    );
+                    return Ok(now);
+                }
+            }
+            if std::time::Instant::now() >= deadline {
+                bail!(
+                    "timer node did not acknowledge the clock advance within {}, is the dataflow running?",
+                    HumanDuration(self.timeout)
+                );
+            }
+        }
+    }
+
+    /// 等待输出的下一条消息，超时返回错误
+    pub fn expect(&mut self, output: &str) -> Result<Vec<u8>> {
+        self.receiver(output)?
+            .recv_timeout(self.timeout)
+            .map_err(|_| {
+                anyhow!(
+                    "no message from `{output}` within {}",
+                    HumanDuration(self.timeout)
+                )
+            })
+    }
+
+    /// 取出输出已经收到的所有消息
+    pub fn drain(&mut self, output: &str) -> Result<Vec<Vec<u8>>> {
+        Ok(self.receiver(output)?.drain().collect())
+    }
+
+    fn receiver(&self, output: &str) -> Result<&flume::Receiver<Vec<u8>>> {
+        self.outputs
+            .get(output)
+            .ok_or_else(|| anyhow!("output `{output}` is not watched, call `watch` first"))
+    }
+}
+
+/// 推进描述文件中dataflow的模拟时钟，输出推进之后的模拟时间
+/// overrides 需要与launch时一致，profile可能修改了 endpoints
+pub fn advance_clock(dataflow: PathBuf, by: Duration, overrides: &Overrides) -> Result<()> {
+    let descriptor = Descriptor::blocking_read_with_overrides(&dataflow, overrides)
+        .with_context(|| format!("failed to read dataflow at `{}`", dataflow.display()))?;
+    let now = ClockDriver::new(&descriptor)?.advance(by)?;
+    println!("{}", humantime::format_rfc3339_micros(now));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{communication::memory::MemoryBus, harness::fixture, runtime::timer};
+
+    #[derive(Deserialize)]
+    struct Tick {
+        seq: u64,
+    }
+
+    // ClockDriver 是阻塞的，需要另一个线程运行定时器
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_clock_driver() {
+        let _simulation = exclusive().await;
+        let doubler = Descriptor::blocking_read(&fixture("doubler.yml")).unwrap();
+        assert!(ClockDriver::new(&doubler).is_err());
+
+        let descriptor = Descriptor::blocking_read(&fixture("clock.yml")).unwrap();
+        let bus = MemoryBus::new();
+        let backend = Backend::Memory(bus.clone());
+        let nodes = descriptor.resolve_node_defaults();
+        let deploy = descriptor.deploy.clone();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let timers = tokio::spawn({
+            let backend = backend.clone();
+            async move {
+                timer::start(&nodes, &deploy, &backend, async {
+                    let _ = stopped.await;
+                })
+                .await
+            }
+        });
+
+        let mut driver = ClockDriver::connect(&descriptor, &backend).unwrap();
+        driver.watch("dataflow/timer/millis/100").unwrap();
+        assert!(driver.watch("fast/unknown").is_err());
+        let start = driver.advance(Duration::from_millis(250)).unwrap();
+        let seqs = |ticks: Vec<Vec<u8>>| -> Vec<u64> {
+            ticks
+                .iter()
+                .map(|tick| serde_json::from_slice::<Tick>(tick).unwrap().seq)
+                .collect()
+        };
+        // 0ms、100ms 和 200ms 的tick
+        assert_eq!(
+            seqs(driver.drain("dataflow/timer/millis/100").unwrap()),
+            vec![0, 1, 2]
+        );
+        // 每次推进只发布其间的tick
+        let now = driver.advance(Duration::from_millis(100)).unwrap();
+        assert_eq!(
+            now.duration_since(start).unwrap(),
+            Duration::from_millis(100)
+        );
+        let tick = driver.expect("dataflow/timer/millis/100").unwrap();
+        assert_eq!(seqs(vec![tick]), vec![3]);
+        assert!(driver
+            .drain("dataflow/timer/millis/100")
+            .unwrap()
+            .is_empty());
+        assert!(driver.expect("slow/out").is_err());
+
+        let _ = stop.send(());
+        timers.await.unwrap().unwrap();
+        bus.close();
+        reset();
+    }
+}
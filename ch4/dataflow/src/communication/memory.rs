@@ -0,0 +1,125 @@
+use super::{BoxError, Envelope, Metadata, PubSubCommunicationLayer, Publisher, Subscriber};
+use crate::clock;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+/// topic 到订阅者的映射
+type Topics = BTreeMap<String, Vec<flume::Sender<Envelope>>>;
+
+/// 进程内的消息总线，同一个总线上的通信层互相可见
+/// 与 Zenoh 一样，消息只发送给发布时已经存在的订阅者
+#[derive(Clone)]
+pub struct MemoryBus {
+    /// 关闭之后为空
+    topics: Arc<Mutex<Option<Topics>>>,
+}
+
+impl MemoryBus {
+    pub fn new() -> Self {
+        Self {
+            topics: Arc::new(Mutex::new(Some(BTreeMap::new()))),
+        }
+    }
+
+    /// 创建一个通信层，发布的topic加上 namespace 前缀
+    pub fn layer(&self, namespace: &str) -> MemoryCommunicationLayer {
+        MemoryCommunicationLayer {
+            bus: self.clone(),
+            namespace: namespace.to_owned(),
+        }
+    }
+
+    /// 订阅完整的topic，总线关闭后接收端断开
+    pub fn subscribe(&self, topic: &str) -> flume::Receiver<Envelope> {
+        let (tx, rx) = flume::unbounded();
+        if let Some(topics) = self.topics.lock().unwrap().as_mut() {
+            topics.entry(topic.to_owned()).or_default().push(tx);
+        }
+        rx
+    }
+
+    /// 发布到完整的topic，同时清理已经断开的订阅者
+    pub fn publish(&self, topic: &str, data: &[u8], metadata: &Metadata) {
+        let envelope = Envelope {
+            data: data.to_vec(),
+            timestamp: Some(clock::now()),
+            metadata: metadata.clone(),
+        };
+        if let Some(subscribers) = self
+            .topics
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|topics| topics.get_mut(topic))
+        {
+            subscribers.retain(|tx| tx.send(envelope.clone()).is_ok());
+        }
+    }
+
+    /// 关闭总线，所有的接收端都会断开，之后的消息被丢弃
+    pub fn close(&self) {
+        self.topics.lock().unwrap().take();
+    }
+}
+
+impl Default for MemoryBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 基于 MemoryBus 实现的 PubSubCommunicationLayer
+pub struct MemoryCommunicationLayer {
+    bus: MemoryBus,
+    /// 用于topic prefix
+    namespace: String,
+}
+
+#[derive(Clone)]
+pub struct MemoryPublisher {
+    bus: MemoryBus,
+    topic: String,
+}
+
+impl Publisher for MemoryPublisher {
+    fn publish(&self, data: &[u8]) -> Result<(), BoxError> {
+        self.bus.publish(&self.topic, data, &Metadata::new());
+        Ok(())
+    }
+
+    fn publish_with_metadata(&self, data: &[u8], metadata: &Metadata) -> Result<(), BoxError> {
+        self.bus.publish(&self.topic, data, metadata);
+        Ok(())
+    }
+
+    fn dyn_clone(&self) -> Box<dyn Publisher> {
+        Box::new(self.clone())
+    }
+}
+
+pub struct MemorySubscriber(flume::Receiver<Envelope>);
+
+impl Subscriber for MemorySubscriber {
+    fn recv_envelope(&mut self) -> Result<Option<Envelope>, BoxError> {
+        Ok(self.0.recv().ok())
+    }
+
+    fn pending(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl PubSubCommunicationLayer for MemoryCommunicationLayer {
+    fn publisher(&mut self, topic: &str) -> Result<Box<dyn Publisher>, BoxError> {
+        Ok(Box::new(MemoryPublisher {
+            bus: self.bus.clone(),
+            topic: format!("{}/{topic}", self.namespace),
+        }))
+    }
+
+    fn subscribe(&mut self, topic: &str) -> Result<Box<dyn Subscriber>, BoxError> {
+        Ok(Box::new(MemorySubscriber(self.bus.subscribe(topic))))
+    }
+}
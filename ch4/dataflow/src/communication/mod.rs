@@ -1,16 +1,83 @@
+pub mod memory;
 pub mod pub_sub;
-type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+use std::{collections::BTreeMap, time::SystemTime};
+
+use crate::descriptor::descriptor::Deploy;
+use anyhow::{anyhow, Result};
+use memory::MemoryBus;
+use pub_sub::ZenohCommunicationLayer;
+
+pub(crate) type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
 
 pub trait PubSubCommunicationLayer: Send + Sync {
+    /// 发布到当前节点的topic，会加上节点的前缀
     fn publisher(&mut self, topic: &str) -> Result<Box<dyn Publisher>, BoxError>;
+    /// 订阅其他节点的topic，topic是完整的，不加前缀
     fn subscribe(&mut self, topic: &str) -> Result<Box<dyn Subscriber>, BoxError>;
 }
 
+/// 随消息传递的元数据，如追踪上下文
+pub type Metadata = BTreeMap<String, String>;
+
 pub trait Publisher: Send + Sync {
     fn dyn_clone(&self) -> Box<dyn Publisher>;
     fn publish(&self, data: &[u8]) -> Result<(), BoxError>;
+    /// 发布带元数据的消息，通信层不支持元数据时只发布数据
+    fn publish_with_metadata(&self, data: &[u8], _metadata: &Metadata) -> Result<(), BoxError> {
+        self.publish(data)
+    }
+}
+
+/// 收到的一条消息
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    pub data: Vec<u8>,
+    /// 消息发布的时间，通信层不支持时为空
+    pub timestamp: Option<SystemTime>,
+    pub metadata: Metadata,
 }
 
 pub trait Subscriber: Send + Sync {
-    fn recv(&mut self) -> Result<Option<Vec<u8>>, BoxError>;
+    fn recv(&mut self) -> Result<Option<Vec<u8>>, BoxError> {
+        Ok(self.recv_envelope()?.map(|envelope| envelope.data))
+    }
+    fn recv_envelope(&mut self) -> Result<Option<Envelope>, BoxError>;
+    /// 已经到达但还没有被接收的消息数量
+    fn pending(&self) -> usize {
+        0
+    }
+}
+
+/// 节点使用的通信后端
+#[derive(Clone)]
+pub enum Backend {
+    /// 通过 Zenoh 在进程之间通信
+    Zenoh { endpoints: Vec<String>, mode: String },
+    /// 在进程内通信，用于测试
+    Memory(MemoryBus),
+}
+
+impl Backend {
+    /// 根据dataflow的部署信息使用 Zenoh 通信
+    pub fn from_deploy(deploy: &Deploy) -> Result<Self> {
+        Ok(Self::Zenoh {
+            endpoints: deploy
+                .endpoints
+                .clone()
+                .ok_or_else(|| anyhow!("dataflow has no endpoints defined"))?,
+            mode: deploy.mode.clone().unwrap_or_else(|| "peer".to_string()),
+        })
+    }
+
+    /// 创建一个通信层，namespace 是发布的topic的前缀
+    pub fn connect(&self, namespace: &str) -> Result<Box<dyn PubSubCommunicationLayer>> {
+        Ok(match self {
+            Self::Zenoh { endpoints, mode } => Box::new(ZenohCommunicationLayer::init(
+                endpoints.clone(),
+                mode.clone(),
+                namespace.to_owned(),
+            )?),
+            Self::Memory(bus) => Box::new(bus.layer(namespace)),
+        })
+    }
 }
@@ -1,6 +1,7 @@
-use super::{BoxError, PubSubCommunicationLayer, Publisher, Subscriber};
+use super::{BoxError, Envelope, Metadata, PubSubCommunicationLayer, Publisher, Subscriber};
+use crate::clock;
 use anyhow::{anyhow, Result};
-use config::{whatami::WhatAmI, ConnectConfig, EndPoint};
+use config::{whatami::WhatAmI, ConnectConfig, EndPoint, ModeDependentValue};
 use flume::Receiver;
 use std::{str::FromStr, sync::Arc};
 use zenoh::prelude::{sync::SyncResolve, *};
@@ -22,6 +23,23 @@ impl Publisher for ZenohPublisher {
         self.publisher.put(data).res_sync().map_err(BoxError::from)
     }
 
+    fn publish_with_metadata(&self, data: &[u8], metadata: &Metadata) -> Result<(), BoxError> {
+        if metadata.is_empty() {
+            return self.publish(data);
+        }
+        // 元数据放在编码的后缀中，数据本身保持不变
+        let value = Value::from(data).encoding(
+            Encoding::APP_OCTET_STREAM.with_suffix(
+                metadata
+                    .iter()
+                    .map(|(k, v)| format!("{k}={v}"))
+                    .collect::<Vec<_>>()
+                    .join(";"),
+            ),
+        );
+        self.publisher.put(value).res_sync().map_err(BoxError::from)
+    }
+
     fn dyn_clone(&self) -> Box<dyn Publisher> {
         Box::new(self.clone())
     }
@@ -30,12 +48,31 @@ impl Publisher for ZenohPublisher {
 pub struct ZenohReceiver(zenoh::subscriber::Subscriber<'static, Receiver<Sample>>);
 
 impl Subscriber for ZenohReceiver {
-    fn recv(&mut self) -> Result<Option<Vec<u8>>, BoxError> {
+    fn recv_envelope(&mut self) -> Result<Option<Envelope>, BoxError> {
         match self.0.recv() {
-            Ok(sample) => Ok(Some(sample.value.payload.contiguous().into_owned())),
+            Ok(sample) => Ok(Some(Envelope {
+                data: sample.value.payload.contiguous().into_owned(),
+                // zenoh的时间戳是系统时间，开启模拟时钟时不可比较
+                timestamp: sample
+                    .timestamp
+                    .filter(|_| !clock::is_simulated())
+                    .map(|timestamp| timestamp.get_time().to_system_time()),
+                metadata: sample
+                    .value
+                    .encoding
+                    .suffix()
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                    .collect(),
+            })),
             Err(flume::RecvError::Disconnected) => Ok(None),
         }
     }
+
+    fn pending(&self) -> usize {
+        self.0.len()
+    }
 }
 
 impl ZenohCommunicationLayer {
@@ -49,6 +86,10 @@ impl ZenohCommunicationLayer {
                 .filter_map(|e| EndPoint::from_str(e).ok())
                 .collect(),
         };
+        // 发布的消息带上时间戳，接收者据此计算端到端的延迟
+        let _ = config
+            .timestamping
+            .set_enabled(Some(ModeDependentValue::Unique(true)));
         let session = zenoh::open(config)
             .res_sync()
             .map_err(|e| anyhow!(e))?
@@ -80,7 +121,7 @@ impl PubSubCommunicationLayer for ZenohCommunicationLayer {
     fn subscribe(&mut self, topic: &str) -> Result<Box<dyn Subscriber>, BoxError> {
         let subscriber = self
             .session
-            .declare_subscriber(self.prefixed(topic))
+            .declare_subscriber(topic.to_owned())
             .reliable()
             .res_sync()
             .map_err(BoxError::from)?;
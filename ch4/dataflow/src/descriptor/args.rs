@@ -0,0 +1,109 @@
+use anyhow::{bail, Result};
+
+/// 按照shell的规则切分参数
+/// 支持单引号、双引号以及引号外的反斜杠转义，引号可以用来传递空参数
+pub(crate) fn split_args(args: &str) -> Result<Vec<String>> {
+    let mut argv = vec![];
+    // 当前参数，使用Option区分空参数和没有参数
+    let mut current: Option<String> = None;
+    let mut chars = args.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => bail!("unterminated single quote in args `{args}`"),
+                    }
+                }
+            }
+            '"' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        // 双引号中只转义双引号和反斜杠
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => arg.push(c),
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => bail!("unterminated double quote in args `{args}`"),
+                        },
+                        Some(c) => arg.push(c),
+                        None => bail!("unterminated double quote in args `{args}`"),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => current.get_or_insert_with(String::new).push(c),
+                None => bail!("trailing backslash in args `{args}`"),
+            },
+            c if c.is_whitespace() => {
+                if let Some(arg) = current.take() {
+                    argv.push(arg);
+                }
+            }
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(arg) = current {
+        argv.push(arg);
+    }
+    Ok(argv)
+}
+
+/// 展开参数中的 `${NAME}`，`$${` 表示字面的 `${`
+/// 找不到变量时返回错误
+pub(crate) fn expand_vars(arg: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String> {
+    let mut expanded = String::with_capacity(arg.len());
+    let mut rest = arg;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        if let Some(tail) = tail.strip_prefix("$${") {
+            expanded.push_str("${");
+            rest = tail;
+        } else if let Some(tail) = tail.strip_prefix("${") {
+            let Some(end) = tail.find('}') else {
+                bail!("unterminated `${{` in `{arg}`");
+            };
+            let name = &tail[..end];
+            match lookup(name) {
+                Some(value) => expanded.push_str(&value),
+                None => bail!("undefined variable `{name}` in `{arg}`"),
+            }
+            rest = &tail[end + 1..];
+        } else {
+            expanded.push('$');
+            rest = &tail[1..];
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_expand() {
+        assert_eq!(
+            split_args(r#"--config '{"a": 1}' --path "my dir/x" a\ b """#).unwrap(),
+            vec!["--config", r#"{"a": 1}"#, "--path", "my dir/x", "a b", ""]
+        );
+        assert!(split_args("'open").is_err());
+
+        let lookup = |name: &str| (name == "HOME").then(|| "/home/df".to_owned());
+        assert_eq!(
+            expand_vars("${HOME}/data $${HOME} $5", &lookup).unwrap(),
+            "/home/df/data ${HOME} $5"
+        );
+        assert!(expand_vars("${MISSING}", &lookup).is_err());
+        assert!(expand_vars("${HOME", &lookup).is_err());
+    }
+}
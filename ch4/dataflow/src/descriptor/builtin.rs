@@ -0,0 +1,353 @@
+use std::{collections::BTreeMap, fmt, path::Path, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_yaml::Value;
+
+use super::descriptor::{HumanDuration, NodeId, NodeRunConfig, NormalNode, OperatorSource};
+
+/// 内置的operator，在节点进程中运行，不启动子进程
+/// 参数在operator的 `params` 中设置
+/// throttle、sample、debounce 和 batch 的时间窗口在 `deploy.clock: simulated` 时使用模拟时间
+/// ```yaml
+/// - id: limiter
+///   builtin: throttle
+///   params:
+///     interval: 100ms
+///   inputs:
+///     in: camera/image
+///   outputs:
+///     - out
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "builtin", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BuiltinOperator {
+    /// 每个 interval 内只转发第一条消息
+    Throttle { interval: HumanDuration },
+    /// 每个 interval 转发一次期间收到的最后一条消息
+    Sample { interval: HumanDuration },
+    /// 输入停止 interval 之后转发最后一条消息
+    Debounce { interval: HumanDuration },
+    /// 攒够 count 条或者第一条到达 interval 之后，以json数组输出
+    Batch {
+        #[serde(default)]
+        count: Option<usize>,
+        #[serde(default)]
+        interval: Option<HumanDuration>,
+    },
+    /// 所有输入按到达顺序转发到输出
+    /// 没有参数的operator也使用结构体变体，这样多余的参数才会被拒绝
+    Merge {},
+    /// 所有输入都有数据之后，任一输入更新时输出各输入最新值组成的json对象
+    ZipLatest {},
+    /// 所有输入都收到 key 字段相同的json消息之后，输出这些消息组成的json对象
+    Join { key: String },
+    /// 原样转发
+    Passthrough {},
+    /// 原样转发到所有的输出
+    Tee {},
+    /// 从json消息中取出 field 转发，field 为顶层的key或者以 `/` 开头的 json pointer，如 `/pose/x`
+    /// 设置 fields 时输出只包含这些字段的json对象，key 为字段名或者 pointer 的最后一段
+    /// 没有该字段的消息被丢弃
+    Map {
+        #[serde(default)]
+        field: Option<String>,
+        #[serde(default)]
+        fields: Option<Vec<String>>,
+    },
+    /// 只转发 field 满足条件的json消息，field 的写法与 map 相同
+    /// equals 要求字段等于给定的值，min 和 max 要求字段是不超出范围的数字
+    /// 没有设置条件时，要求字段存在且不是 null 或 false
+    Filter {
+        field: String,
+        #[serde(default)]
+        equals: Option<Value>,
+        #[serde(default)]
+        min: Option<f64>,
+        #[serde(default)]
+        max: Option<f64>,
+    },
+    /// 每个 interval 从文件中读取一行，设置 chunk_size 时读取固定大小的块
+    /// 读完之后结束，repeat 为 true 时从头重放
+    FileSource {
+        path: String,
+        interval: HumanDuration,
+        #[serde(default)]
+        chunk_size: Option<usize>,
+        #[serde(default)]
+        repeat: bool,
+    },
+    /// 从节点进程的标准输入逐行读取
+    StdinSource {},
+    /// 轮询目录，文件新建、修改和删除时输出json格式的事件
+    DirWatch {
+        path: String,
+        #[serde(default)]
+        interval: Option<HumanDuration>,
+        /// 只关心文件名匹配这个glob的文件
+        #[serde(default)]
+        pattern: Option<String>,
+    },
+    /// 每条消息追加一行到文件中
+    /// 设置 max_bytes 时文件超过大小之后轮转为 `path.1`、`path.2` ...，最多保留 keep 个
+    FileSink {
+        path: String,
+        #[serde(default)]
+        max_bytes: Option<u64>,
+        #[serde(default)]
+        keep: Option<usize>,
+    },
+    /// 格式化之后打印到标准输出，json会被展开
+    StdoutSink {},
+}
+
+/// 目录轮询的默认间隔
+pub const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+/// 轮转时默认保留的文件数量
+pub const DEFAULT_ROTATE_KEEP: usize = 3;
+
+impl BuiltinOperator {
+    /// 根据名字和参数解析内置operator
+    pub fn parse(name: &str, params: Option<&BTreeMap<String, Value>>) -> Result<Self> {
+        let mut mapping = serde_yaml::Mapping::new();
+        mapping.insert("builtin".into(), name.into());
+        for (key, value) in params.into_iter().flatten() {
+            mapping.insert(key.as_str().into(), value.clone());
+        }
+        serde_yaml::from_value(Value::Mapping(mapping))
+            .with_context(|| format!("invalid builtin operator `{name}`"))
+    }
+
+    /// 名字，与描述文件中的写法一致
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Throttle { .. } => "throttle",
+            Self::Sample { .. } => "sample",
+            Self::Debounce { .. } => "debounce",
+            Self::Batch { .. } => "batch",
+            Self::Merge {} => "merge",
+            Self::ZipLatest {} => "zip_latest",
+            Self::Join { .. } => "join",
+            Self::Passthrough {} => "passthrough",
+            Self::Tee {} => "tee",
+            Self::Map { .. } => "map",
+            Self::Filter { .. } => "filter",
+            Self::FileSource { .. } => "file_source",
+            Self::StdinSource {} => "stdin_source",
+            Self::DirWatch { .. } => "dir_watch",
+            Self::FileSink { .. } => "file_sink",
+            Self::StdoutSink {} => "stdout_sink",
+        }
+    }
+
+    /// 检查参数以及输入输出的数量
+    pub fn validate(&self, run_config: &NodeRunConfig) -> Result<()> {
+        match self {
+            Self::Throttle { interval }
+            | Self::Sample { interval }
+            | Self::Debounce { interval }
+            | Self::FileSource { interval, .. }
+            | Self::DirWatch {
+                interval: Some(interval),
+                ..
+            } if interval.0.is_zero() => {
+                bail!("`interval` must be greater than 0")
+            }
+            Self::FileSource {
+                chunk_size: Some(0),
+                ..
+            } => bail!("`chunk_size` must be greater than 0"),
+            Self::DirWatch {
+                pattern: Some(pattern),
+                ..
+            } => {
+                glob::Pattern::new(pattern)
+                    .with_context(|| format!("invalid `pattern` `{pattern}`"))?;
+            }
+            Self::FileSink {
+                max_bytes, keep, ..
+            } => {
+                if *max_bytes == Some(0) || *keep == Some(0) {
+                    bail!("`max_bytes` and `keep` must be greater than 0");
+                }
+                if keep.is_some() && max_bytes.is_none() {
+                    bail!("`keep` needs `max_bytes`");
+                }
+            }
+            Self::Map { field, fields } => {
+                match (field, fields) {
+                    (Some(_), Some(_)) => bail!("map needs either `field` or `fields`, not both"),
+                    (None, None) => bail!("map needs `field` or `fields`"),
+                    (None, Some(fields)) if fields.is_empty() => {
+                        bail!("`fields` must not be empty")
+                    }
+                    _ => {}
+                }
+                if field
+                    .iter()
+                    .chain(fields.iter().flatten())
+                    .any(String::is_empty)
+                {
+                    bail!("field names must not be empty");
+                }
+            }
+            Self::Filter { min, max, .. } => {
+                if let (Some(min), Some(max)) = (min, max) {
+                    if min > max {
+                        bail!("`min` ({min}) must not be greater than `max` ({max})");
+                    }
+                }
+            }
+            Self::Batch { count, interval } => {
+                if count.is_none() && interval.is_none() {
+                    bail!("batch needs `count`, `interval` or both");
+                }
+                if *count == Some(0) || interval.is_some_and(|interval| interval.0.is_zero()) {
+                    bail!("`count` and `interval` must be greater than 0");
+                }
+            }
+            _ => {}
+        }
+        // 输入和输出的最少数量，以及是否只能是这个数量
+        let (inputs, outputs) = match self {
+            Self::Merge {} => ((1, false), (1, true)),
+            Self::ZipLatest {} | Self::Join { .. } => ((2, false), (1, true)),
+            Self::Tee {} => ((1, true), (1, false)),
+            Self::FileSource { .. } | Self::StdinSource {} | Self::DirWatch { .. } => {
+                ((0, true), (1, true))
+            }
+            Self::FileSink { .. } | Self::StdoutSink {} => ((1, false), (0, true)),
+            _ => ((1, true), (1, true)),
+        };
+        check_count("inputs", run_config.inputs.len(), inputs)?;
+        check_count("outputs", run_config.outputs.len(), outputs)?;
+        Ok(())
+    }
+
+    /// 检查读写的文件和目录，路径相对于工作目录
+    pub fn validate_paths(&self, working_dir: &Path) -> Result<()> {
+        match self {
+            Self::FileSource { path, .. } if !working_dir.join(path).is_file() => {
+                bail!("no file at `{path}`")
+            }
+            Self::DirWatch { path, .. } if !working_dir.join(path).is_dir() => {
+                bail!("no directory at `{path}`")
+            }
+            Self::FileSink { path, .. } => {
+                let path = working_dir.join(path);
+                if path.is_dir() {
+                    bail!("`{}` is a directory", path.display());
+                }
+                if let Some(parent) = path.parent().filter(|parent| !parent.is_dir()) {
+                    bail!("no directory at `{}`", parent.display());
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for BuiltinOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// 使用 stdin_source 的节点，launch 只能把自己的标准输入交给其中一个节点
+pub(crate) fn stdin_nodes(nodes: &[NormalNode]) -> Vec<&NodeId> {
+    nodes
+        .iter()
+        .filter(|node| {
+            node.kind.operators.iter().any(|operator| {
+                matches!(&operator.config.source, OperatorSource::Builtin(name) if name == "stdin_source")
+            })
+        })
+        .map(|node| &node.id)
+        .collect()
+}
+
+fn check_count(what: &str, count: usize, (min, exact): (usize, bool)) -> Result<()> {
+    if exact && count != min {
+        bail!("needs exactly {min} {what}, got {count}");
+    }
+    if count < min {
+        bail!("needs at least {min} {what}, got {count}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_config(inputs: usize, outputs: usize) -> NodeRunConfig {
+        let yaml = format!(
+            "inputs: {{{}}}\noutputs: [{}]",
+            (0..inputs)
+                .map(|i| format!("in{i}: source/out{i}"))
+                .collect::<Vec<_>>()
+                .join(", "),
+            (0..outputs)
+                .map(|i| format!("out{i}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_validate() {
+        let params: BTreeMap<String, Value> =
+            serde_yaml::from_str("count: 10\ninterval: 1s").unwrap();
+        let batch = BuiltinOperator::parse("batch", Some(&params)).unwrap();
+        assert_eq!(
+            batch,
+            BuiltinOperator::Batch {
+                count: Some(10),
+                interval: Some("1s".parse().unwrap())
+            }
+        );
+        assert!(batch.validate(&run_config(1, 1)).is_ok());
+        assert!(batch.validate(&run_config(2, 1)).is_err());
+
+        assert_eq!(
+            BuiltinOperator::parse("merge", None).unwrap(),
+            BuiltinOperator::Merge {}
+        );
+        assert!(BuiltinOperator::Merge {}
+            .validate(&run_config(3, 1))
+            .is_ok());
+        assert!(BuiltinOperator::Tee {}.validate(&run_config(1, 3)).is_ok());
+        assert!(BuiltinOperator::ZipLatest {}
+            .validate(&run_config(1, 1))
+            .is_err());
+
+        // 未知的operator、缺少或者多余的参数
+        assert!(BuiltinOperator::parse("flat_map", None).is_err());
+        let params = serde_yaml::from_str("field: id\nfields: [id]").unwrap();
+        let map = BuiltinOperator::parse("map", Some(&params)).unwrap();
+        assert!(map.validate(&run_config(1, 1)).is_err());
+        let params = serde_yaml::from_str("field: /pose/x\nmin: 1\nmax: 0.5").unwrap();
+        let filter = BuiltinOperator::parse("filter", Some(&params)).unwrap();
+        assert!(filter.validate(&run_config(1, 1)).is_err());
+        assert!(BuiltinOperator::parse("throttle", None).is_err());
+        assert!(BuiltinOperator::parse("merge", Some(&params)).is_err());
+        let params = serde_yaml::from_str("interval: 1s\nburst: 2").unwrap();
+        assert!(BuiltinOperator::parse("throttle", Some(&params)).is_err());
+        let empty = BuiltinOperator::parse("batch", None).unwrap();
+        assert!(empty.validate(&run_config(1, 1)).is_err());
+
+        // source没有输入，sink没有输出
+        let params = serde_yaml::from_str("path: in.txt\ninterval: 1s").unwrap();
+        let source = BuiltinOperator::parse("file_source", Some(&params)).unwrap();
+        assert!(source.validate(&run_config(0, 1)).is_ok());
+        assert!(source.validate(&run_config(1, 1)).is_err());
+        assert!(BuiltinOperator::StdoutSink {}
+            .validate(&run_config(2, 0))
+            .is_ok());
+        let params = serde_yaml::from_str("path: out.txt\nkeep: 2").unwrap();
+        let sink = BuiltinOperator::parse("file_sink", Some(&params)).unwrap();
+        assert!(sink.validate(&run_config(1, 0)).is_err());
+    }
+}
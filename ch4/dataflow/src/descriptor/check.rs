@@ -0,0 +1,111 @@
+use super::{
+    diagnostic::{render_diagnostics, Diagnostic, Location},
+    expand::{Expanded, Overrides, SourceError},
+    validate::check_dataflow,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::{collections::BTreeMap, path::PathBuf};
+
+/// 诊断信息的输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DiagnosticFormat {
+    /// 编译器风格的文本
+    Human,
+    /// json，供编辑器使用
+    Json,
+}
+
+/// json 格式输出的结构
+#[derive(Debug, Serialize)]
+struct CheckReport<'a> {
+    file: String,
+    diagnostics: &'a [Diagnostic],
+}
+
+/// 检查描述文件，一次性输出所有的诊断信息
+/// 存在错误时返回Err
+pub fn check(
+    dataflow: PathBuf,
+    format: DiagnosticFormat,
+    build: bool,
+    offline: bool,
+    overrides: &Overrides,
+) -> Result<()> {
+    let file = dataflow.display().to_string();
+    // include的文件中的诊断信息会标记所在的文件
+    let other_file = |path: &PathBuf| (path != &dataflow).then(|| path.display().to_string());
+    let mut sources = BTreeMap::new();
+    let expanded = Expanded::read(&dataflow, overrides).and_then(|expanded| {
+        let descriptor = expanded.descriptor()?;
+        Ok((expanded, descriptor))
+    });
+    let diagnostics = match expanded {
+        Ok((expanded, descriptor)) => {
+            // 获取描述文件定义的的工作目录
+            let working_dir = dataflow
+                .canonicalize()
+                .context("failed to canonicalize dataflow path")?
+                .parent()
+                .ok_or_else(|| anyhow!("dataflow path has no parent dir"))?
+                .to_owned();
+            for (path, source) in expanded.sources() {
+                sources.insert(path.display().to_string(), source.to_owned());
+            }
+            let mut diagnostics: Vec<_> = check_dataflow(&descriptor, &working_dir, build, offline)
+                .into_iter()
+                .map(|d| {
+                    let (path, span) = expanded.resolve(&descriptor, &d.location);
+                    d.with_span(span).with_file(other_file(&path))
+                })
+                .collect();
+            diagnostics.sort_by_key(|d| {
+                (
+                    d.file.clone(),
+                    d.span.map(|s| (s.line, s.column)),
+                    d.severity,
+                )
+            });
+            diagnostics
+        }
+        // 无法解析时只有一条解析错误
+        Err(e) => {
+            let Some(error) = e.downcast_ref::<SourceError>() else {
+                return Err(e.context(format!(
+                    "failed to read dataflow at `{}`",
+                    dataflow.display()
+                )));
+            };
+            let source = std::fs::read_to_string(&error.file).unwrap_or_default();
+            sources.insert(error.file.display().to_string(), source);
+            vec![
+                Diagnostic::error("E000", Location::Dataflow, error.message.clone())
+                    .with_span(error.span)
+                    .with_file(other_file(&error.file)),
+            ]
+        }
+    };
+
+    match format {
+        DiagnosticFormat::Human => {
+            println!("{}", render_diagnostics(&file, &sources, &diagnostics));
+        }
+        DiagnosticFormat::Json => {
+            let report = CheckReport {
+                file,
+                diagnostics: &diagnostics,
+            };
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report).context("failed to serialize diagnostics")?
+            );
+        }
+    }
+
+    let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+    if errors > 0 {
+        bail!("dataflow check failed with {errors} error(s)");
+    }
+    Ok(())
+}
@@ -0,0 +1,242 @@
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+/// 最多向后查找的年数，超过仍然没有触发时间的表达式视为永远不会触发
+const MAX_LOOKAHEAD_YEARS: i64 = 8;
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// 5个字段的cron表达式：分 时 日 月 星期，按UTC计算
+/// 每个字段支持 `*`、`N`、`a-b`、`*/step`、`a-b/step` 以及逗号分隔的列表
+/// 月份和星期可以使用英文缩写，星期的0和7都表示周日
+/// 日和星期都不是 `*` 时，满足其一即可触发
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CronSchedule {
+    expr: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronSchedule {
+    /// 原始表达式，字段之间只保留一个空格
+    pub fn expr(&self) -> &str {
+        &self.expr
+    }
+
+    /// 计算严格晚于 `after` 的下一次触发时间，精确到分钟
+    pub fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        let secs = after.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+        // 从下一个整分钟开始查找
+        let mut t = (secs / 60 + 1) * 60;
+        let (limit_year, _, _) = civil_from_days(t.div_euclid(86400));
+        let limit_year = limit_year + MAX_LOOKAHEAD_YEARS;
+        loop {
+            let days = t.div_euclid(86400);
+            let (year, month, day) = civil_from_days(days);
+            if year > limit_year {
+                return None;
+            }
+            if !contains(self.months, month) {
+                // 跳到下个月的第一天
+                let (year, month) = if month == 12 {
+                    (year + 1, 1)
+                } else {
+                    (year, month + 1)
+                };
+                t = days_from_civil(year, month, 1) * 86400;
+                continue;
+            }
+            if !self.day_matches(day, weekday(days)) {
+                t = (days + 1) * 86400;
+                continue;
+            }
+            let secs_of_day = t.rem_euclid(86400);
+            if !contains(self.hours, (secs_of_day / 3600) as u32) {
+                t = (t / 3600 + 1) * 3600;
+                continue;
+            }
+            if !contains(self.minutes, (secs_of_day % 3600 / 60) as u32) {
+                t += 60;
+                continue;
+            }
+            return Some(UNIX_EPOCH + Duration::from_secs(t as u64));
+        }
+    }
+
+    fn day_matches(&self, day: u32, weekday: u32) -> bool {
+        let day_ok = contains(self.days, day);
+        let weekday_ok = contains(self.weekdays, weekday);
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day_ok || weekday_ok,
+            _ => day_ok && weekday_ok,
+        }
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let fields: Vec<_> = s.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            bail!(
+                "cron expression must have 5 fields (minute hour day month weekday), got {}",
+                fields.len()
+            );
+        };
+        let mut weekdays = parse_field(weekday, 0, 7, &WEEKDAY_NAMES, 0).context("weekday")?;
+        // 7 也表示周日
+        if contains(weekdays, 7) {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        let schedule = CronSchedule {
+            expr: fields.join(" "),
+            minutes: parse_field(minute, 0, 59, &[], 0).context("minute")?,
+            hours: parse_field(hour, 0, 23, &[], 0).context("hour")?,
+            days: parse_field(day, 1, 31, &[], 0).context("day of month")?,
+            months: parse_field(month, 1, 12, &MONTH_NAMES, 1).context("month")?,
+            weekdays,
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        };
+        if schedule.next_after(UNIX_EPOCH).is_none() {
+            bail!("cron expression `{}` never fires", schedule.expr);
+        }
+        Ok(schedule)
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expr)
+    }
+}
+
+fn contains(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+/// 解析一个字段，返回取值的位图
+/// names 是从 `first_name` 开始的取值的英文缩写
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str], first_name: u32) -> Result<u64> {
+    let value = |s: &str| -> Result<u32> {
+        let lower = s.to_ascii_lowercase();
+        let value = match names.iter().position(|name| *name == lower) {
+            Some(i) => i as u32 + first_name,
+            None => s.parse().map_err(|_| anyhow!("invalid value `{s}`"))?,
+        };
+        if value < min || value > max {
+            bail!("value {value} out of range {min}-{max}");
+        }
+        Ok(value)
+    };
+    let mut set = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step.parse().map_err(|_| anyhow!("invalid step `{step}`"))?;
+                if step == 0 {
+                    bail!("step must be greater than 0");
+                }
+                (range, step)
+            }
+            None => (item, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (value(start)?, value(end)?),
+            // `N/step` 表示从N开始到最大值
+            None if step > 1 => (value(range)?, max),
+            None => {
+                let value = value(range)?;
+                (value, value)
+            }
+        };
+        if start > end {
+            bail!("invalid range `{range}`");
+        }
+        for v in (start..=end).step_by(step as usize) {
+            set |= 1 << v;
+        }
+    }
+    Ok(set)
+}
+
+/// 1970-01-01 之后的天数对应的星期，0 表示周日
+fn weekday(days: i64) -> u32 {
+    (days + 4).rem_euclid(7) as u32
+}
+
+/// 天数转换为公历日期
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// 公历日期转换为天数
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> SystemTime {
+        humantime::parse_rfc3339(s).unwrap()
+    }
+
+    #[test]
+    fn test_next_after() {
+        let every_5: CronSchedule = "*/5  * * * *".parse().unwrap();
+        assert_eq!(every_5.to_string(), "*/5 * * * *");
+        assert_eq!(
+            every_5.next_after(at("2024-02-28T23:58:30Z")),
+            Some(at("2024-02-29T00:00:00Z"))
+        );
+
+        // 每月1号或者周一的 9:30
+        let schedule: CronSchedule = "30 9 1 * mon".parse().unwrap();
+        assert_eq!(
+            schedule.next_after(at("2024-03-01T09:30:00Z")),
+            Some(at("2024-03-04T09:30:00Z"))
+        );
+
+        let leap: CronSchedule = "0 0 29 feb *".parse().unwrap();
+        assert_eq!(
+            leap.next_after(at("2024-03-01T00:00:00Z")),
+            Some(at("2028-02-29T00:00:00Z"))
+        );
+
+        assert!("0 0 30 2 *".parse::<CronSchedule>().is_err());
+        assert!("61 * * * *".parse::<CronSchedule>().is_err());
+        assert!("* * * *".parse::<CronSchedule>().is_err());
+    }
+}
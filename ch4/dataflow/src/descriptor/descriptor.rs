@@ -1,7 +1,13 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use once_cell::sync::OnceCell;
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, ObjectValidation, Schema, SchemaObject, SubschemaValidation},
+    JsonSchema,
+};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_with_expand_env::with_expand_envs;
+use sha2::{Digest, Sha256};
 use std::{
     borrow::Borrow,
     collections::{BTreeMap, BTreeSet},
@@ -12,7 +18,15 @@ use std::{
     time::Duration,
 };
 
-use super::validate::validate_dataflow;
+use super::{
+    args::{expand_vars, split_args},
+    cron::CronSchedule,
+    expand::{Expanded, Overrides},
+    format::Format,
+    graph::DataflowGraph,
+    topology::{analyze_topology, Topology},
+    validate::validate_dataflow,
+};
 
 /// 用于从String创建自定义类型的宏
 macro_rules! custom_type_of_String {
@@ -39,6 +53,20 @@ macro_rules! custom_type_of_String {
             }
         }
 
+        impl JsonSchema for $name {
+            fn is_referenceable() -> bool {
+                false
+            }
+
+            fn schema_name() -> String {
+                String::schema_name()
+            }
+
+            fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+                String::json_schema(gen)
+            }
+        }
+
         impl From<$name> for String {
             fn from(id: $name) -> Self {
                 id.0
@@ -100,9 +128,12 @@ macro_rules! custom_type_of_String {
 }
 
 /// 用于解析申明式描述文件的结构体
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Descriptor {
+    /// 描述文件的版本，如 `1.0`，写成数字时转为字符串
+    #[serde(deserialize_with = "version_string")]
+    #[schemars(schema_with = "version_schema")]
     pub version: String,
     /// 描述整个数据流的部署信息
     #[serde(default)]
@@ -129,6 +160,9 @@ impl Descriptor {
                 name: node.name,
                 description: node.description,
                 envs: node.envs,
+                resources: node.resources,
+                readiness: node.readiness,
+                liveness: node.liveness,
                 // 处理节点的deploy的默认值
                 deploy: resolve_deploy,
                 // 将node 从 单op节点转为多op节点
@@ -139,18 +173,83 @@ impl Descriptor {
     }
 
     /// 从文件中读取描述文件
-    /// 然后反序列化
+    /// 展开include的文件和模板的实例之后，再反序列化
     pub(crate) fn blocking_read(path: &Path) -> Result<Descriptor> {
-        let buf = std::fs::read(path).context("failed to open given file")?;
-        Descriptor::parse(buf)
+        Descriptor::blocking_read_with_overrides(path, &Overrides::default())
+    }
+
+    /// 从文件中读取描述文件，使用选择的profile和覆盖的变量
+    pub(crate) fn blocking_read_with_overrides(
+        path: &Path,
+        overrides: &Overrides,
+    ) -> Result<Descriptor> {
+        Expanded::read(path, overrides)?.descriptor()
     }
 
     /// 检查当前的yaml文件是否合法
-    pub(crate) fn validate(&self, working_dir: &Path, build: bool) -> Result<()> {
-        validate_dataflow(self, &working_dir, build).context("failed to validate yaml")?;
+    /// offline 为 true 时，url类型的source只检查本地的下载缓存
+    pub(crate) fn validate(&self, working_dir: &Path, build: bool, offline: bool) -> Result<()> {
+        validate_dataflow(self, &working_dir, build, offline).context("failed to validate yaml")?;
         Ok(())
     }
 
+    /// 对数据流图进行拓扑分析，包括反馈环检测和节点的拓扑顺序
+    pub fn topology(&self) -> Topology {
+        analyze_topology(&self.resolve_node_defaults())
+    }
+
+    /// 解析节点的输出，写法与输入映射相同，如 `node/output` 或 `node/operator/output`
+    /// 返回该输出发布的topic，输出需要在描述文件中声明
+    pub(crate) fn output_topic(&self, output: &str) -> Result<String> {
+        let (node_id, output_id) = output
+            .split_once('/')
+            .ok_or_else(|| anyhow!("output must be `<node>/<output>` (got `{output}`)"))?;
+        let node = self
+            .nodes
+            .iter()
+            .find(|node| node.id.as_str() == node_id)
+            .ok_or_else(|| anyhow!("unknown node `{node_id}` in output `{output}`"))?;
+        let (operator_id, run_config, output_id) = match &node.kind {
+            NodeKind::Operator(operator) => (
+                operator.id.as_ref().map_or(node_id, |id| id.as_str()),
+                &operator.config.run_config,
+                output_id,
+            ),
+            NodeKind::Operators(operators) => {
+                let (operator_id, output_id) = output_id.split_once('/').ok_or_else(|| {
+                    anyhow!("node `{node_id}` has multiple operators, output must be `<node>/<operator>/<output>` (got `{output}`)")
+                })?;
+                let operator = operators
+                    .operators
+                    .iter()
+                    .find(|operator| operator.id.as_str() == operator_id)
+                    .ok_or_else(|| anyhow!("unknown operator `{operator_id}` in output `{output}`"))?;
+                (operator_id, &operator.config.run_config, output_id)
+            }
+        };
+        if !run_config.outputs.contains(output_id) {
+            bail!("`{output}` is not an output declared in the dataflow");
+        }
+        Ok(format!("{node_id}/{operator_id}/{output_id}"))
+    }
+
+    /// 没有设置日志目录时，使用以描述文件名命名的默认目录，使不同dataflow的日志互不覆盖
+    /// 不同目录下的同名描述文件通过路径的哈希区分
+    pub(crate) fn set_default_log_dir(&mut self, dataflow: &Path) {
+        if self.deploy.log.is_none() {
+            let stem = dataflow
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "dataflow".to_owned());
+            let path = dataflow
+                .canonicalize()
+                .unwrap_or_else(|_| dataflow.to_owned());
+            let digest = Sha256::digest(path.as_os_str().as_encoded_bytes());
+            let name = format!("{stem}-{}", &hex::encode(digest)[..8]);
+            self.deploy.log = Some(default_log_dir().join(name));
+        }
+    }
+
     /// 将节点的部署信息设置为默认的部署信息
     /// 如果当前节点没有部署信息，就去获取description的部署信息
     fn resolve_node_deploy_defaults(&self, node: Node) -> Deploy {
@@ -160,19 +259,26 @@ impl Descriptor {
             Some(m) => m,
             None => default_endpoint.to_owned(),
         };
-        let default_log = self
-            .deploy
-            .log
-            .clone()
-            .unwrap_or(env::temp_dir().join("log.txt"));
+        // 每个节点默认使用dataflow日志目录下以节点id命名的子目录
         let log = match node.deploy.log {
             Some(m) => m,
-            None => default_log.to_owned(),
+            None => self
+                .deploy
+                .log
+                .clone()
+                .unwrap_or_else(default_log_dir)
+                .join(node.id.as_str()),
         };
         // 重新设置deploy的
         Deploy {
             endpoints: Some(endpoint),
             log: Some(log),
+            log_rotation: node
+                .deploy
+                .log_rotation
+                .or_else(|| self.deploy.log_rotation.clone()),
+            log_format: node.deploy.log_format.or(self.deploy.log_format),
+            validate_urls: node.deploy.validate_urls.or(self.deploy.validate_urls),
             ..node.deploy
         }
     }
@@ -228,7 +334,7 @@ impl Descriptor {
             for input_mapping in input_mappings
                 .into_iter()
                 .filter_map(|i| match &mut i.mapping {
-                    InputMapping::Timer { .. } => None,
+                    InputMapping::Timer(_) => None,
                     InputMapping::User(m) => Some(m),
                 })
             {
@@ -244,11 +350,12 @@ impl Descriptor {
         nodes
     }
 
-    /// 封装了一下反序列化函数，输入是一个字节数组，输出是一个Descriptor
-    /// 这里使用了serde_yaml::from_slice
-    /// 所以可以修改该函数修改配置文件类型
-    fn parse(buf: Vec<u8>) -> Result<Descriptor> {
-        serde_yaml::from_slice(&buf).context("failed to parse given descriptor")
+    /// 封装了一下反序列化函数，输入是描述文件的文本，输出是一个Descriptor
+    /// 文本的格式由 format 决定，见 [`Format::from_path`]
+    pub(crate) fn parse(text: &str, format: Format) -> Result<Descriptor> {
+        format
+            .parse(text)
+            .context("failed to parse given descriptor")
     }
 
     /// 可视化当前dataflow yaml 定义文件，作为mermaid图
@@ -258,6 +365,25 @@ impl Descriptor {
 
         Ok(flowchart)
     }
+
+    /// 导出完全解析之后的数据流图
+    pub fn export_graph(&self) -> DataflowGraph {
+        let resolved = self.resolve_node_defaults();
+        crate::descriptor::graph::export_nodes(&self.version, &resolved)
+    }
+
+    /// 将完全解析之后的数据流图导出为json
+    pub fn visualize_as_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(&self.export_graph()).context("failed to serialize graph")
+    }
+
+    /// 可视化当前dataflow yaml 定义文件，作为graphviz dot图
+    pub fn visualize_as_dot(&self) -> Result<String> {
+        let resolved = self.resolve_node_defaults();
+        let graph = crate::descriptor::dot::visualize_nodes(&resolved);
+
+        Ok(graph)
+    }
 }
 
 custom_type_of_String!(pub NodeId); //节点Id
@@ -267,7 +393,7 @@ custom_type_of_String!(pub OperatorId); //OpId
 
 /// 自定义枚举类型，分别对应不同类型的环境变量值
 /// 分别是布尔值，整数值，字符串值
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(untagged)]
 pub enum EnvValue {
     /// 指定了反序列化函数 with_expand_envs
@@ -291,8 +417,446 @@ impl fmt::Display for EnvValue {
     }
 }
 
+/// operator 的构建命令
+/// 字符串形式通过平台的shell执行，支持引号、管道、`&&` 等
+/// 结构化形式不经过shell，直接执行 cmd
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum BuildCommand {
+    Shell(String),
+    Structured(StructuredBuild),
+}
+
+/// 结构化的构建命令
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct StructuredBuild {
+    /// 程序及其参数
+    pub cmd: Vec<String>,
+    /// 执行构建的目录，相对于工作目录
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+    /// 构建时的环境变量，会覆盖节点和operator的环境变量
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub envs: Option<BTreeMap<String, EnvValue>>,
+}
+
+impl BuildCommand {
+    /// 构建命令是否为空
+    pub fn is_empty(&self) -> bool {
+        match self {
+            BuildCommand::Shell(script) => script.trim().is_empty(),
+            BuildCommand::Structured(build) => build.cmd.is_empty(),
+        }
+    }
+}
+
+/// 输出构建命令，结构化形式中包含空白的参数会加上引号
+impl fmt::Display for BuildCommand {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildCommand::Shell(script) => fmt.write_str(script.trim()),
+            BuildCommand::Structured(build) => {
+                let cmd = build
+                    .cmd
+                    .iter()
+                    .map(|arg| {
+                        if arg.is_empty() || arg.contains(char::is_whitespace) {
+                            format!("{arg:?}")
+                        } else {
+                            arg.clone()
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                fmt.write_str(&cmd)?;
+                if let Some(cwd) = &build.cwd {
+                    write!(fmt, " (in {})", cwd.display())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// operator 进程的资源限制
+/// linux 下优先使用 cgroup v2，需要节点所在的cgroup已经向子cgroup开放了内存和cpu控制器，不可用时退回到 rlimit
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Resources {
+    /// 内存上限，如 `512M`、`2G`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<ByteSize>,
+    /// cpu 配额，单位为核，如 `0.5`，只在 cgroup v2 可用时生效
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<f64>,
+    /// 最多可以打开的文件数量
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nofile: Option<u64>,
+    /// 进程的nice值，-20到19
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nice: Option<i32>,
+}
+
+impl Resources {
+    /// 合并资源限制，other 中设置了的项优先
+    pub fn merge(&self, other: &Resources) -> Resources {
+        Resources {
+            memory: other.memory.or(self.memory),
+            cpus: other.cpus.or(self.cpus),
+            nofile: other.nofile.or(self.nofile),
+            nice: other.nice.or(self.nice),
+        }
+    }
+
+    /// 是否没有设置任何限制
+    pub fn is_empty(&self) -> bool {
+        self == &Resources::default()
+    }
+    /// 检查限制的取值范围
+    pub fn validate(&self) -> Result<()> {
+        if self.memory == Some(ByteSize(0)) {
+            bail!("memory limit must be greater than 0");
+        }
+        if let Some(cpus) = self.cpus {
+            if !(cpus > 0.0 && cpus.is_finite()) {
+                bail!("cpus must be a positive number, got {cpus}");
+            }
+        }
+        if self.nofile == Some(0) {
+            bail!("nofile must be greater than 0");
+        }
+        if let Some(nice) = self.nice {
+            if !(-20..=19).contains(&nice) {
+                bail!("nice must be between -20 and 19, got {nice}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 日志文件的轮转策略，超过大小或者时间时将当前文件轮转为 `node.log.1`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LogRotation {
+    /// 单个日志文件的最大大小，默认为10MiB
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<ByteSize>,
+    /// 单个日志文件最长的写入时间，如 `1h`，默认不按时间轮转
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<HumanDuration>,
+    /// 保留的历史日志文件数量，默认为5
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep: Option<usize>,
+}
+
+impl LogRotation {
+    pub const DEFAULT_MAX_SIZE: ByteSize = ByteSize(10 << 20);
+    pub const DEFAULT_KEEP: usize = 5;
+
+    pub fn max_size(&self) -> ByteSize {
+        self.max_size.unwrap_or(Self::DEFAULT_MAX_SIZE)
+    }
+
+    pub fn keep(&self) -> usize {
+        self.keep.unwrap_or(Self::DEFAULT_KEEP)
+    }
+}
+
+/// 运行时指标的http端点，launch 在 `http://<listen>/metrics` 提供所有节点的指标
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// 监听的地址，如 `127.0.0.1:9090`
+    pub listen: String,
+}
+
+/// 消息的分布式追踪，launch 汇总所有节点的span后导出
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TracingConfig {
+    /// OTLP/HTTP 的地址，如 `http://127.0.0.1:4318`，span 以json发送到 `/v1/traces`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub otlp: Option<String>,
+    /// chrome trace event 格式的trace文件，可以在 Perfetto 中打开，相对于描述文件的目录
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+}
+
+/// 节点的就绪检查
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Readiness {
+    /// 判断节点就绪的信号
+    pub signal: ReadinessSignal,
+    /// 等待就绪的超时时间，默认为30s
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<HumanDuration>,
+    /// 需要报告就绪的operator，只用于 `sdk` 信号，默认为节点中所有的非内置operator
+    /// 这些operator都报告就绪之后节点才就绪
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operators: Option<Vec<OperatorId>>,
+}
+
+impl Readiness {
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+            .map_or(Self::DEFAULT_TIMEOUT, |timeout| timeout.0)
+    }
+}
+
+/// 判断节点就绪的信号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadinessSignal {
+    /// 节点中的operator通过sdk报告就绪，即在标准输出中输出 `@dataflow:ready`
+    /// 多个operator的节点等待所有声明就绪的operator
+    Sdk,
+    /// 节点的任意一个输出发布了第一条数据，只能用于没有输入的节点
+    FirstOutput,
+}
+
+impl fmt::Display for ReadinessSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadinessSignal::Sdk => write!(f, "sdk"),
+            ReadinessSignal::FirstOutput => write!(f, "first_output"),
+        }
+    }
+}
+
+/// 节点的存活检查
+/// 非内置的operator通过sdk发送心跳，即在标准输出中输出 `@dataflow:heartbeat`
+/// 所有运行中的operator都发送过心跳后，节点才向launch发送一次心跳
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Liveness {
+    /// operator发送心跳的间隔，默认为1s
+    /// 节点中只有内置operator时，由节点按该间隔发送心跳
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<HumanDuration>,
+    /// 超过该时间没有收到心跳时认为节点失去响应，默认为5s
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<HumanDuration>,
+}
+
+impl Liveness {
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+            .map_or(Self::DEFAULT_INTERVAL, |interval| interval.0)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+            .map_or(Self::DEFAULT_TIMEOUT, |timeout| timeout.0)
+    }
+}
+
+/// 节点输出的日志格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// 普通文本，按行识别日志级别
+    #[default]
+    Text,
+    /// 每行一个json对象，无法解析的行按照普通文本处理
+    Json,
+}
+
+/// 定时器错过tick时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MissedTick {
+    /// 立即补发所有错过的tick，之后仍然按照原来的计划触发
+    #[default]
+    Burst,
+    /// 只发送一次，跳过错过的tick，之后对齐到原来的计划
+    Skip,
+    /// 只发送一次，之后的计划从这次实际触发的时间开始重新计算
+    Delay,
+}
+
+/// dataflow 使用的时钟
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ClockMode {
+    /// 系统时钟
+    #[default]
+    Real,
+    /// 模拟时钟，只在测试驱动推进时前进，定时器按照模拟时间触发
+    Simulated,
+}
+
+/// 人类可读的时间长度，如 `30s`、`1h 30m`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HumanDuration(pub Duration);
+
+impl FromStr for HumanDuration {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        humantime::parse_duration(s.trim())
+            .map(HumanDuration)
+            .with_context(|| format!("invalid duration `{s}`"))
+    }
+}
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", humantime::format_duration(self.0))
+    }
+}
+
+impl Serialize for HumanDuration {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl JsonSchema for HumanDuration {
+    fn schema_name() -> String {
+        "HumanDuration".to_owned()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+impl<'de> Deserialize<'de> for HumanDuration {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// 字节数，可以是整数或者带单位的字符串，如 `512K`、`64Mi`、`2G`，单位都是1024进制
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number: f64 = number
+            .parse()
+            .with_context(|| format!("invalid byte size `{s}`"))?;
+        let unit: u64 = match unit.trim().trim_end_matches(['i', 'B', 'b']) {
+            "" => 1,
+            "K" | "k" => 1 << 10,
+            "M" | "m" => 1 << 20,
+            "G" | "g" => 1 << 30,
+            "T" | "t" => 1 << 40,
+            _ => bail!("unknown unit in byte size `{s}`"),
+        };
+        Ok(ByteSize((number * unit as f64) as u64))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 4] = ["TiB", "GiB", "MiB", "KiB"];
+        for (index, unit) in UNITS.iter().enumerate() {
+            let size = 1u64 << (10 * (UNITS.len() - index));
+            if self.0 >= size && self.0.is_multiple_of(size) {
+                return write!(f, "{}{unit}", self.0 / size);
+            }
+        }
+        write!(f, "{}B", self.0)
+    }
+}
+
+impl Serialize for ByteSize {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u64(self.0)
+    }
+}
+
+/// 整数或者带单位的字符串
+impl JsonSchema for ByteSize {
+    fn schema_name() -> String {
+        "ByteSize".to_owned()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(vec![InstanceType::Integer, InstanceType::String].into()),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bytes(u64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Bytes(bytes) => Ok(ByteSize(bytes)),
+            Raw::Text(text) => text.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// yaml中不加引号的 `1.0` 以及json中的 `1.0` 都是数字
+fn version_string<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Number(serde_json::Number),
+        Text(String),
+    }
+    Ok(match Raw::deserialize(deserializer)? {
+        Raw::Number(number) => number.to_string(),
+        Raw::Text(text) => text,
+    })
+}
+
+/// 数字或者字符串
+fn version_schema(_: &mut SchemaGenerator) -> Schema {
+    SchemaObject {
+        instance_type: Some(vec![InstanceType::Number, InstanceType::String].into()),
+        ..Default::default()
+    }
+    .into()
+}
+
+/// 默认的日志根目录
+fn default_log_dir() -> PathBuf {
+    env::temp_dir().join("dataflow").join("logs")
+}
+
 /// 描述节点的部署信息
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Deploy {
     /// 通信端点
@@ -301,13 +865,34 @@ pub struct Deploy {
     /// 通信模式
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub mode: Option<String>,
-    /// 日志文件地址
+    /// 日志目录，dataflow的deploy中为所有节点的根目录，节点的deploy中为该节点的目录
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub log: Option<PathBuf>,
+    /// 日志文件的轮转策略
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_rotation: Option<LogRotation>,
+    /// 节点输出的日志格式，默认为text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_format: Option<LogFormat>,
+    /// 校验时是否通过网络请求检查url类型的source，为false时只检查本地的下载缓存
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validate_urls: Option<bool>,
+    /// 运行时指标的http端点，只在dataflow的deploy中生效
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<MetricsConfig>,
+    /// 消息的分布式追踪，只在dataflow的deploy中生效
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tracing: Option<TracingConfig>,
+    /// 定时器的tick延迟之后如何处理错过的tick，默认为burst，只在dataflow的deploy中生效
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub missed_tick: Option<MissedTick>,
+    /// 定时器和运行时使用的时钟，默认为real，只在dataflow的deploy中生效
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock: Option<ClockMode>,
 }
 
 /// dataflow的工作节点申明结构体
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Node {
     /// 节点ID
     pub id: NodeId,
@@ -320,6 +905,15 @@ pub struct Node {
     /// 节点环境变量设置
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub envs: Option<BTreeMap<String, EnvValue>>,
+    /// 节点中所有operator的资源限制，operator可以覆盖其中的每一项
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<Resources>,
+    /// 节点的就绪检查，launch 会等待节点就绪后再启动它的上游节点
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub readiness: Option<Readiness>,
+    /// 节点的存活检查，节点中的operator定时发送心跳
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub liveness: Option<Liveness>,
     /// 部署信息
     #[serde(default)]
     pub deploy: Deploy,
@@ -329,7 +923,7 @@ pub struct Node {
 }
 
 /// dataflow的工作节点处理后的结构体
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct NormalNode {
     /// 节点ID
     pub id: NodeId,
@@ -342,6 +936,15 @@ pub struct NormalNode {
     /// 节点环境变量设置
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub envs: Option<BTreeMap<String, EnvValue>>,
+    /// 节点中所有operator的资源限制，operator可以覆盖其中的每一项
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<Resources>,
+    /// 节点的就绪检查，launch 会等待节点就绪后再启动它的上游节点
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub readiness: Option<Readiness>,
+    /// 节点的存活检查，节点中的operator定时发送心跳
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub liveness: Option<Liveness>,
     /// 部署信息
     #[serde(default)]
     pub deploy: Deploy,
@@ -352,7 +955,7 @@ pub struct NormalNode {
 
 impl NormalNode {
     /// 收集normalNode中的timer
-    pub(crate) fn collect_node_timer(&self) -> BTreeSet<Duration> {
+    pub(crate) fn collect_node_timer(&self) -> BTreeSet<Timer> {
         let mut dataflow_timers = BTreeSet::new();
         for operator in &self.kind.operators {
             dataflow_timers.extend(operator.config.run_config.collect_input_timers());
@@ -368,6 +971,32 @@ impl NormalNode {
         dataflow_timers
     }
 
+    /// 需要报告就绪的operator，没有设置 `sdk` 就绪信号时为空
+    pub(crate) fn ready_operators(&self) -> BTreeSet<OperatorId> {
+        match &self.readiness {
+            Some(Readiness {
+                signal: ReadinessSignal::Sdk,
+                operators: Some(operators),
+                ..
+            }) => operators.iter().cloned().collect(),
+            Some(Readiness {
+                signal: ReadinessSignal::Sdk,
+                ..
+            }) => self.heartbeat_operators(),
+            _ => BTreeSet::new(),
+        }
+    }
+
+    /// 通过sdk发送信号的operator，内置operator运行在节点进程中，不发送信号
+    pub(crate) fn heartbeat_operators(&self) -> BTreeSet<OperatorId> {
+        self.kind
+            .operators
+            .iter()
+            .filter(|operator| !matches!(operator.config.source, OperatorSource::Builtin(_)))
+            .map(|operator| operator.id.clone())
+            .collect()
+    }
+
     /// 收集normalNode中的input映射
     pub(crate) fn collect_node_input(&self) -> BTreeMap<DataId, Input> {
         let mut dataflow_inputs = BTreeMap::new();
@@ -386,7 +1015,7 @@ impl NormalNode {
     }
 
     /// 添加一个关联函数处理列表
-    pub(crate) fn collect_timers_from_nodes(nodes: &[NormalNode]) -> BTreeSet<Duration> {
+    pub(crate) fn collect_timers_from_nodes(nodes: &[NormalNode]) -> BTreeSet<Timer> {
         let mut nodes_timer = BTreeSet::new();
         for node in nodes {
             nodes_timer.extend(node.collect_node_timer());
@@ -404,7 +1033,7 @@ impl NormalNode {
 }
 
 /// 节点的类型，这里是个枚举，三选一
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(untagged)]
 pub enum NodeKind {
     Operators(MultipleOperatorDefinitions),
@@ -412,7 +1041,7 @@ pub enum NodeKind {
 }
 
 /// Operators配置信息的包装-多个Operator列表
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct MultipleOperatorDefinitions {
     pub operators: Vec<NormalOperatorDefinition>,
 }
@@ -427,7 +1056,7 @@ pub struct MultipleOperatorDefinitions {
 /// outputs:
 ///     - half-status
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct NodeRunConfig {
     /// 其中 DataId 就是 inputs 中 数据的id，如counter_1，counter_2 等
     /// Input 就是输入的映射，其中包含了输入的来源和队列的大小
@@ -441,12 +1070,12 @@ pub struct NodeRunConfig {
 
 impl NodeRunConfig {
     /// 将inputs中的timer收集起来，并返回
-    pub fn collect_input_timers(&self) -> BTreeSet<Duration> {
+    pub fn collect_input_timers(&self) -> BTreeSet<Timer> {
         self.inputs
             .values()
             .filter_map(|input| {
-                if let InputMapping::Timer { interval } = &input.mapping {
-                    Some(*interval)
+                if let InputMapping::Timer(timer) = &input.mapping {
+                    Some(timer.clone())
                 } else {
                     None
                 }
@@ -458,9 +1087,8 @@ impl NodeRunConfig {
         self.inputs
             .values()
             .filter_map(|input| {
-                if let InputMapping::Timer { interval } = &input.mapping {
-                    let duration = FormattedDuration(*interval);
-                    Some((DataId::from(format!("{duration}")), input.clone()))
+                if let InputMapping::Timer(timer) = &input.mapping {
+                    Some((timer.output_id(), input.clone()))
                 } else {
                     None
                 }
@@ -469,6 +1097,38 @@ impl NodeRunConfig {
     }
 }
 
+/// operator 的运行参数
+/// 字符串形式按照shell的规则切分，支持引号和转义
+/// 列表形式的每一项就是一个参数
+/// 两种形式中的 `${NAME}` 都会被展开
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum OperatorArgs {
+    Line(String),
+    List(Vec<String>),
+}
+
+impl OperatorArgs {
+    /// 得到最终的参数列表，lookup 用于查找变量
+    pub fn argv(&self, lookup: &dyn Fn(&str) -> Option<String>) -> Result<Vec<String>> {
+        let args = match self {
+            OperatorArgs::Line(line) => split_args(line)?,
+            OperatorArgs::List(list) => list.clone(),
+        };
+        args.iter().map(|arg| expand_vars(arg, lookup)).collect()
+    }
+}
+
+/// shell operator 的标准输入输出协议，每一行是一条数据
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OperatorIo {
+    /// `<id> <data>`，数据中的 `\\` 和换行会被转义
+    Lines,
+    /// `{"id": .., "data": ..}`
+    Jsonl,
+}
+
 /// 描述了Operator的来源
 #[derive(Debug, Serialize, Deserialize, Clone)]
 /// 并且所有都使用中划线分割约定
@@ -479,7 +1139,60 @@ pub enum OperatorSource {
     PythonModule(String),
     WasmModule(String),
     Shell(String),
+    /// 内置的operator，如 throttle、batch、merge
+    Builtin(String),
+}
+impl OperatorSource {
+    /// 获取source的类型名，和描述文件中的字段名一致
+    pub fn kind(&self) -> &'static str {
+        match self {
+            OperatorSource::ExeTarget(_) => "exe_target",
+            OperatorSource::SharedLibrary(_) => "shared_library",
+            OperatorSource::PythonModule(_) => "python_module",
+            OperatorSource::WasmModule(_) => "wasm_module",
+            OperatorSource::Shell(_) => "shell",
+            OperatorSource::Builtin(_) => "builtin",
+        }
+    }
 }
+/// operator中展开的字段，每种source只要求自己的key，不能限制operator的其他属性
+impl JsonSchema for OperatorSource {
+    fn schema_name() -> String {
+        "OperatorSource".to_owned()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let kinds = [
+            "exe_target",
+            "shared_library",
+            "python_module",
+            "wasm_module",
+            "shell",
+            "builtin",
+        ];
+        let variants = kinds.map(|kind| {
+            SchemaObject {
+                instance_type: Some(InstanceType::Object.into()),
+                object: Some(Box::new(ObjectValidation {
+                    required: [kind.to_owned()].into(),
+                    properties: [(kind.to_owned(), String::json_schema(gen))].into(),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            }
+            .into()
+        });
+        SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                one_of: Some(variants.to_vec()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
 impl ToString for OperatorSource {
     fn to_string(&self) -> String {
         match self {
@@ -488,12 +1201,13 @@ impl ToString for OperatorSource {
             OperatorSource::PythonModule(module) => module.clone(),
             OperatorSource::WasmModule(wasm) => wasm.clone(),
             OperatorSource::Shell(shell) => shell.clone(),
+            OperatorSource::Builtin(name) => name.clone(),
         }
     }
 }
 
 /// Operator配置信息
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct OperatorConfig {
     /// 名字
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -502,22 +1216,50 @@ pub struct OperatorConfig {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
 
-    /// 自定义节点的运行参数
+    /// 自定义节点的运行参数，可以是字符串或者列表
+    /// shell operator 的参数作为位置参数传递，脚本中通过 `"$@"` 或 `$1` 读取
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub args: Option<String>,
+    pub args: Option<OperatorArgs>,
+
+    /// shell operator 通过标准输入输出与dataflow交换数据的协议，不设置时不交换数据
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub io: Option<OperatorIo>,
+
+    /// 内置operator的参数，只用于builtin类型的source
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<BTreeMap<String, serde_json::Value>>")]
+    pub params: Option<BTreeMap<String, serde_yaml::Value>>,
 
     ///环境变量
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub envs: Option<BTreeMap<String, EnvValue>>,
 
+    /// 资源限制，会覆盖节点的资源限制
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<Resources>,
+
     /// 使用中划线分割定义Operator的来源
     #[serde(flatten)]
     pub source: OperatorSource,
 
+    /// url类型的source内容的sha256，下载后以及执行前都会进行校验
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+
     /// 描述运行之前的构建命令
     /// skip_serializing_if 表示 当值为None时，不序列化
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub build: Option<String>,
+    pub build: Option<BuildCommand>,
+
+    /// 构建命令的输入文件，支持glob，相对于工作目录
+    /// 声明后，输入文件和构建命令都没有变化时会跳过构建
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_inputs: Option<Vec<String>>,
+
+    /// 需要先完成构建的operator，格式为 `node` 或 `node/operator`，`node` 表示节点中所有的operator
+    /// 数据流中的上下游关系不影响构建的顺序
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_deps: Option<Vec<String>>,
 
     /// 节点运行配置
     #[serde(flatten)]
@@ -525,7 +1267,7 @@ pub struct OperatorConfig {
 }
 
 /// Operator配置信息的包装-单个的op
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct SingleOperatorDefinition {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub id: Option<OperatorId>,
@@ -534,7 +1276,7 @@ pub struct SingleOperatorDefinition {
     pub config: OperatorConfig,
 }
 /// Operator配置信息的包装-多个op
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct NormalOperatorDefinition {
     pub id: OperatorId,
     /// 嵌入了OperatorConfig
@@ -549,6 +1291,9 @@ pub struct NormalOperatorDefinition {
 ///     tick:
 ///         source: dataflow/timer/millis/100
 ///         queue_size: 1000
+///     feedback:
+///         source: runtime-node/operator-c-api/half-status
+///         allow_cycle: true
 /// ```
 /// 其中
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -556,9 +1301,11 @@ pub struct NormalOperatorDefinition {
 pub struct Input {
     pub mapping: InputMapping,
     pub queue_size: usize,
+    /// 是否允许该输入形成反馈环，允许时拓扑分析会忽略这条边
+    pub allow_cycle: bool,
 }
 /// 使用InputDef来兼容两种输入格式
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 #[serde(untagged)]
 pub enum InputDef {
     /// 1. 只有mapping
@@ -574,9 +1321,23 @@ pub enum InputDef {
         source: InputMapping,
         /// 这里匹配的 queue_size: 1000
         queue_size: Option<usize>,
+        /// 这里匹配的 allow_cycle: true
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        allow_cycle: Option<bool>,
     },
 }
 
+/// 描述文件中的写法与InputDef一致
+impl JsonSchema for Input {
+    fn schema_name() -> String {
+        "Input".to_owned()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        InputDef::json_schema(gen)
+    }
+}
+
 /// 为InputDef实现From<Input>和From<InputDef>
 impl From<Input> for InputDef {
     fn from(input: Input) -> Self {
@@ -585,13 +1346,16 @@ impl From<Input> for InputDef {
                 mapping,
                 // 默认为10
                 queue_size: 10,
+                allow_cycle: false,
             } => Self::MappingOnly(mapping),
             Input {
                 mapping,
                 queue_size,
+                allow_cycle,
             } => Self::WithOptions {
                 source: mapping,
                 queue_size: Some(queue_size),
+                allow_cycle: allow_cycle.then_some(true),
             },
         }
     }
@@ -604,24 +1368,164 @@ impl From<InputDef> for Input {
                 mapping,
                 // 默认为10
                 queue_size: 10,
+                allow_cycle: false,
             },
-            InputDef::WithOptions { source, queue_size } => Self {
+            InputDef::WithOptions {
+                source,
+                queue_size,
+                allow_cycle,
+            } => Self {
                 mapping: source,
                 queue_size: queue_size.unwrap_or(10),
+                allow_cycle: allow_cycle.unwrap_or(false),
             },
         }
     }
 }
 
 /// newType模式，创建 FormattedDuration类型专门用于格式化Duration
-/// 然后为其实现Display trait
+/// 然后为其实现Display trait，选择能精确表示该时长的最大单位
 pub struct FormattedDuration(pub Duration);
 impl fmt::Display for FormattedDuration {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.0.subsec_millis() == 0 {
+        if self.0.subsec_nanos() == 0 {
             write!(f, "secs/{}", self.0.as_secs())
-        } else {
+        } else if self.0.subsec_nanos().is_multiple_of(1_000_000) {
             write!(f, "millis/{}", self.0.as_millis())
+        } else {
+            write!(f, "micros/{}", self.0.as_micros())
+        }
+    }
+}
+
+/// 解析 `<unit>/<value>` 形式的时长，unit 为 secs、millis 或 micros
+fn parse_timer_duration(unit: &str, value: &str) -> Result<Duration, String> {
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("{unit} must be an integer (got `{value}`)"))?;
+    match unit {
+        "secs" => Ok(Duration::from_secs(value)),
+        "millis" => Ok(Duration::from_millis(value)),
+        "micros" => Ok(Duration::from_micros(value)),
+        other => Err(format!(
+            "timer unit must be one of secs, millis or micros (got `{other}`)"
+        )),
+    }
+}
+
+/// 内部实现的定时器
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Timer {
+    /// dataflow/timer/millis/100
+    /// dataflow/timer/secs/60/offset/secs/15
+    /// 设置了offset时，tick 对齐到 unix 时间上 interval 的整数倍再加上 offset
+    Interval {
+        interval: Duration,
+        offset: Option<Duration>,
+    },
+    /// dataflow/timer/once 或 dataflow/timer/once/secs/5
+    /// 启动后经过 delay 只触发一次
+    Once { delay: Duration },
+    /// dataflow/cron/*/5 * * * *
+    Cron(CronSchedule),
+}
+
+impl Timer {
+    /// 定时器节点上对应的 output id，同时也是 topic 中 `dataflow/timer/` 之后的部分
+    /// cron 表达式中的空格、`*` 和 `/` 不能出现在 topic 中，需要替换
+    pub fn output_id(&self) -> DataId {
+        let id = match self {
+            Timer::Cron(schedule) => {
+                let expr: String = schedule
+                    .expr()
+                    .chars()
+                    .map(|c| match c {
+                        ' ' => '_',
+                        '*' => 'x',
+                        '/' => ':',
+                        c => c,
+                    })
+                    .collect();
+                format!("cron/{expr}")
+            }
+            timer => timer.label(),
+        };
+        DataId::from(id)
+    }
+
+    /// 可视化时显示的名字，即去掉 `timer/` 前缀的写法
+    pub fn label(&self) -> String {
+        self.to_string().trim_start_matches("timer/").to_owned()
+    }
+
+    /// 解析 `dataflow/` 之后的部分
+    fn parse(kind: &str, rest: &str) -> Result<Timer, String> {
+        match kind {
+            "timer" => {
+                let parts: Vec<_> = rest.split('/').collect();
+                match parts[..] {
+                    ["once"] => Ok(Timer::Once {
+                        delay: Duration::ZERO,
+                    }),
+                    ["once", unit, value] => Ok(Timer::Once {
+                        delay: parse_timer_duration(unit, value)?,
+                    }),
+                    [unit, value] => Ok(Timer::Interval {
+                        interval: parse_timer_duration(unit, value)?,
+                        offset: None,
+                    }),
+                    [unit, value, "offset", offset_unit, offset_value] => Ok(Timer::Interval {
+                        interval: parse_timer_duration(unit, value)?,
+                        offset: Some(parse_timer_duration(offset_unit, offset_value)?),
+                    }),
+                    _ => Err(
+                        "timer input must be `<unit>/<value>[/offset/<unit>/<value>]` or `once[/<unit>/<value>]` (e.g. `secs/5` or `millis/100`)"
+                            .to_owned(),
+                    ),
+                }
+            }
+            "cron" => rest
+                .parse()
+                .map(Timer::Cron)
+                .map_err(|e| format!("invalid cron expression `{rest}`: {e:#}")),
+            other => Err(format!("unknown dataflow input `{other}`")),
+        }
+    }
+
+    /// 检查定时器的参数
+    pub fn validate(&self) -> Result<()> {
+        if let Timer::Interval { interval, offset } = self {
+            if interval.is_zero() {
+                bail!("timer interval must be greater than 0");
+            }
+            if let Some(offset) = offset {
+                if offset >= interval {
+                    bail!(
+                        "timer offset ({}) must be less than the interval ({})",
+                        FormattedDuration(*offset),
+                        FormattedDuration(*interval)
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 不带 `dataflow/` 前缀的写法
+impl fmt::Display for Timer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Timer::Interval { interval, offset } => {
+                write!(f, "timer/{}", FormattedDuration(*interval))?;
+                if let Some(offset) = offset {
+                    write!(f, "/offset/{}", FormattedDuration(*offset))?;
+                }
+                Ok(())
+            }
+            Timer::Once { delay } if delay.is_zero() => write!(f, "timer/once"),
+            Timer::Once { delay } => write!(f, "timer/once/{}", FormattedDuration(*delay)),
+            Timer::Cron(schedule) => write!(f, "cron/{schedule}"),
         }
     }
 }
@@ -631,12 +1535,10 @@ impl fmt::Display for FormattedDuration {
 /// 2. cxx-node-c-api/counter
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum InputMapping {
-    /// dataflow/timer/millis/100
+    /// dataflow/timer/millis/100、dataflow/cron/0 * * * * 等
     /// 表示的是内部实现的 timer 类型
     /// 未来更多的类型可以在这里添加
-    Timer {
-        interval: Duration,
-    },
+    Timer(Timer),
     User(UserInputMapping),
 }
 
@@ -658,59 +1560,36 @@ impl<'de> Deserialize<'de> for InputMapping {
     {
         // 先反序列化为String
         let string = String::deserialize(deserializer)?;
+        string.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl JsonSchema for InputMapping {
+    fn schema_name() -> String {
+        "InputMapping".to_owned()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+impl FromStr for InputMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         // 解析 source/output 的形式
-        let (source, output) = string
+        let (source, output) = s
             .split_once('/')
-            .ok_or_else(|| serde::de::Error::custom("input must start with `<source>/`"))?;
+            .ok_or_else(|| "input must start with `<source>/`".to_string())?;
 
         // 根据source的不同，解析为不同的InputMapping
-        let deserialized = match source {
+        let parsed = match source {
             // 如果source 是dataflow，那么表示是内部实现的 output
             // 我们进一步匹配处理 output
             "dataflow" => match output.split_once('/') {
-                Some(("timer", output)) => {
-                    // 匹配 unit/value 的形式
-                    let (unit, value) = output.split_once('/').ok_or_else(|| {
-                        serde::de::Error::custom(
-                            "timer input must specify unit and value (e.g. `secs/5` or `millis/100`)",
-                        )
-                    })?;
-                    // 只接受 secs 和 millis
-                    let interval: Duration = match unit {
-                        "secs" => {
-                            let value = value.parse().map_err(|_| {
-                                serde::de::Error::custom(format!(
-                                    "secs must be an integer (got `{value}`)"
-                                ))
-                            })?;
-                            Duration::from_secs(value)
-                        }
-                        "millis" => {
-                            let value = value.parse().map_err(|_| {
-                                serde::de::Error::custom(format!(
-                                    "millis must be an integer (got `{value}`)"
-                                ))
-                            })?;
-                            Duration::from_millis(value)
-                        }
-                        other => {
-                            return Err(serde::de::Error::custom(format!(
-                                "timer unit must be either secs or millis (got `{other}`"
-                            )))
-                        }
-                    };
-                    Self::Timer { interval }
-                }
-                Some((other, _)) => {
-                    return Err(serde::de::Error::custom(format!(
-                        "unknown dataflow input `{other}`"
-                    )))
-                }
-                None => {
-                    return Err(serde::de::Error::custom(
-                        "dataflow input has invalid format",
-                    ))
-                }
+                Some((kind, rest)) => Self::Timer(Timer::parse(kind, rest)?),
+                None => return Err("dataflow input has invalid format".to_string()),
             },
             // 否则是用户的 output
             _ => Self::User(UserInputMapping {
@@ -719,7 +1598,7 @@ impl<'de> Deserialize<'de> for InputMapping {
             }),
         };
 
-        Ok(deserialized)
+        Ok(parsed)
     }
 }
 
@@ -732,21 +1611,27 @@ impl InputMapping {
 
         match self {
             InputMapping::User(mapping) => &mapping.source,
-            InputMapping::Timer { .. } => {
+            InputMapping::Timer(_) => {
                 DATAFLOW_NODE_ID.get_or_init(|| NodeId("dataflow".to_string()))
             }
         }
     }
+
+    /// 订阅该输入时使用的topic
+    /// timer 的 topic 是定时器节点上的 output，与描述文件中的写法不一定相同
+    pub fn topic(&self) -> String {
+        match self {
+            InputMapping::Timer(timer) => format!("dataflow/timer/{}", timer.output_id()),
+            InputMapping::User(_) => self.to_string(),
+        }
+    }
 }
 
 /// 为 InputMapping实现Display trait
 impl fmt::Display for InputMapping {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            InputMapping::Timer { interval } => {
-                let duration = FormattedDuration(*interval);
-                write!(f, "dataflow/timer/{duration}")
-            }
+            InputMapping::Timer(timer) => write!(f, "dataflow/{timer}"),
             InputMapping::User(mapping) => {
                 write!(f, "{}/{}", mapping.source, mapping.output)
             }
@@ -766,6 +1651,26 @@ mod tests {
     use super::*;
     use std::path::PathBuf;
 
+    #[test]
+    fn test_default_log_dir() {
+        let log_dir = |path: &str| {
+            let mut descriptor: Descriptor =
+                serde_yaml::from_str("version: 1.0\nnodes: []").unwrap();
+            descriptor.set_default_log_dir(Path::new(path));
+            descriptor.deploy.log.unwrap()
+        };
+        // 不同目录下的同名描述文件使用不同的日志目录
+        let a = log_dir("a/dataflow.yml");
+        assert!(a
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("dataflow-"));
+        assert_eq!(a, log_dir("a/dataflow.yml"));
+        assert_ne!(a, log_dir("b/dataflow.yml"));
+    }
+
     #[test]
     fn test_create() {
         let cargo_path = env!("CARGO_MANIFEST_DIR");
@@ -782,4 +1687,44 @@ mod tests {
         println!("{:#?}", visualized);
         println!("\n\n\n");
     }
+    #[test]
+    fn test_byte_size() {
+        assert_eq!("512".parse::<ByteSize>().unwrap(), ByteSize(512));
+        assert_eq!("64Mi".parse::<ByteSize>().unwrap(), ByteSize(64 << 20));
+        assert_eq!("2GB".parse::<ByteSize>().unwrap(), ByteSize(2 << 30));
+        assert!("12X".parse::<ByteSize>().is_err());
+        assert_eq!(ByteSize(64 << 20).to_string(), "64MiB");
+
+        let resources: Resources = serde_yaml::from_str("memory: 1G\nnice: 30").unwrap();
+        assert_eq!(resources.memory, Some(ByteSize(1 << 30)));
+        assert!(resources.validate().is_err());
+    }
+    #[test]
+    fn test_timer_mapping() {
+        let parse = |s: &str| serde_yaml::from_str::<InputMapping>(s);
+        let micros = parse("dataflow/timer/micros/1500").unwrap();
+        assert_eq!(micros.to_string(), "dataflow/timer/micros/1500");
+        assert_eq!(
+            FormattedDuration(Duration::from_micros(2000)).to_string(),
+            "millis/2"
+        );
+
+        let offset = parse("dataflow/timer/secs/60/offset/millis/500").unwrap();
+        assert_eq!(offset.topic(), "dataflow/timer/secs/60/offset/millis/500");
+
+        let once = parse("dataflow/timer/once").unwrap();
+        assert_eq!(
+            once,
+            InputMapping::Timer(Timer::Once {
+                delay: Duration::ZERO
+            })
+        );
+
+        let cron = parse("dataflow/cron/*/5 * * * *").unwrap();
+        assert_eq!(cron.to_string(), "dataflow/cron/*/5 * * * *");
+        assert_eq!(cron.topic(), "dataflow/timer/cron/x:5_x_x_x_x");
+
+        assert!(parse("dataflow/timer/hours/1").is_err());
+        assert!(parse("dataflow/cron/* * *").is_err());
+    }
 }
@@ -0,0 +1,487 @@
+use super::descriptor::{DataId, Descriptor, NodeId, NodeKind, OperatorId};
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt, fmt::Write as _};
+use yaml_rust::{
+    parser::{Event, MarkedEventReceiver, Parser},
+    scanner::Marker,
+};
+
+/// 诊断信息的严重程度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => f.write_str("error"),
+            Severity::Warning => f.write_str("warning"),
+        }
+    }
+}
+
+/// 描述文件中的位置，行和列都从1开始
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// 诊断信息指向的描述文件中的元素
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Location {
+    /// 整个描述文件
+    Dataflow,
+    /// 部署信息，node 为 None 时表示描述文件顶层的deploy
+    Deploy {
+        node: Option<NodeId>,
+    },
+    Node {
+        node: NodeId,
+    },
+    Operator {
+        node: NodeId,
+        operator: OperatorId,
+    },
+    /// operator 的来源，如 shell、exe_target
+    Source {
+        node: NodeId,
+        operator: OperatorId,
+    },
+    Input {
+        node: NodeId,
+        operator: OperatorId,
+        input: DataId,
+    },
+    Output {
+        node: NodeId,
+        operator: OperatorId,
+        output: DataId,
+    },
+}
+
+/// 一条诊断信息
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    /// 诊断代码，E开头为错误，W开头为警告
+    pub code: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    pub location: Location,
+    /// 在描述文件中的位置，无法定位时为 None
+    pub span: Option<Span>,
+    /// 位置所在的文件，为 None 时是检查的描述文件本身，否则是include的文件
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+}
+
+impl Diagnostic {
+    /// 构造一个错误
+    pub fn error(code: &'static str, location: Location, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            severity: Severity::Error,
+            message: message.into(),
+            location,
+            span: None,
+            file: None,
+        }
+    }
+
+    /// 构造一个警告
+    pub fn warning(code: &'static str, location: Location, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            severity: Severity::Warning,
+            message: message.into(),
+            location,
+            span: None,
+            file: None,
+        }
+    }
+
+    /// 设置在描述文件中的位置
+    pub fn with_span(mut self, span: Option<Span>) -> Self {
+        self.span = span;
+        self
+    }
+
+    /// 设置位置所在的文件
+    pub fn with_file(mut self, file: Option<String>) -> Self {
+        self.file = file;
+        self
+    }
+
+    /// 是否为错误
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)
+    }
+}
+
+/// 描述文件中每个元素的位置索引
+/// key 为从根开始的路径，mapping 使用key，sequence 中的集合使用下标，sequence 中的标量使用值本身
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    spans: BTreeMap<Vec<String>, Span>,
+}
+
+/// 构造 SourceMap 时的栈帧
+enum Frame {
+    Mapping { key: Option<String> },
+    Sequence { index: usize },
+}
+
+/// 接收 yaml 事件，记录路径和位置
+#[derive(Default)]
+struct SourceMapBuilder {
+    frames: Vec<Frame>,
+    /// 每个栈帧对应的路径片段，根节点没有路径片段
+    path: Vec<String>,
+    /// sequence 中的集合，等待使用其第一个标量的位置
+    pending: Option<Vec<String>>,
+    spans: BTreeMap<Vec<String>, Span>,
+}
+
+impl SourceMapBuilder {
+    /// 记录当前路径下某个片段的位置
+    fn record(&mut self, segment: String, mark: Marker) {
+        let mut path = self.path.clone();
+        path.push(segment);
+        self.spans.entry(path).or_insert(Span {
+            line: mark.line(),
+            column: mark.col() + 1,
+        });
+    }
+
+    /// 进入一个集合，记录该集合在父集合中的路径片段
+    fn enter(&mut self) {
+        let segment = match self.frames.last_mut() {
+            Some(Frame::Mapping { key }) => key.take(),
+            Some(Frame::Sequence { index }) => {
+                *index += 1;
+                Some((*index - 1).to_string())
+            }
+            None => None,
+        };
+        if let Some(segment) = segment {
+            self.path.push(segment);
+            // 集合的起始位置不够准确，使用集合中第一个标量的位置
+            if let Some(Frame::Sequence { .. }) = self.frames.last() {
+                self.pending.get_or_insert_with(|| self.path.clone());
+            }
+        }
+    }
+
+    /// 处理标量，mapping 中的key 和 sequence 中的标量会被记录
+    fn on_scalar(&mut self, value: String, mark: Marker) {
+        match self.frames.last_mut() {
+            // mapping 中没有key时，这个标量就是key
+            Some(Frame::Mapping { key }) if key.is_none() => {
+                *key = Some(value.clone());
+                self.record(value, mark);
+            }
+            Some(Frame::Mapping { key }) => {
+                *key = None;
+            }
+            Some(Frame::Sequence { index }) => {
+                *index += 1;
+                self.record(value, mark);
+            }
+            None => {}
+        }
+    }
+
+    /// 离开一个集合
+    fn leave(&mut self) {
+        self.frames.pop();
+        if !self.frames.is_empty() {
+            self.path.pop();
+        }
+    }
+}
+
+impl MarkedEventReceiver for SourceMapBuilder {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(value, ..) => {
+                if let Some(path) = self.pending.take() {
+                    self.spans.entry(path).or_insert(Span {
+                        line: mark.line(),
+                        column: mark.col() + 1,
+                    });
+                }
+                self.on_scalar(value, mark)
+            }
+            Event::MappingStart(_) => {
+                self.enter();
+                self.frames.push(Frame::Mapping { key: None });
+            }
+            Event::SequenceStart(_) => {
+                self.enter();
+                self.frames.push(Frame::Sequence { index: 0 });
+            }
+            Event::MappingEnd | Event::SequenceEnd => self.leave(),
+            // 引用也是一个值
+            Event::Alias(_) => {
+                if let Some(Frame::Mapping { key }) = self.frames.last_mut() {
+                    *key = None;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl SourceMap {
+    /// 解析yaml文本，构造位置索引，yaml不合法时返回已经解析的部分
+    pub fn parse(source: &str) -> Self {
+        let mut builder = SourceMapBuilder::default();
+        let _ = Parser::new(source.chars()).load(&mut builder, false);
+        Self {
+            spans: builder.spans,
+        }
+    }
+
+    /// 查找诊断信息指向的元素在描述文件中的位置
+    /// 找不到精确的位置时，返回最近的父元素的位置
+    pub fn resolve(&self, descriptor: &Descriptor, location: &Location) -> Option<Span> {
+        self.resolve_path(Self::path(descriptor, location))
+    }
+
+    /// 查找路径在描述文件中的位置，找不到时返回最近的父元素的位置
+    pub(crate) fn resolve_path(&self, mut path: Vec<String>) -> Option<Span> {
+        while !path.is_empty() {
+            if let Some(span) = self.spans.get(&path) {
+                return Some(*span);
+            }
+            path.pop();
+        }
+        None
+    }
+
+    /// 计算元素在描述文件中的路径
+    pub(crate) fn path(descriptor: &Descriptor, location: &Location) -> Vec<String> {
+        // 找到operator 所在的路径，单op节点的operator就是节点本身
+        let operator_path = |node_id: &NodeId, operator_id: &OperatorId| {
+            let Some(index) = descriptor.nodes.iter().position(|n| &n.id == node_id) else {
+                return vec![];
+            };
+            let mut path = vec!["nodes".to_owned(), index.to_string()];
+            if let NodeKind::Operators(definitions) = &descriptor.nodes[index].kind {
+                if let Some(operator_index) = definitions
+                    .operators
+                    .iter()
+                    .position(|o| &o.id == operator_id)
+                {
+                    path.extend(["operators".to_owned(), operator_index.to_string()]);
+                }
+            }
+            path
+        };
+        match location {
+            Location::Dataflow => vec![],
+            Location::Deploy { node: None } => vec!["deploy".to_owned()],
+            Location::Deploy { node: Some(node) } => {
+                let mut path = match descriptor.nodes.iter().position(|n| &n.id == node) {
+                    Some(index) => vec!["nodes".to_owned(), index.to_string()],
+                    None => return vec![],
+                };
+                path.push("deploy".to_owned());
+                path
+            }
+            Location::Node { node } => match descriptor.nodes.iter().position(|n| &n.id == node) {
+                Some(index) => vec!["nodes".to_owned(), index.to_string()],
+                None => vec![],
+            },
+            Location::Operator { node, operator } => operator_path(node, operator),
+            Location::Source { node, operator } => {
+                let mut path = operator_path(node, operator);
+                let source = descriptor
+                    .nodes
+                    .iter()
+                    .find(|n| &n.id == node)
+                    .and_then(|n| match &n.kind {
+                        NodeKind::Operator(definition) => Some(&definition.config.source),
+                        NodeKind::Operators(definitions) => definitions
+                            .operators
+                            .iter()
+                            .find(|o| &o.id == operator)
+                            .map(|o| &o.config.source),
+                    });
+                if let Some(source) = source {
+                    path.push(source.kind().to_owned());
+                }
+                path
+            }
+            Location::Input {
+                node,
+                operator,
+                input,
+            } => {
+                let mut path = operator_path(node, operator);
+                path.extend(["inputs".to_owned(), input.to_string()]);
+                path
+            }
+            Location::Output {
+                node,
+                operator,
+                output,
+            } => {
+                let mut path = operator_path(node, operator);
+                path.extend(["outputs".to_owned(), output.to_string()]);
+                path
+            }
+        }
+    }
+}
+
+/// 以编译器的风格输出诊断信息
+/// ```text
+/// error[E005]: output `a/a/out` mapped to input `b/b/in` does not exist
+///   --> dataflow.yml:12:7
+///    |
+/// 12 |       in: a/out
+///    |       ^
+/// ```
+/// sources 为文件名到文件内容的映射，包括include的文件
+pub fn render_diagnostics(
+    file_name: &str,
+    sources: &BTreeMap<String, String>,
+    diagnostics: &[Diagnostic],
+) -> String {
+    let mut rendered = String::new();
+    for diagnostic in diagnostics {
+        writeln!(rendered, "{diagnostic}").unwrap();
+        let span_file = diagnostic.file.as_deref().unwrap_or(file_name);
+        let lines: Vec<_> = sources
+            .get(span_file)
+            .map(|source| source.lines().collect())
+            .unwrap_or_default();
+        match diagnostic.span {
+            Some(Span { line, column }) => {
+                let gutter = " ".repeat(line.to_string().len());
+                writeln!(rendered, "{gutter}--> {span_file}:{line}:{column}").unwrap();
+                if let Some(text) = lines.get(line.wrapping_sub(1)) {
+                    writeln!(rendered, "{gutter} |").unwrap();
+                    writeln!(rendered, "{line} | {text}").unwrap();
+                    let padding = " ".repeat(column.saturating_sub(1));
+                    writeln!(rendered, "{gutter} | {padding}^").unwrap();
+                }
+            }
+            None => writeln!(rendered, " --> {span_file}").unwrap(),
+        }
+        rendered.push('\n');
+    }
+    let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+    let warnings = diagnostics.len() - errors;
+    write!(
+        rendered,
+        "{file_name}: {errors} error(s), {warnings} warning(s)"
+    )
+    .unwrap();
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATAFLOW: &str = r#"version: 1.0
+nodes:
+  - id: source
+    shell: ./source.sh
+    outputs:
+      - image
+  - id: runtime
+    operators:
+      - id: detect
+        exe_target: ./detect
+        inputs:
+          image: source/image
+"#;
+
+    #[test]
+    fn test_resolve_span() {
+        let descriptor: Descriptor = serde_yaml::from_str(DATAFLOW).unwrap();
+        let source_map = SourceMap::parse(DATAFLOW);
+        let node = |id: &str| NodeId::from(id.to_owned());
+        let operator = |id: &str| OperatorId::from(id.to_owned());
+
+        let span = source_map.resolve(
+            &descriptor,
+            &Location::Output {
+                node: node("source"),
+                operator: operator("source"),
+                output: DataId::from("image".to_owned()),
+            },
+        );
+        assert_eq!(span, Some(Span { line: 6, column: 9 }));
+
+        let span = source_map.resolve(
+            &descriptor,
+            &Location::Input {
+                node: node("runtime"),
+                operator: operator("detect"),
+                input: DataId::from("image".to_owned()),
+            },
+        );
+        assert_eq!(
+            span,
+            Some(Span {
+                line: 12,
+                column: 11
+            })
+        );
+
+        let span = source_map.resolve(
+            &descriptor,
+            &Location::Source {
+                node: node("runtime"),
+                operator: operator("detect"),
+            },
+        );
+        assert_eq!(
+            span,
+            Some(Span {
+                line: 10,
+                column: 9
+            })
+        );
+
+        // 节点中没有deploy时，返回节点的位置
+        let span = source_map.resolve(
+            &descriptor,
+            &Location::Deploy {
+                node: Some(node("runtime")),
+            },
+        );
+        assert_eq!(span, Some(Span { line: 7, column: 5 }));
+    }
+
+    #[test]
+    fn test_render_diagnostics() {
+        let diagnostic = Diagnostic::error("E002", Location::Dataflow, "no executable")
+            .with_span(Some(Span { line: 4, column: 5 }));
+        let sources = BTreeMap::from([("dataflow.yml".to_owned(), DATAFLOW.to_owned())]);
+        let rendered = render_diagnostics("dataflow.yml", &sources, &[diagnostic]);
+        assert_eq!(
+            rendered,
+            "error[E002]: no executable\n \
+            --> dataflow.yml:4:5\n  \
+            |\n\
+            4 |     shell: ./source.sh\n  \
+            |     ^\n\n\
+            dataflow.yml: 1 error(s), 0 warning(s)"
+        );
+    }
+}
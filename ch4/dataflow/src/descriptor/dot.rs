@@ -0,0 +1,253 @@
+use super::descriptor::{
+    DataId, Input, InputMapping, NodeId, NormalNode, NormalOperatorDefinition, OperatorSource,
+    UserInputMapping,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+};
+
+/// 将所有节点转为graphviz dot图字符串
+/// 结构与mermaid图保持一致：节点子图、operator形状、timer子图以及missing输入
+pub(crate) fn visualize_nodes(nodes: &[NormalNode]) -> String {
+    let mut graph = "digraph dataflow {\n".to_owned();
+    graph.push_str("  rankdir=TB;\n");
+    graph.push_str("  compound=true;\n");
+    graph.push_str("  node [fontname=\"Helvetica\"];\n");
+    graph.push_str("  edge [fontname=\"Helvetica\", fontsize=10];\n");
+    let mut all_nodes = HashMap::new();
+
+    // 处理节点信息
+    for node in nodes {
+        visualize_node(node, &mut graph);
+        all_nodes.insert(&node.id, node);
+    }
+
+    // 处理dataflow中的timer
+    let dataflow_timers = NormalNode::collect_timers_from_nodes(nodes);
+    if !dataflow_timers.is_empty() {
+        writeln!(graph, "  subgraph cluster___dataflow___ {{").unwrap();
+        writeln!(graph, "    label=\"dataflow\";").unwrap();
+        writeln!(graph, "    subgraph cluster___timer_timer___ {{").unwrap();
+        writeln!(graph, "      label=\"timer\";").unwrap();
+        for timer in dataflow_timers {
+            writeln!(
+                graph,
+                "      {} [label={}, shape=invtrapezium];",
+                quote(&format!("dataflow/timer/{}", timer.output_id())),
+                quote(&timer.label())
+            )
+            .unwrap();
+        }
+        graph.push_str("    }\n");
+        graph.push_str("  }\n");
+    }
+
+    // 处理每个节点的输入
+    let mut missing = false;
+    for node in nodes {
+        visualize_node_inputs(node, &mut graph, &all_nodes, &mut missing)
+    }
+    // 只有存在找不到的输入时，才声明missing节点
+    if missing {
+        writeln!(
+            graph,
+            "  missing [label=\"missing\", shape=cds, color=red];"
+        )
+        .unwrap();
+    }
+
+    graph.push_str("}\n");
+    graph
+}
+
+/// 为dot的id或label加上双引号，并转义其中的特殊字符
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// 可视化节点，主要是operators的可视化，将其转为字符串
+fn visualize_node(node: &NormalNode, graph: &mut String) {
+    visualize_operators(&node.id, &node.kind.operators, graph);
+}
+
+/// 可视化operators，每个节点是一个cluster子图
+fn visualize_operators(
+    node_id: &NodeId,
+    operators: &[NormalOperatorDefinition],
+    graph: &mut String,
+) {
+    writeln!(
+        graph,
+        "  subgraph {} {{",
+        quote(&format!("cluster_{node_id}"))
+    )
+    .unwrap();
+    writeln!(graph, "    label={};", quote(node_id)).unwrap();
+    for operator in operators {
+        let operator_id = &operator.id;
+        let (label, shape) = match &operator.config.source {
+            // builtin operator，标注内置的类型
+            OperatorSource::Builtin(name) => {
+                (format!("{operator_id} (builtin: {name})"), "hexagon")
+            }
+            _ => (operator_id.to_string(), operator_shape(operator)),
+        };
+        writeln!(
+            graph,
+            "    {} [label={}, shape={shape}];",
+            quote(&format!("{node_id}/{operator_id}")),
+            quote(&label)
+        )
+        .unwrap();
+    }
+
+    graph.push_str("  }\n");
+}
+
+/// operator的形状，与mermaid图一致
+fn operator_shape(operator: &NormalOperatorDefinition) -> &'static str {
+    if operator.config.run_config.inputs.is_empty() {
+        // source operator
+        "invtrapezium"
+    } else if operator.config.run_config.outputs.is_empty() {
+        // sink operator
+        "trapezium"
+    } else {
+        // normal operator
+        "box"
+    }
+}
+
+/// 可视化节点的输入
+/// 每个节点可能有多个operator
+fn visualize_node_inputs(
+    node: &NormalNode,
+    graph: &mut String,
+    nodes: &HashMap<&NodeId, &NormalNode>,
+    missing: &mut bool,
+) {
+    let node_id = &node.id;
+    // 对于每个节点的每个operator
+    // 将其输入可视化
+    for operator in node.kind.operators.iter() {
+        visualize_operator_inputs(
+            &format!("{node_id}/{}", operator.id),
+            &operator.config.run_config.inputs,
+            graph,
+            nodes,
+            missing,
+        )
+    }
+}
+
+/// 可视化operator的输入
+fn visualize_operator_inputs(
+    target: &str,
+    inputs: &BTreeMap<DataId, Input>,
+    graph: &mut String,
+    nodes: &HashMap<&NodeId, &NormalNode>,
+    missing: &mut bool,
+) {
+    for (input_id, input) in inputs {
+        match &input.mapping {
+            // 对于时间类型的输入，将timer 作为 source
+            mapping @ InputMapping::Timer(_) => {
+                writeln!(
+                    graph,
+                    "  {} -> {} [label={}];",
+                    quote(&mapping.topic()),
+                    quote(target),
+                    quote(input_id)
+                )
+                .unwrap();
+            }
+            InputMapping::User(mapping) => {
+                // 自定义的mapping直接调用此函数
+                visualize_user_mapping(mapping, target, nodes, input_id, graph, missing)
+            }
+        }
+    }
+}
+
+/// 可视化自定义的input mapping
+fn visualize_user_mapping(
+    mapping: &UserInputMapping,
+    target: &str,
+    nodes: &HashMap<&NodeId, &NormalNode>,
+    input_id: &DataId,
+    graph: &mut String,
+    missing: &mut bool,
+) {
+    let UserInputMapping { source, output } = mapping;
+    let mut source_found = false;
+    if let Some(source_node) = nodes.get(source) {
+        // 如果source是一个节点，就连接source和target
+        let (operator_id, output) = output.split_once('/').unwrap_or(("", output));
+        // 找到source节点中的指定的那个operator
+        if let Some(operator) = source_node
+            .kind
+            .operators
+            .iter()
+            .find(|o| o.id.to_string().as_str() == operator_id)
+        {
+            // 如果operator中有那个output data
+            // 那么就将其连接到target
+            if operator.config.run_config.outputs.contains(output) {
+                // 如果名字不一样还要设置一个as
+                let data = if output == input_id.as_str() {
+                    output.to_string()
+                } else {
+                    format!("{output} as {input_id}")
+                };
+                writeln!(
+                    graph,
+                    "  {} -> {} [label={}];",
+                    quote(&format!("{source}/{operator_id}")),
+                    quote(target),
+                    quote(&data)
+                )
+                .unwrap();
+                source_found = true;
+            }
+        }
+    }
+    // 如果，没有找到source，就将其作为missing
+    if !source_found {
+        *missing = true;
+        writeln!(
+            graph,
+            "  missing -> {} [label={}, color=red];",
+            quote(target),
+            quote(input_id)
+        )
+        .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{descriptor::descriptor::Descriptor, harness::fixture};
+
+    #[test]
+    fn test_visualize_dot() {
+        let descriptor = Descriptor::blocking_read(&fixture("camera.yml")).unwrap();
+        let dot = descriptor.visualize_as_dot().unwrap();
+        assert!(dot.starts_with("digraph dataflow {\n"));
+        assert!(dot.ends_with("}\n"));
+        // 每个节点是一个cluster，operator的形状与mermaid图一致
+        assert!(dot.contains("subgraph \"cluster_camera\" {"));
+        assert!(dot.contains("\"camera/camera\" [label=\"camera\", shape=box];"));
+        assert!(dot
+            .contains("\"limiter/limiter\" [label=\"limiter (builtin: batch)\", shape=hexagon];"));
+        assert!(dot.contains("\"runtime/detect\" [label=\"detect\", shape=box];"));
+        // timer、普通的边以及找不到的输入
+        assert!(dot.contains("[label=\"millis/100\", shape=invtrapezium];"));
+        assert!(dot.contains("\"camera/camera\" -> \"limiter/limiter\" [label=\"image as in\"];"));
+        assert!(
+            dot.contains("\"limiter/limiter\" -> \"runtime/detect\" [label=\"out as frames\"];")
+        );
+        assert!(dot.contains("missing [label=\"missing\", shape=cds, color=red];"));
+        assert!(dot.contains("missing -> \"runtime/detect\" [label=\"missing\", color=red];"));
+    }
+}
@@ -0,0 +1,1046 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_yaml::{Mapping, Value};
+
+use super::{
+    descriptor::{Descriptor, Node, NodeId},
+    diagnostic::{Location, SourceMap, Span},
+    format::Format,
+};
+
+/// 指向原始文件中某个位置的错误
+/// include 和模板展开之后，错误仍然指向定义它的文件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SourceError {
+    pub file: PathBuf,
+    pub span: Option<Span>,
+    pub message: String,
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.span {
+            Some(Span { line, column }) => write!(
+                f,
+                "{}:{line}:{column}: {}",
+                self.file.display(),
+                self.message
+            ),
+            None => write!(f, "{}: {}", self.file.display(), self.message),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+/// 读取过的描述文件
+struct SourceFile {
+    text: String,
+    map: SourceMap,
+}
+
+/// 节点定义所在的文件，以及在文件中的路径
+#[derive(Debug, Clone)]
+struct Origin {
+    file: PathBuf,
+    path: Vec<String>,
+}
+
+/// 可以多次实例化的子图
+/// ```yaml
+/// templates:
+///   detector:
+///     params:
+///       threshold: 0.5
+///       model: ~          # 没有默认值，实例必须设置
+///     inputs: [image]
+///     outputs:
+///       boxes: infer/boxes
+///     nodes:
+///       - id: infer
+///         shell: ./detect.sh --model ${model} --threshold ${threshold}
+///         inputs:
+///           image: inputs/image
+///         outputs: [boxes]
+/// ```
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Template {
+    #[serde(default)]
+    #[schemars(with = "BTreeMap<String, serde_json::Value>")]
+    params: BTreeMap<String, Value>,
+    /// 子图的输入，模板中的节点通过 `inputs/<name>` 引用
+    #[serde(default)]
+    inputs: BTreeSet<String>,
+    /// 子图的输出，对应模板中某个节点的输出
+    #[serde(default)]
+    outputs: BTreeMap<String, String>,
+    #[schemars(with = "Vec<Node>")]
+    nodes: Vec<Value>,
+}
+
+/// 模板的实例，实例的id作为展开之后节点id的前缀
+/// ```yaml
+/// nodes:
+///   - id: front
+///     template: detector
+///     params:
+///       model: front.onnx
+///     inputs:
+///       image: camera/front
+/// ```
+/// 其他节点通过 `front/boxes` 引用实例的输出
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Instance {
+    id: String,
+    template: String,
+    #[serde(default)]
+    #[schemars(with = "BTreeMap<String, serde_json::Value>")]
+    params: BTreeMap<String, Value>,
+    #[serde(default)]
+    inputs: BTreeMap<String, String>,
+}
+
+/// 命令行选择的profile以及覆盖的变量
+/// ```yaml
+/// vars:
+///   fps: 10
+///   host: 127.0.0.1
+///   endpoint: tcp/${host}:7447      # 可以引用其他变量，不能成环
+/// deploy:
+///   endpoints: ["${endpoint}"]
+/// profiles:
+///   prod:
+///     vars:
+///       fps: 30
+///     deploy:
+///       log_format: json
+///     nodes:
+///       - id: camera
+///         envs:
+///           DEVICE: /dev/video0
+/// ```
+/// profile 按key合并到描述文件中，节点按id合并，sequence 整个替换
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct Overrides {
+    /// 使用描述文件 `profiles` 中的配置覆盖默认的配置，如 prod
+    #[arg(long, value_name = "PROFILE")]
+    pub profile: Option<String>,
+    /// 覆盖 `vars` 中的变量，可以重复，如 --set fps=30
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_var)]
+    pub vars: Vec<(String, String)>,
+}
+
+/// 解析命令行中的 `KEY=VALUE`
+fn parse_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_owned(), value.to_owned()))
+        }
+        _ => Err(format!("expected `KEY=VALUE`, got `{s}`")),
+    }
+}
+
+/// 展开 `include` 和 `templates` 之后的描述文件
+pub(crate) struct Expanded {
+    root: PathBuf,
+    value: Value,
+    files: BTreeMap<PathBuf, SourceFile>,
+    /// 每个节点在原始文件中的位置
+    origins: BTreeMap<NodeId, Origin>,
+    /// 是否有需要展开的内容，没有时直接从文本反序列化以保留错误的位置
+    expanded: bool,
+}
+
+impl Expanded {
+    /// 读取描述文件，递归读取include的文件并展开模板的实例
+    /// include 的路径相对于声明它的文件
+    /// 然后合并选择的profile，最后将 `vars` 中的变量替换到整个描述文件
+    pub(crate) fn read(path: &Path, overrides: &Overrides) -> Result<Self> {
+        let mut loader = Loader::default();
+        let mut root = loader.load(path, None)?;
+        let root_origin = Origin {
+            file: path.to_owned(),
+            path: vec![],
+        };
+        let expanded = !loader.templates.is_empty()
+            || loader.files.len() > 1
+            || root.contains_key(&"vars".into())
+            || root.contains_key(&"profiles".into());
+        let vars = loader.apply_profile(&mut root, &root_origin, overrides)?;
+
+        let mut nodes = vec![];
+        let mut origins = BTreeMap::new();
+        // 实例的id到实例输出对应的真实输出
+        let mut instance_outputs = BTreeMap::new();
+        for (value, origin) in std::mem::take(&mut loader.nodes) {
+            if value.get("template").is_none() {
+                if let Some(id) = value.get("id").and_then(Value::as_str) {
+                    origins.entry(NodeId::from(id.to_owned())).or_insert(origin);
+                }
+                nodes.push(value);
+                continue;
+            }
+            let (instance, template, template_origin) = loader.instance(value, &origin)?;
+            let prefixed = |id: &str| format!("{}_{id}", instance.id);
+            let locals: BTreeSet<String> = template
+                .nodes
+                .iter()
+                .filter_map(|node| node.get("id").and_then(Value::as_str))
+                .map(str::to_owned)
+                .collect();
+            // 模板中引用本地节点的输出加上前缀
+            let local_mapping = |mapping: &str| match mapping.split_once('/') {
+                Some((source, output)) if locals.contains(source) => {
+                    Some(format!("{}/{output}", prefixed(source)))
+                }
+                _ => None,
+            };
+            let params = instance_params(&instance, &template).map_err(|e| {
+                loader.error(&origin, &[], format!("invalid template instance: {e:#}"))
+            })?;
+            for (index, mut node) in template.nodes.into_iter().enumerate() {
+                let mut node_origin = template_origin.clone();
+                node_origin
+                    .path
+                    .extend(["nodes".to_owned(), index.to_string()]);
+                substitute(&mut node, &params);
+                if let Some(Value::String(id)) = node.get_mut("id") {
+                    *id = prefixed(id);
+                    origins.insert(NodeId::from(id.clone()), node_origin.clone());
+                }
+                map_inputs(&mut node, &mut |mapping| {
+                    if let Some(input) = mapping.strip_prefix("inputs/") {
+                        return match instance.inputs.get(input) {
+                            Some(mapping) => Ok(Some(mapping.clone())),
+                            None => bail!("template has no input `{input}`"),
+                        };
+                    }
+                    Ok(local_mapping(mapping))
+                })
+                .map_err(|e| loader.error(&node_origin, &[], format!("{e:#}")))?;
+                nodes.push(node);
+            }
+            let outputs = template
+                .outputs
+                .iter()
+                .map(|(name, output)| {
+                    let output = local_mapping(output).ok_or_else(|| {
+                        loader.error(
+                            &template_origin,
+                            &["outputs".to_owned(), name.clone()],
+                            format!("output `{name}` must refer to a node of the template"),
+                        )
+                    })?;
+                    Ok((name.clone(), output))
+                })
+                .collect::<Result<BTreeMap<_, _>>>()?;
+            instance_outputs.insert(instance.id, outputs);
+        }
+
+        // 引用模板实例输出的地方替换为实例中真实的输出
+        for node in &mut nodes {
+            map_inputs(node, &mut |mapping| {
+                Ok(mapping.split_once('/').and_then(|(source, output)| {
+                    instance_outputs
+                        .get(source)
+                        .and_then(|outputs| outputs.get(output))
+                        .cloned()
+                }))
+            })?;
+        }
+        root.insert("nodes".into(), Value::Sequence(nodes));
+        let mut value = Value::Mapping(root);
+        substitute(&mut value, &vars);
+        Ok(Self {
+            root: path.to_owned(),
+            value,
+            files: loader.files,
+            origins,
+            expanded,
+        })
+    }
+
+    /// 反序列化为描述文件，出错时指出出错的节点在原始文件中的位置
+    pub(crate) fn descriptor(&self) -> Result<Descriptor> {
+        if !self.expanded {
+            let text = &self.files[&self.root].text;
+            let format = Format::from_path(&self.root);
+            return Descriptor::parse(text, format).map_err(|e| {
+                SourceError {
+                    file: self.root.clone(),
+                    span: format.error_span(text, &e),
+                    message: format!("failed to parse: {}", Format::error_message(&e)),
+                }
+                .into()
+            });
+        }
+        // 经过yaml文本再反序列化，和直接读取yaml文件一样，数字等标量也可以作为字符串
+        let text = serde_yaml::to_string(&self.value).context("failed to serialize descriptor")?;
+        Descriptor::parse(&text, Format::Yaml).map_err(|e| {
+            // 逐个节点反序列化，找到出错的节点
+            let nodes = self.value.get("nodes").and_then(Value::as_sequence);
+            for node in nodes.into_iter().flatten() {
+                if let Err(e) = from_yaml::<Node>(node) {
+                    let id = node.get("id").and_then(Value::as_str).unwrap_or_default();
+                    let origin = self.origins.get(id).cloned().unwrap_or_else(|| Origin {
+                        file: self.root.clone(),
+                        path: vec![],
+                    });
+                    return self
+                        .error(&origin, &[], format!("failed to parse node `{id}`: {e}"))
+                        .into();
+                }
+            }
+            SourceError {
+                file: self.root.clone(),
+                span: None,
+                message: format!("{e:#}"),
+            }
+            .into()
+        })
+    }
+
+    /// 诊断信息指向的元素所在的文件和位置
+    pub(crate) fn resolve(
+        &self,
+        descriptor: &Descriptor,
+        location: &Location,
+    ) -> (PathBuf, Option<Span>) {
+        let mut path = SourceMap::path(descriptor, location);
+        let mut file = self.root.clone();
+        // 节点的路径替换为节点在原始文件中的路径
+        if let (Some("nodes"), Some(index)) = (
+            path.first().map(String::as_str),
+            path.get(1).and_then(|index| index.parse::<usize>().ok()),
+        ) {
+            if let Some(origin) = descriptor
+                .nodes
+                .get(index)
+                .and_then(|node| self.origins.get(&node.id))
+            {
+                path.splice(..2, origin.path.iter().cloned());
+                file = origin.file.clone();
+            }
+        }
+        let span = self
+            .files
+            .get(&file)
+            .and_then(|source| source.map.resolve_path(path));
+        (file, span)
+    }
+
+    /// 读取过的所有文件的内容
+    pub(crate) fn sources(&self) -> impl Iterator<Item = (&PathBuf, &str)> {
+        self.files
+            .iter()
+            .map(|(path, source)| (path, source.text.as_str()))
+    }
+
+    fn error(&self, origin: &Origin, path: &[String], message: String) -> SourceError {
+        error_at(&self.files, origin, path, message)
+    }
+}
+
+/// 递归读取描述文件
+#[derive(Default)]
+struct Loader {
+    files: BTreeMap<PathBuf, SourceFile>,
+    /// 正在读取的文件，用于检测循环include
+    stack: Vec<PathBuf>,
+    /// 已经读取的文件，同一个文件只读取一次
+    loaded: BTreeSet<PathBuf>,
+    templates: BTreeMap<String, (Template, Origin)>,
+    nodes: Vec<(Value, Origin)>,
+}
+
+impl Loader {
+    /// 读取一个文件，返回根文件中除了 `include` 和 `templates` 之外的内容
+    /// included_by 为 include 这个文件的位置，根文件为 None
+    fn load(&mut self, path: &Path, included_by: Option<&Origin>) -> Result<Mapping> {
+        let canonical = path.canonicalize().map_err(|e| match included_by {
+            Some(origin) => self
+                .error(
+                    origin,
+                    &[],
+                    format!("failed to include `{}`: {e}", path.display()),
+                )
+                .into(),
+            None => anyhow::Error::new(e).context("failed to open given file"),
+        })?;
+        if self.stack.contains(&canonical) {
+            let origin = included_by.expect("root file is never included");
+            return Err(self
+                .error(
+                    origin,
+                    &[],
+                    format!("`{}` is included recursively", path.display()),
+                )
+                .into());
+        }
+        if !self.loaded.insert(canonical.clone()) {
+            return Ok(Mapping::new());
+        }
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to open `{}`", path.display()))?;
+        // 每个文件按照自己的扩展名解析，include 的文件可以使用不同的格式
+        let format = Format::from_path(path);
+        let value = format.parse::<Value>(&text).map_err(|e| SourceError {
+            file: path.to_owned(),
+            span: format.error_span(&text, &e),
+            message: format!("failed to parse: {}", Format::error_message(&e)),
+        });
+        self.files.insert(
+            path.to_owned(),
+            SourceFile {
+                map: format.source_map(&text),
+                text,
+            },
+        );
+        let mut mapping = match value? {
+            Value::Mapping(mapping) => mapping,
+            _ => return Err(self.file_error(path, "descriptor must be a mapping").into()),
+        };
+        let origin = |path_in_file: &[&str]| Origin {
+            file: path.to_owned(),
+            path: path_in_file.iter().map(|s| s.to_string()).collect(),
+        };
+
+        self.stack.push(canonical);
+        if let Some(includes) = mapping.remove(&"include".into()) {
+            let includes: Vec<String> = serde_yaml::from_value(includes).map_err(|e| {
+                self.error(
+                    &origin(&["include"]),
+                    &[],
+                    format!("invalid `include`: {e}"),
+                )
+            })?;
+            let dir = path.parent().unwrap_or(Path::new(""));
+            for include in &includes {
+                let included_by = origin(&["include", include]);
+                self.load(&dir.join(include), Some(&included_by))?;
+            }
+        }
+        self.stack.pop();
+
+        if let Some(templates) = mapping.remove(&"templates".into()) {
+            let templates: BTreeMap<String, Value> =
+                serde_yaml::from_value(templates).map_err(|e| {
+                    self.error(
+                        &origin(&["templates"]),
+                        &[],
+                        format!("invalid `templates`: {e}"),
+                    )
+                })?;
+            for (name, template) in templates {
+                let template_origin = origin(&["templates", &name]);
+                let template = serde_yaml::from_value::<Template>(template).map_err(|e| {
+                    self.error(
+                        &template_origin,
+                        &[],
+                        format!("invalid template `{name}`: {e}"),
+                    )
+                })?;
+                if let Some((_, previous)) = self.templates.get(&name) {
+                    let message = format!(
+                        "template `{name}` is already defined in `{}`",
+                        previous.file.display()
+                    );
+                    return Err(self.error(&template_origin, &[], message).into());
+                }
+                self.templates.insert(name, (template, template_origin));
+            }
+        }
+
+        if let Some(Value::Sequence(nodes)) = mapping.get(&"nodes".into()) {
+            for (index, node) in nodes.iter().enumerate() {
+                let index = index.to_string();
+                self.nodes.push((node.clone(), origin(&["nodes", &index])));
+            }
+        }
+        // include的文件只能定义节点和模板
+        if included_by.is_some() {
+            mapping.remove(&"nodes".into());
+            if let Some((key, _)) = mapping.iter().next() {
+                let key = key.as_str().unwrap_or_default().to_owned();
+                let message = format!(
+                    "`{key}` is not allowed in an included file, only `include`, `templates` and `nodes`"
+                );
+                return Err(self.error(&origin(&[&key]), &[], message).into());
+            }
+        }
+        Ok(mapping)
+    }
+
+    /// 合并选择的profile，返回合并之后的变量
+    fn apply_profile(
+        &mut self,
+        root: &mut Mapping,
+        origin: &Origin,
+        overrides: &Overrides,
+    ) -> Result<BTreeMap<String, Value>> {
+        let at = |key: &str| vec![key.to_owned()];
+        let mut vars: BTreeMap<String, Value> = match root.remove(&"vars".into()) {
+            Some(vars) => serde_yaml::from_value(vars)
+                .map_err(|e| self.error(origin, &at("vars"), format!("invalid `vars`: {e}")))?,
+            None => BTreeMap::new(),
+        };
+        let profiles: BTreeMap<String, Value> = match root.remove(&"profiles".into()) {
+            Some(profiles) => serde_yaml::from_value(profiles).map_err(|e| {
+                self.error(origin, &at("profiles"), format!("invalid `profiles`: {e}"))
+            })?,
+            None => BTreeMap::new(),
+        };
+        if let Some(name) = &overrides.profile {
+            let profile = profiles.get(name).cloned().ok_or_else(|| {
+                let names: Vec<_> = profiles.keys().collect();
+                anyhow!("unknown profile `{name}`, available profiles: {names:?}")
+            })?;
+            let path = ["profiles".to_owned(), name.clone()];
+            let Value::Mapping(mut profile) = profile else {
+                bail!(self.error(origin, &path, format!("profile `{name}` must be a mapping")));
+            };
+            if let Some(profile_vars) = profile.remove(&"vars".into()) {
+                let profile_vars: BTreeMap<String, Value> = serde_yaml::from_value(profile_vars)
+                    .map_err(|e| {
+                        self.error(origin, &path, format!("invalid `vars` of profile: {e}"))
+                    })?;
+                vars.extend(profile_vars);
+            }
+            // 节点按id合并到对应的节点中
+            if let Some(nodes) = profile.remove(&"nodes".into()) {
+                let nodes: Vec<Value> = serde_yaml::from_value(nodes).map_err(|e| {
+                    self.error(origin, &path, format!("invalid `nodes` of profile: {e}"))
+                })?;
+                for overlay in nodes {
+                    let id = overlay.get("id").cloned();
+                    let index = self
+                        .nodes
+                        .iter()
+                        .position(|(node, _)| id.is_some() && node.get("id") == id.as_ref())
+                        .ok_or_else(|| {
+                            let id = id.as_ref().and_then(Value::as_str).unwrap_or_default();
+                            self.error(origin, &path, format!("profile has unknown node `{id}`"))
+                        })?;
+                    merge(&mut self.nodes[index].0, overlay);
+                }
+            }
+            for (key, value) in profile {
+                match root.get_mut(&key) {
+                    Some(base) => merge(base, value),
+                    None => {
+                        root.insert(key, value);
+                    }
+                }
+            }
+        }
+        for (name, value) in &overrides.vars {
+            if !vars.contains_key(name) {
+                bail!("unknown var `{name}`, declare it in `vars` first");
+            }
+            // 值按yaml解析，保留数字和布尔值的类型
+            let value = match value.is_empty() {
+                true => Value::String(String::new()),
+                false => serde_yaml::from_str(value).unwrap_or_else(|_| value.as_str().into()),
+            };
+            vars.insert(name.clone(), value);
+        }
+        // 变量的值中可以引用其他变量，先展开变量之间的引用
+        resolve_vars(&vars).map_err(|e| self.error(origin, &at("vars"), e.to_string()).into())
+    }
+
+    /// 解析模板的实例
+    fn instance(&self, value: Value, origin: &Origin) -> Result<(Instance, Template, Origin)> {
+        let instance = serde_yaml::from_value::<Instance>(value)
+            .map_err(|e| self.error(origin, &[], format!("invalid template instance: {e}")))?;
+        let (template, template_origin) =
+            self.templates.get(&instance.template).ok_or_else(|| {
+                self.error(
+                    origin,
+                    &["template".to_owned()],
+                    format!("unknown template `{}`", instance.template),
+                )
+            })?;
+        Ok((instance, template.clone(), template_origin.clone()))
+    }
+
+    fn error(&self, origin: &Origin, path: &[String], message: String) -> SourceError {
+        error_at(&self.files, origin, path, message)
+    }
+
+    fn file_error(&self, file: &Path, message: &str) -> SourceError {
+        SourceError {
+            file: file.to_owned(),
+            span: None,
+            message: message.to_owned(),
+        }
+    }
+}
+
+/// 构造指向原始文件中某个元素的错误
+fn error_at(
+    files: &BTreeMap<PathBuf, SourceFile>,
+    origin: &Origin,
+    path: &[String],
+    message: String,
+) -> SourceError {
+    let mut full_path = origin.path.clone();
+    full_path.extend(path.iter().cloned());
+    SourceError {
+        file: origin.file.clone(),
+        span: files
+            .get(&origin.file)
+            .and_then(|source| source.map.resolve_path(full_path)),
+        message,
+    }
+}
+
+/// 将 overlay 合并到 base 中，mapping 按key递归合并，其他的值直接替换
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(base) => merge(base, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// 经过yaml文本反序列化
+fn from_yaml<T: DeserializeOwned>(value: &Value) -> Result<T, serde_yaml::Error> {
+    serde_yaml::from_str(&serde_yaml::to_string(value)?)
+}
+
+/// 合并实例的参数和模板参数的默认值，默认值为空的参数必须设置
+fn instance_params(instance: &Instance, template: &Template) -> Result<BTreeMap<String, Value>> {
+    let mut params = template.params.clone();
+    for (name, value) in &instance.params {
+        match params.get_mut(name) {
+            Some(param) => *param = value.clone(),
+            None => bail!("template `{}` has no param `{name}`", instance.template),
+        }
+    }
+    if let Some((name, _)) = params.iter().find(|(_, value)| value.is_null()) {
+        bail!(
+            "param `{name}` of template `{}` is required",
+            instance.template
+        );
+    }
+    let declared: BTreeSet<_> = instance.inputs.keys().cloned().collect();
+    if declared != template.inputs {
+        bail!(
+            "inputs of template `{}` are {:?}, got {:?}",
+            instance.template,
+            template.inputs,
+            declared
+        );
+    }
+    Ok(params)
+}
+
+/// 替换字符串中的 `${param}`
+/// 整个字符串就是一个参数时替换为参数的值，保留参数的类型
+/// 不是参数的 `${VAR}` 原样保留，之后作为环境变量展开
+pub(crate) fn substitute(value: &mut Value, params: &BTreeMap<String, Value>) {
+    match value {
+        Value::String(text) => {
+            if let Some(param) = text
+                .strip_prefix("${")
+                .and_then(|rest| rest.strip_suffix('}'))
+                .and_then(|name| params.get(name))
+            {
+                *value = param.clone();
+                return;
+            }
+            *text = replace_refs(text, &mut |name| params.get(name).map(scalar_string));
+        }
+        Value::Sequence(values) => values.iter_mut().for_each(|v| substitute(v, params)),
+        Value::Mapping(mapping) => mapping.iter_mut().for_each(|(_, v)| substitute(v, params)),
+        _ => {}
+    }
+}
+
+/// 展开变量之间的引用，如 `endpoint: "tcp/${host}:7447"`，引用成环时返回错误
+fn resolve_vars(vars: &BTreeMap<String, Value>) -> Result<BTreeMap<String, Value>> {
+    let mut resolved = BTreeMap::new();
+    for name in vars.keys() {
+        resolve_var(name, vars, &mut resolved, &mut vec![])?;
+    }
+    Ok(resolved)
+}
+
+/// 先展开 name 引用的变量，stack 为正在展开的变量
+fn resolve_var(
+    name: &str,
+    vars: &BTreeMap<String, Value>,
+    resolved: &mut BTreeMap<String, Value>,
+    stack: &mut Vec<String>,
+) -> Result<()> {
+    if resolved.contains_key(name) {
+        return Ok(());
+    }
+    if let Some(start) = stack.iter().position(|var| var == name) {
+        let cycle: Vec<_> = stack[start..]
+            .iter()
+            .map(String::as_str)
+            .chain([name])
+            .collect();
+        bail!("vars reference each other: {}", cycle.join(" -> "));
+    }
+    let mut value = vars[name].clone();
+    stack.push(name.to_owned());
+    for var in vars.keys().filter(|var| references(&value, var)) {
+        resolve_var(var, vars, resolved, stack)?;
+    }
+    stack.pop();
+    substitute(&mut value, resolved);
+    resolved.insert(name.to_owned(), value);
+    Ok(())
+}
+
+/// value 中是否引用了变量 name
+fn references(value: &Value, name: &str) -> bool {
+    match value {
+        Value::String(text) => {
+            let mut found = false;
+            replace_refs(text, &mut |var| {
+                found |= var == name;
+                None
+            });
+            found
+        }
+        Value::Sequence(values) => values.iter().any(|value| references(value, name)),
+        Value::Mapping(mapping) => mapping.iter().any(|(_, value)| references(value, name)),
+        _ => false,
+    }
+}
+
+/// 替换字符串中的 `${name}`，f 返回 None 时保持原样
+/// `$${` 是转义的 `${`，原样保留，由启动时的 expand_vars 还原
+fn replace_refs(text: &str, f: &mut dyn FnMut(&str) -> Option<String>) -> String {
+    let mut replaced = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        replaced.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        if let Some(after) = tail.strip_prefix("$${") {
+            replaced.push_str("$${");
+            rest = after;
+        } else if let Some((name, after)) = tail
+            .strip_prefix("${")
+            .and_then(|tail| tail.split_once('}'))
+        {
+            match f(name) {
+                Some(value) => replaced.push_str(&value),
+                None => replaced.push_str(&tail[..name.len() + 3]),
+            }
+            rest = after;
+        } else {
+            replaced.push('$');
+            rest = &tail[1..];
+        }
+    }
+    replaced.push_str(rest);
+    replaced
+}
+
+/// 嵌入到字符串中时参数的写法
+fn scalar_string(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        value => serde_yaml::to_string(value)
+            .map(|text| text.trim_start_matches("---").trim().to_owned())
+            .unwrap_or_default(),
+    }
+}
+
+/// 修改节点和节点中每个operator的输入映射，f 返回 None 时保持不变
+fn map_inputs(node: &mut Value, f: &mut dyn FnMut(&str) -> Result<Option<String>>) -> Result<()> {
+    let mut inputs = vec![];
+    for (key, value) in node
+        .as_mapping_mut()
+        .into_iter()
+        .flat_map(|node| node.iter_mut())
+    {
+        match (key.as_str(), value) {
+            (Some("inputs"), value) => inputs.push(value),
+            (Some("operators"), Value::Sequence(operators)) => {
+                inputs.extend(operators.iter_mut().filter_map(|op| op.get_mut("inputs")))
+            }
+            _ => {}
+        }
+    }
+    for input in inputs.into_iter().filter_map(Value::as_mapping_mut) {
+        for (_, input) in input.iter_mut() {
+            // 输入可以是映射本身，也可以是带有 source 的结构
+            let mapping = match input {
+                Value::Mapping(options) => options.get_mut(&"source".into()),
+                input => Some(input),
+            };
+            if let Some(Value::String(mapping)) = mapping {
+                if let Some(mapped) = f(mapping)? {
+                    *mapping = mapped;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptor::diagnostic::Location;
+
+    const TEMPLATES: &str = r#"
+templates:
+  detector:
+    params:
+      threshold: 0.5
+      model: ~
+    inputs: [image]
+    outputs:
+      boxes: infer/boxes
+    nodes:
+      - id: resize
+        shell: ./resize.sh
+        inputs:
+          image: inputs/image
+        outputs: [small]
+      - id: infer
+        shell: ./infer.sh --threshold ${threshold}
+        envs:
+          MODEL: ${model}
+        inputs:
+          small: resize/small
+        outputs: [boxes]
+"#;
+
+    const DATAFLOW: &str = r#"version: 1.0
+include:
+  - common/detector.yaml
+nodes:
+  - id: camera
+    shell: ./camera.sh
+    outputs: [front, back]
+  - id: front
+    template: detector
+    params:
+      model: front.onnx
+    inputs:
+      image: camera/front
+  - id: back
+    template: detector
+    params:
+      model: back.onnx
+      threshold: 0.8
+    inputs:
+      image: camera/back
+  - id: fusion
+    shell: ./fusion.sh
+    inputs:
+      front: front/boxes
+      back:
+        source: back/boxes
+        queue_size: 1
+"#;
+
+    fn write_dataflow(dataflow: &str, template: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("dataflow-expand-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("common")).unwrap();
+        std::fs::write(dir.join("common/detector.yaml"), template).unwrap();
+        std::fs::write(dir.join("dataflow.yaml"), dataflow).unwrap();
+        (dir.clone(), dir.join("dataflow.yaml"))
+    }
+
+    #[test]
+    fn test_expand_templates() {
+        let (dir, path) = write_dataflow(DATAFLOW, TEMPLATES);
+        let expanded = Expanded::read(&path, &Overrides::default()).unwrap();
+        let descriptor = expanded.descriptor().unwrap();
+        let ids: Vec<_> = descriptor.nodes.iter().map(|n| n.id.to_string()).collect();
+        assert_eq!(
+            ids,
+            [
+                "camera",
+                "front_resize",
+                "front_infer",
+                "back_resize",
+                "back_infer",
+                "fusion"
+            ]
+        );
+
+        let resolved = descriptor.resolve_node_defaults();
+        let node = |id: &str| resolved.iter().find(|n| n.id.as_str() == id).unwrap();
+        let input = |id: &str, input: &str| {
+            node(id).kind.operators[0].config.run_config.inputs[input]
+                .mapping
+                .to_string()
+        };
+        assert_eq!(input("front_resize", "image"), "camera/camera/front");
+        assert_eq!(
+            input("back_infer", "small"),
+            "back_resize/back_resize/small"
+        );
+        assert_eq!(input("fusion", "front"), "front_infer/front_infer/boxes");
+        assert_eq!(input("fusion", "back"), "back_infer/back_infer/boxes");
+        let source = |id: &str| node(id).kind.operators[0].config.source.to_string();
+        assert_eq!(source("front_infer"), "./infer.sh --threshold 0.5");
+        assert_eq!(source("back_infer"), "./infer.sh --threshold 0.8");
+        let model = node("back_infer").envs.as_ref().unwrap()["MODEL"].to_string();
+        assert_eq!(model, "back.onnx");
+
+        // 模板中的节点指向模板定义的位置
+        let (file, span) = expanded.resolve(
+            &descriptor,
+            &Location::Node {
+                node: NodeId::from("back_infer".to_owned()),
+            },
+        );
+        assert_eq!(file, dir.join("common/detector.yaml"));
+        assert_eq!(span.map(|s| s.line), Some(16));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_expand_errors() {
+        // 缺少必须的参数，错误指向实例
+        let dataflow = r#"version: 1.0
+include: [common/detector.yaml]
+nodes:
+  - id: camera
+    shell: ./camera.sh
+    outputs: [front]
+  - id: front
+    template: detector
+    inputs:
+      image: camera/front
+"#;
+        let (dir, path) = write_dataflow(dataflow, TEMPLATES);
+        let error = Expanded::read(&path, &Overrides::default()).err().unwrap();
+        let error = error.downcast_ref::<SourceError>().unwrap();
+        assert_eq!(error.file, path);
+        assert_eq!(error.span.map(|s| s.line), Some(7));
+        assert!(error.message.contains("param `model`"), "{}", error.message);
+
+        // include的文件中的解析错误指向该文件
+        std::fs::write(dir.join("common/detector.yaml"), "templates: [\n").unwrap();
+        let error = Expanded::read(&path, &Overrides::default()).err().unwrap();
+        let error = error.downcast_ref::<SourceError>().unwrap();
+        assert_eq!(error.file, dir.join("common/detector.yaml"));
+
+        // 循环include
+        std::fs::write(
+            dir.join("common/detector.yaml"),
+            "include: [../dataflow.yaml]",
+        )
+        .unwrap();
+        let error = Expanded::read(&path, &Overrides::default()).err().unwrap();
+        assert!(error.to_string().contains("recursively"), "{error}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_profiles_and_vars() {
+        let dataflow = r#"version: 1.0
+vars:
+  host: 127.0.0.1
+  port: 7447
+  rate: 100
+  endpoint: tcp/${host}:${port}
+deploy:
+  endpoints: ["${endpoint}"]
+profiles:
+  prod:
+    vars:
+      port: 8000
+    deploy:
+      log_format: json
+    nodes:
+      - id: camera
+        envs:
+          DEVICE: /dev/video0
+nodes:
+  - id: camera
+    shell: ./camera.sh --rate ${rate} --home ${HOME}
+    args: "--port $${port}"
+    envs:
+      RATE: ${rate}
+    inputs:
+      tick: dataflow/timer/millis/${rate}
+"#;
+        let (dir, path) = write_dataflow(dataflow, "");
+        let read = |overrides: &Overrides| {
+            Expanded::read(&path, overrides)
+                .and_then(|expanded| expanded.descriptor())
+                .unwrap()
+        };
+        let descriptor = read(&Overrides::default());
+        assert_eq!(
+            descriptor.deploy.endpoints.clone().unwrap(),
+            ["tcp/127.0.0.1:7447".to_owned()]
+        );
+        assert!(descriptor.nodes[0].envs.is_some());
+
+        let descriptor = read(&Overrides {
+            profile: Some("prod".to_owned()),
+            vars: vec![("rate".to_owned(), "20".to_owned())],
+        });
+        assert_eq!(
+            descriptor.deploy.endpoints.clone().unwrap(),
+            ["tcp/127.0.0.1:8000".to_owned()]
+        );
+        assert!(descriptor.deploy.log_format.is_some());
+        let node = &descriptor.resolve_node_defaults()[0];
+        let envs = node.envs.as_ref().unwrap();
+        assert_eq!(envs["RATE"].to_string(), "20");
+        assert_eq!(envs["DEVICE"].to_string(), "/dev/video0");
+        let config = &node.kind.operators[0].config;
+        // 不是变量的 `${HOME}` 保持不变
+        assert_eq!(
+            config.source.to_string(),
+            "./camera.sh --rate 20 --home ${HOME}"
+        );
+        // `$${port}` 是转义，最终得到字面的 `${port}`
+        let argv = config.args.as_ref().unwrap().argv(&|_| None).unwrap();
+        assert_eq!(argv, ["--port", "${port}"]);
+        assert_eq!(
+            config.run_config.inputs["tick"].mapping.to_string(),
+            "dataflow/timer/millis/20"
+        );
+
+        let error = Expanded::read(
+            &path,
+            &Overrides {
+                profile: Some("staging".to_owned()),
+                vars: vec![],
+            },
+        )
+        .err()
+        .unwrap();
+        assert!(error.to_string().contains("unknown profile"), "{error}");
+
+        // 变量之间的引用成环
+        let dataflow = "version: 1.0\nvars:\n  a: ${b}/x\n  b: [\"${c}\"]\n  c: ${a}\nnodes: []\n";
+        std::fs::write(&path, dataflow).unwrap();
+        let error = Expanded::read(&path, &Overrides::default()).err().unwrap();
+        assert!(error.to_string().contains("a -> b -> c -> a"), "{error}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
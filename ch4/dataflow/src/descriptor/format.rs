@@ -0,0 +1,156 @@
+use std::{fmt, path::Path};
+
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::diagnostic::{SourceMap, Span};
+
+/// 描述文件的格式，由文件的扩展名决定
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// `.yaml`、`.yml`，以及其他未知的扩展名
+    #[default]
+    Yaml,
+    /// `.json`
+    Json,
+    /// `.toml`
+    Toml,
+}
+
+impl Format {
+    /// 根据文件的扩展名选择格式，无法识别的扩展名按照yaml处理
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("json") => Format::Json,
+            Some("toml") => Format::Toml,
+            _ => Format::Yaml,
+        }
+    }
+
+    /// 从文本反序列化
+    pub fn parse<T: DeserializeOwned>(self, text: &str) -> Result<T> {
+        let value = match self {
+            Format::Yaml => serde_yaml::from_str(text)?,
+            Format::Json => serde_json::from_str(text)?,
+            Format::Toml => toml::from_str(text)?,
+        };
+        Ok(value)
+    }
+
+    /// 序列化为文本
+    pub fn to_string<T: Serialize>(self, value: &T) -> Result<String> {
+        let text: Result<String, anyhow::Error> = match self {
+            Format::Yaml => serde_yaml::to_string(value).map_err(Into::into),
+            Format::Json => serde_json::to_string_pretty(value)
+                .map(|json| json + "\n")
+                .map_err(Into::into),
+            Format::Toml => toml::to_string_pretty(value).map_err(Into::into),
+        };
+        text.with_context(|| format!("failed to serialize as {self}"))
+    }
+
+    /// parse 返回的错误在文本中的位置
+    pub(crate) fn error_span(self, text: &str, error: &anyhow::Error) -> Option<Span> {
+        if let Some(error) = error.downcast_ref::<serde_yaml::Error>() {
+            return error.location().map(|location| Span {
+                line: location.line(),
+                column: location.column(),
+            });
+        }
+        if let Some(error) = error.downcast_ref::<serde_json::Error>() {
+            return (error.line() > 0).then(|| Span {
+                line: error.line(),
+                column: error.column(),
+            });
+        }
+        let index = error.downcast_ref::<toml::de::Error>()?.span()?.start;
+        let before = text.get(..index)?;
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        Some(Span {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        })
+    }
+
+    /// parse 返回的错误的描述，toml的错误中本身带有出错的代码片段，这里去掉
+    pub(crate) fn error_message(error: &anyhow::Error) -> String {
+        match error.downcast_ref::<toml::de::Error>() {
+            Some(error) => error.message().trim().to_owned(),
+            None => error.root_cause().to_string(),
+        }
+    }
+
+    /// 元素位置的索引，json是yaml的子集可以直接使用yaml的解析器，toml没有位置信息
+    pub(crate) fn source_map(self, text: &str) -> SourceMap {
+        match self {
+            Format::Yaml | Format::Json => SourceMap::parse(text),
+            Format::Toml => SourceMap::default(),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Yaml => f.write_str("yaml"),
+            Format::Json => f.write_str("json"),
+            Format::Toml => f.write_str("toml"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{descriptor::descriptor::Descriptor, harness::fixture};
+
+    /// 序列化之后统一转为json比较
+    fn normalize(descriptor: &Descriptor) -> serde_json::Value {
+        serde_json::to_value(descriptor).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let descriptor = Descriptor::blocking_read(&fixture("camera.yml")).unwrap();
+        let expected = normalize(&descriptor);
+        for format in [Format::Yaml, Format::Json, Format::Toml] {
+            let text = format.to_string(&descriptor).unwrap();
+            let parsed: Descriptor = format.parse(&text).unwrap();
+            assert_eq!(normalize(&parsed), expected, "{format}:\n{text}");
+            // 再转换为其他格式
+            for other in [Format::Yaml, Format::Json, Format::Toml] {
+                let converted = other.to_string(&parsed).unwrap();
+                let parsed: Descriptor = other.parse(&converted).unwrap();
+                assert_eq!(normalize(&parsed), expected, "{format} -> {other}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_path_and_error_span() {
+        assert_eq!(Format::from_path(Path::new("a.yml")), Format::Yaml);
+        assert_eq!(Format::from_path(Path::new("a.JSON")), Format::Json);
+        assert_eq!(Format::from_path(Path::new("dir/a.toml")), Format::Toml);
+        assert_eq!(Format::from_path(Path::new("dataflow")), Format::Yaml);
+
+        let text = "{\n  \"version\": \"1.0\",\n  \"nodes\": [,]\n}";
+        let error = Format::Json.parse::<Descriptor>(text).unwrap_err();
+        let span = Format::Json.error_span(text, &error);
+        assert_eq!(
+            span,
+            Some(Span {
+                line: 3,
+                column: 13
+            })
+        );
+
+        let text = "version = \"1.0\"\nnodes = [\n  { id = },\n]";
+        let error = Format::Toml.parse::<Descriptor>(text).unwrap_err();
+        let span = Format::Toml.error_span(text, &error).unwrap();
+        assert_eq!(span.line, 3);
+    }
+}
@@ -0,0 +1,283 @@
+use super::descriptor::{
+    DataId, Deploy, InputMapping, NodeId, NormalNode, OperatorId, Timer, UserInputMapping,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// 导出的图结构的版本号
+/// 对导出结构做不兼容的修改时需要增加该版本号
+pub const GRAPH_SCHEMA_VERSION: u32 = 2;
+
+/// 完全解析之后的数据流图，用于以json的形式提供给外部工具
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataflowGraph {
+    /// 导出结构的版本号
+    pub schema_version: u32,
+    /// 描述文件的版本号
+    pub version: String,
+    /// 所有的节点
+    pub nodes: Vec<GraphNode>,
+    /// 所有的边，从生产者的output指向消费者的input
+    pub edges: Vec<GraphEdge>,
+    /// 数据流中用到的所有timer
+    pub timers: Vec<GraphTimer>,
+}
+
+/// 图中的节点，deploy 为合并默认值之后的部署信息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphNode {
+    pub id: NodeId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub deploy: Deploy,
+    pub operators: Vec<GraphOperator>,
+}
+
+/// 图中的operator
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphOperator {
+    pub id: OperatorId,
+    /// operator来源的类型，如 exe_target、shell
+    pub source_kind: String,
+    /// operator来源的内容
+    pub source: String,
+    pub inputs: Vec<DataId>,
+    pub outputs: Vec<DataId>,
+}
+
+/// 边的生产者一端
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphProducer {
+    pub node: NodeId,
+    pub operator: OperatorId,
+    pub output: DataId,
+}
+
+/// 边的消费者一端
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphConsumer {
+    pub node: NodeId,
+    pub operator: OperatorId,
+    pub input: DataId,
+}
+
+/// 图中的边
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphEdge {
+    pub producer: GraphProducer,
+    pub consumer: GraphConsumer,
+    pub queue_size: usize,
+    /// 生产者是否能在数据流中找到，为false时表示输入映射到了不存在的output
+    pub resolved: bool,
+}
+
+/// 图中的timer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphTimer {
+    /// timer 的完整id，如 dataflow/timer/millis/100
+    pub id: String,
+    /// timer 的类型：interval、once 或 cron
+    pub kind: String,
+    /// 周期 timer 的间隔，单位为微秒
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_micros: Option<u128>,
+    /// 周期 timer 对齐时的偏移，单位为微秒
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset_micros: Option<u128>,
+    /// 一次性 timer 的延迟，单位为微秒
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delay_micros: Option<u128>,
+    /// cron 表达式
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+}
+
+impl GraphTimer {
+    fn new(timer: Timer) -> Self {
+        let mut graph_timer = GraphTimer {
+            id: InputMapping::Timer(timer.clone()).to_string(),
+            kind: String::new(),
+            interval_micros: None,
+            offset_micros: None,
+            delay_micros: None,
+            cron: None,
+        };
+        match timer {
+            Timer::Interval { interval, offset } => {
+                graph_timer.kind = "interval".to_owned();
+                graph_timer.interval_micros = Some(interval.as_micros());
+                graph_timer.offset_micros = offset.map(|offset| offset.as_micros());
+            }
+            Timer::Once { delay } => {
+                graph_timer.kind = "once".to_owned();
+                graph_timer.delay_micros = Some(delay.as_micros());
+            }
+            Timer::Cron(schedule) => {
+                graph_timer.kind = "cron".to_owned();
+                graph_timer.cron = Some(schedule.expr().to_owned());
+            }
+        }
+        graph_timer
+    }
+}
+
+/// timer 作为生产者时的节点和operator id
+const TIMER_PRODUCER_NODE: &str = "dataflow";
+const TIMER_PRODUCER_OPERATOR: &str = "timer";
+
+/// 根据处理过默认值的节点构造数据流图
+pub(crate) fn export_nodes(version: &str, nodes: &[NormalNode]) -> DataflowGraph {
+    let all_nodes: BTreeMap<_, _> = nodes.iter().map(|n| (&n.id, n)).collect();
+    let mut edges = vec![];
+    for node in nodes {
+        for operator in &node.kind.operators {
+            for (input_id, input) in &operator.config.run_config.inputs {
+                let consumer = GraphConsumer {
+                    node: node.id.clone(),
+                    operator: operator.id.clone(),
+                    input: input_id.clone(),
+                };
+                let (producer, resolved) = match &input.mapping {
+                    InputMapping::Timer(timer) => (
+                        GraphProducer {
+                            node: NodeId::from(TIMER_PRODUCER_NODE.to_owned()),
+                            operator: OperatorId::from(TIMER_PRODUCER_OPERATOR.to_owned()),
+                            output: timer.output_id(),
+                        },
+                        true,
+                    ),
+                    InputMapping::User(mapping) => resolve_producer(mapping, &all_nodes),
+                };
+                edges.push(GraphEdge {
+                    producer,
+                    consumer,
+                    queue_size: input.queue_size,
+                    resolved,
+                });
+            }
+        }
+    }
+
+    DataflowGraph {
+        schema_version: GRAPH_SCHEMA_VERSION,
+        version: version.to_owned(),
+        nodes: nodes
+            .iter()
+            .map(|node| GraphNode {
+                id: node.id.clone(),
+                name: node.name.clone(),
+                description: node.description.clone(),
+                deploy: node.deploy.clone(),
+                operators: node
+                    .kind
+                    .operators
+                    .iter()
+                    .map(|operator| GraphOperator {
+                        id: operator.id.clone(),
+                        source_kind: operator.config.source.kind().to_owned(),
+                        source: operator.config.source.to_string(),
+                        inputs: operator.config.run_config.inputs.keys().cloned().collect(),
+                        outputs: operator.config.run_config.outputs.iter().cloned().collect(),
+                    })
+                    .collect(),
+            })
+            .collect(),
+        edges,
+        timers: NormalNode::collect_timers_from_nodes(nodes)
+            .into_iter()
+            .map(GraphTimer::new)
+            .collect(),
+    }
+}
+
+/// 解析用户输入映射的生产者，返回生产者以及是否找到了该生产者
+fn resolve_producer(
+    mapping: &UserInputMapping,
+    nodes: &BTreeMap<&NodeId, &NormalNode>,
+) -> (GraphProducer, bool) {
+    let UserInputMapping { source, output } = mapping;
+    // 经过resolve之后，output 的格式为 operator_id/output
+    let (operator_id, output) = output.split_once('/').unwrap_or(("", output));
+    let resolved = nodes
+        .get(source)
+        .and_then(|node| {
+            node.kind
+                .operators
+                .iter()
+                .find(|o| o.id.as_str() == operator_id)
+        })
+        .map(|operator| operator.config.run_config.outputs.contains(output))
+        .unwrap_or(false);
+    (
+        GraphProducer {
+            node: source.clone(),
+            operator: OperatorId::from(operator_id.to_owned()),
+            output: DataId::from(output.to_owned()),
+        },
+        resolved,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptor::descriptor::Descriptor;
+
+    const DATAFLOW: &str = r#"
+version: 1.0
+deploy:
+  endpoints:
+    - tcp/127.0.0.1:7447
+nodes:
+  - id: source
+    shell: ./source.sh
+    inputs:
+      tick: dataflow/timer/millis/100
+    outputs:
+      - image
+  - id: runtime
+    operators:
+      - id: detect
+        exe_target: ./detect
+        inputs:
+          image:
+            source: source/image
+            queue_size: 2
+          missing: nowhere/bbox
+        outputs:
+          - bbox
+"#;
+
+    #[test]
+    fn test_export_nodes() {
+        let descriptor: Descriptor = serde_yaml::from_str(DATAFLOW).unwrap();
+        let graph = descriptor.export_graph();
+        assert_eq!(graph.schema_version, GRAPH_SCHEMA_VERSION);
+        assert_eq!(graph.nodes.len(), 2);
+        assert_eq!(graph.nodes[1].operators[0].source_kind, "exe_target");
+        assert_eq!(graph.timers.len(), 1);
+        assert_eq!(graph.timers[0].id, "dataflow/timer/millis/100");
+        assert_eq!(graph.timers[0].kind, "interval");
+        assert_eq!(graph.timers[0].interval_micros, Some(100_000));
+
+        let edge = graph
+            .edges
+            .iter()
+            .find(|e| e.consumer.input.as_str() == "image")
+            .unwrap();
+        assert_eq!(edge.producer.node.as_str(), "source");
+        assert_eq!(edge.producer.operator.as_str(), "source");
+        assert_eq!(edge.producer.output.as_str(), "image");
+        assert_eq!(edge.queue_size, 2);
+        assert!(edge.resolved);
+
+        let missing = graph
+            .edges
+            .iter()
+            .find(|e| e.consumer.input.as_str() == "missing")
+            .unwrap();
+        assert!(!missing.resolved);
+    }
+}
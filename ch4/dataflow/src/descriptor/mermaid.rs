@@ -1,5 +1,5 @@
 use super::descriptor::{
-    DataId, FormattedDuration, Input, InputMapping, NodeId, NormalNode, NormalOperatorDefinition,
+    DataId, Input, InputMapping, NodeId, NormalNode, NormalOperatorDefinition, OperatorSource,
     UserInputMapping,
 };
 use std::{
@@ -23,9 +23,14 @@ pub(crate) fn visualize_nodes(nodes: &[NormalNode]) -> String {
     if !dataflow_timers.is_empty() {
         writeln!(flowchart, "subgraph ___dataflow___ [dataflow]").unwrap();
         writeln!(flowchart, "  subgraph ___timer_timer___ [timer]").unwrap();
-        for interval in dataflow_timers {
-            let duration = FormattedDuration(interval);
-            writeln!(flowchart, "    dataflow/timer/{duration}[\\{duration}/]").unwrap();
+        for timer in dataflow_timers {
+            writeln!(
+                flowchart,
+                "    dataflow/timer/{}[\\{}/]",
+                timer.output_id(),
+                timer.label()
+            )
+            .unwrap();
         }
         flowchart.push_str("  end\n");
         flowchart.push_str("end\n");
@@ -53,7 +58,14 @@ fn visualize_operators(
     writeln!(flowchart, "subgraph {node_id}").unwrap();
     for operator in operators {
         let operator_id = &operator.id;
-        if operator.config.run_config.inputs.is_empty() {
+        if let OperatorSource::Builtin(name) = &operator.config.source {
+            // builtin operator，使用六边形并标注内置的类型
+            writeln!(
+                flowchart,
+                "  {node_id}/{operator_id}{{{{\"{operator_id}<br>builtin: {name}\"}}}}"
+            )
+            .unwrap();
+        } else if operator.config.run_config.inputs.is_empty() {
             // source operator
             writeln!(flowchart, "  {node_id}/{operator_id}[\\{operator_id}/]").unwrap();
         } else if operator.config.run_config.outputs.is_empty() {
@@ -98,8 +110,13 @@ fn visualize_operator_inputs(
     for (input_id, input) in inputs {
         match &input.mapping {
             // 对于时间类型的输入，将timmer 作为 source
-            mapping @ InputMapping::Timer { .. } => {
-                writeln!(flowchart, "  {} -- {input_id} --> {target}", mapping).unwrap();
+            mapping @ InputMapping::Timer(_) => {
+                writeln!(
+                    flowchart,
+                    "  {} -- {input_id} --> {target}",
+                    mapping.topic()
+                )
+                .unwrap();
             }
             InputMapping::User(mapping) => {
                 // 自定义的mapping直接调用此函数
@@ -1,6 +1,17 @@
+mod args;
+pub mod builtin;
 #[warn(dead_code)]
+pub mod check;
+pub mod cron;
 pub mod descriptor;
+pub mod diagnostic;
+mod dot;
+pub mod expand;
+pub mod format;
+pub mod graph;
 mod mermaid;
+pub mod schema;
+pub mod topology;
 pub mod visualize;
 pub mod validate;
 
@@ -0,0 +1,113 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+use schemars::gen::SchemaSettings;
+use serde_json::{json, Value};
+
+use super::{
+    descriptor::Descriptor,
+    expand::{Instance, Template},
+};
+
+/// 输出描述文件的JSON Schema，用于编辑器的自动补全和校验
+/// output 为 None 时输出到标准输出
+pub fn schema(output: Option<PathBuf>) -> Result<()> {
+    let text = serde_json::to_string_pretty(&descriptor_schema())? + "\n";
+    match output {
+        Some(output) => fs::write(&output, text)
+            .with_context(|| format!("failed to write schema to `{}`", output.display())),
+        None => {
+            print!("{text}");
+            Ok(())
+        }
+    }
+}
+
+/// 描述文件的JSON Schema
+/// 除了 `Descriptor` 本身，还包括展开之前才有的 `include`、`templates`、`vars` 和 `profiles`
+pub(crate) fn descriptor_schema() -> Value {
+    let mut gen = SchemaSettings::draft07().into_generator();
+    let root = gen.root_schema_for::<Descriptor>();
+    let template = gen.subschema_for::<Template>();
+    let instance = gen.subschema_for::<Instance>();
+    let mut schema = serde_json::to_value(root).expect("schema is always valid json");
+    for (name, definition) in gen.take_definitions() {
+        schema["definitions"][name] = json!(definition);
+    }
+
+    let node = schema["properties"]["nodes"]["items"].take();
+    schema["properties"]["nodes"]["items"] = json!({ "anyOf": [node, instance] });
+    let properties = &mut schema["properties"];
+    properties["include"] = json!({
+        "description": "include的文件，路径相对于当前文件",
+        "type": "array",
+        "items": { "type": "string" },
+    });
+    properties["templates"] = json!({
+        "description": "可以多次实例化的子图",
+        "type": "object",
+        "additionalProperties": template,
+    });
+    properties["vars"] = json!({
+        "description": "替换描述文件中 `${name}` 的变量，可以通过 --set 覆盖",
+        "type": "object",
+    });
+    properties["profiles"] = json!({
+        "description": "通过 --profile 选择，按key合并到描述文件中",
+        "type": "object",
+        "additionalProperties": { "type": "object" },
+    });
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{descriptor::format::Format, harness::fixture};
+    use jsonschema::JSONSchema;
+
+    #[test]
+    fn test_descriptor_schema() {
+        let schema = descriptor_schema();
+        for key in [
+            "version",
+            "deploy",
+            "nodes",
+            "include",
+            "templates",
+            "vars",
+            "profiles",
+        ] {
+            assert!(schema["properties"].get(key).is_some(), "missing `{key}`");
+        }
+        let validator = JSONSchema::compile(&schema).unwrap();
+        for name in [
+            "camera.yml",
+            "builtins.yml",
+            "clock.yml",
+            "windows.yml",
+            "doubler.yml",
+            "files.yml",
+        ] {
+            let text = fs::read_to_string(fixture(name)).unwrap();
+            let instance: Value = serde_yaml::from_str(&text).unwrap();
+            let errors: Vec<_> = match validator.validate(&instance) {
+                Ok(()) => vec![],
+                Err(errors) => errors.map(|error| error.to_string()).collect(),
+            };
+            assert!(
+                errors.is_empty(),
+                "{name} does not match the schema: {errors:#?}"
+            );
+        }
+        assert!(!validator.is_valid(&json!({ "version": "1.0", "nodes": [{ "id": 1 }] })));
+
+        // json中数字形式的版本
+        let text = r#"{ "version": 1.0, "vars": { "fps": 10 }, "nodes": [] }"#;
+        assert!(validator.is_valid(&serde_json::from_str(text).unwrap()));
+        let descriptor: Descriptor = Format::Json
+            .parse(r#"{ "version": 1.0, "nodes": [] }"#)
+            .unwrap();
+        assert_eq!(descriptor.version, "1.0");
+    }
+}
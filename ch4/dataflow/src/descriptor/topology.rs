@@ -0,0 +1,414 @@
+use super::{
+    descriptor::{DataId, InputMapping, NodeId, NormalNode, OperatorId},
+    diagnostic::{Diagnostic, Location},
+};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+/// operator 在图中的唯一标识 (node_id, operator_id)
+type OperatorKey = (NodeId, OperatorId);
+
+/// 数据流图的拓扑分析结果
+#[derive(Debug, Clone, Default)]
+pub struct Topology {
+    /// 节点的拓扑顺序，生产者在前，消费者在后
+    pub order: Vec<NodeId>,
+    /// 会导致校验失败的问题，如未允许的反馈环
+    pub errors: Vec<Diagnostic>,
+    /// 只需要提示的问题，如没有被消费的输出、不可达的节点
+    pub warnings: Vec<Diagnostic>,
+}
+
+/// 图中的一条边，从生产者operator指向消费者operator
+struct Edge {
+    producer: OperatorKey,
+    consumer: OperatorKey,
+    /// 消费者的输入id
+    input: DataId,
+    /// 是否允许这条边形成反馈环
+    allow_cycle: bool,
+}
+
+/// 对处理过默认值的节点进行拓扑分析
+/// 1. 检测没有被 allow_cycle 标记的反馈环
+/// 2. 找出没有被任何输入消费的输出
+/// 3. 找出无法从任何 source 或 timer 到达的节点和operator
+/// 4. 计算节点的拓扑顺序
+pub(crate) fn analyze_topology(nodes: &[NormalNode]) -> Topology {
+    let mut topology = Topology::default();
+
+    // 收集所有的operator及其输出
+    let mut outputs: BTreeMap<OperatorKey, &BTreeSet<DataId>> = BTreeMap::new();
+    for node in nodes {
+        for operator in &node.kind.operators {
+            outputs.insert(
+                (node.id.clone(), operator.id.clone()),
+                &operator.config.run_config.outputs,
+            );
+        }
+    }
+
+    // 收集所有的边，以及被消费的输出和根operator(source 或者使用了timer)
+    let mut edges = vec![];
+    let mut consumed: BTreeSet<(OperatorKey, DataId)> = BTreeSet::new();
+    let mut roots: BTreeSet<OperatorKey> = BTreeSet::new();
+    for node in nodes {
+        for operator in &node.kind.operators {
+            let consumer = (node.id.clone(), operator.id.clone());
+            let inputs = &operator.config.run_config.inputs;
+            if inputs.is_empty() {
+                roots.insert(consumer.clone());
+            }
+            for (input_id, input) in inputs {
+                match &input.mapping {
+                    InputMapping::Timer(_) => {
+                        roots.insert(consumer.clone());
+                    }
+                    InputMapping::User(mapping) => {
+                        // 经过resolve之后，output 的格式为 operator_id/output
+                        let (operator_id, output) =
+                            mapping.output.split_once('/').unwrap_or_default();
+                        let producer = (
+                            mapping.source.clone(),
+                            OperatorId::from(operator_id.to_owned()),
+                        );
+                        let output = DataId::from(output.to_owned());
+                        // 不存在的输出由 validate_input 报告，这里直接忽略
+                        match outputs.get(&producer) {
+                            Some(producer_outputs) if producer_outputs.contains(&output) => {
+                                consumed.insert((producer.clone(), output));
+                                edges.push(Edge {
+                                    producer,
+                                    consumer: consumer.clone(),
+                                    input: input_id.clone(),
+                                    allow_cycle: input.allow_cycle,
+                                });
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    topology.errors.extend(detect_cycles(&outputs, &edges));
+
+    // 没有被消费的输出
+    for (operator, operator_outputs) in &outputs {
+        for output in operator_outputs.iter() {
+            if !consumed.contains(&(operator.clone(), output.clone())) {
+                let (node_id, operator_id) = operator;
+                topology.warnings.push(Diagnostic::warning(
+                    "W001",
+                    Location::Output {
+                        node: node_id.clone(),
+                        operator: operator_id.clone(),
+                        output: output.clone(),
+                    },
+                    format!(
+                        "output `{node_id}/{operator_id}/{output}` is not consumed by any input"
+                    ),
+                ));
+            }
+        }
+    }
+
+    // 从 source 和 timer 出发，找出所有可达的operator
+    let reachable = reachable_operators(&roots, &edges);
+    for node in nodes {
+        let unreachable: Vec<_> = node
+            .kind
+            .operators
+            .iter()
+            .filter(|o| !reachable.contains(&(node.id.clone(), o.id.clone())))
+            .collect();
+        if unreachable.is_empty() {
+            continue;
+        }
+        if unreachable.len() == node.kind.operators.len() {
+            topology.warnings.push(Diagnostic::warning(
+                "W002",
+                Location::Node {
+                    node: node.id.clone(),
+                },
+                format!(
+                    "node `{}` is unreachable: no path from any source or timer",
+                    node.id
+                ),
+            ));
+        } else {
+            for operator in unreachable {
+                topology.warnings.push(Diagnostic::warning(
+                    "W002",
+                    Location::Operator {
+                        node: node.id.clone(),
+                        operator: operator.id.clone(),
+                    },
+                    format!(
+                        "operator `{}/{}` is unreachable: no path from any source or timer",
+                        node.id, operator.id
+                    ),
+                ));
+            }
+        }
+    }
+
+    let (order, remaining) = topological_order(nodes, &edges);
+    // operator 级别的反馈环已经作为错误报告，不再重复提示
+    if !remaining.is_empty() && topology.errors.is_empty() {
+        let remaining: Vec<_> = remaining.iter().map(|n| format!("`{n}`")).collect();
+        topology.warnings.push(Diagnostic::warning(
+            "W003",
+            Location::Dataflow,
+            format!(
+                "nodes {} depend on each other through different operators, \
+                their launch order is arbitrary",
+                remaining.join(", ")
+            ),
+        ));
+    }
+    topology.order = order;
+    topology
+}
+
+/// 使用深度优先搜索检测反馈环，被 allow_cycle 标记的边不参与检测
+fn detect_cycles(
+    outputs: &BTreeMap<OperatorKey, &BTreeSet<DataId>>,
+    edges: &[Edge],
+) -> Vec<Diagnostic> {
+    let mut adjacency: BTreeMap<&OperatorKey, Vec<&Edge>> = BTreeMap::new();
+    for edge in edges.iter().filter(|e| !e.allow_cycle) {
+        adjacency.entry(&edge.producer).or_default().push(edge);
+    }
+
+    /// 节点的访问状态
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        Visiting,
+        Done,
+    }
+
+    fn visit<'a>(
+        operator: &'a OperatorKey,
+        adjacency: &BTreeMap<&'a OperatorKey, Vec<&'a Edge>>,
+        states: &mut BTreeMap<&'a OperatorKey, State>,
+        stack: &mut Vec<&'a OperatorKey>,
+        cycles: &mut Vec<Diagnostic>,
+    ) {
+        states.insert(operator, State::Visiting);
+        stack.push(operator);
+        for edge in adjacency.get(operator).into_iter().flatten() {
+            let next: &'a OperatorKey = &edge.consumer;
+            match states.get(next) {
+                None => visit(next, adjacency, states, stack, cycles),
+                // 访问中的节点再次被访问，说明存在环
+                Some(State::Visiting) => {
+                    let start = stack.iter().position(|o| *o == next).unwrap_or_default();
+                    let path: Vec<_> = stack[start..]
+                        .iter()
+                        .chain(std::iter::once(&next))
+                        .map(|(node_id, operator_id)| format!("{node_id}/{operator_id}"))
+                        .collect();
+                    // 诊断信息指向闭合这个环的输入
+                    let (node, operator) = next.clone();
+                    cycles.push(Diagnostic::error(
+                        "E006",
+                        Location::Input {
+                            node,
+                            operator,
+                            input: edge.input.clone(),
+                        },
+                        format!(
+                            "feedback cycle detected: {}, mark one of its inputs with \
+                            `allow_cycle: true` if it is intended",
+                            path.join(" -> ")
+                        ),
+                    ));
+                }
+                Some(State::Done) => {}
+            }
+        }
+        stack.pop();
+        states.insert(operator, State::Done);
+    }
+
+    let mut states = BTreeMap::new();
+    let mut cycles = vec![];
+    for operator in outputs.keys() {
+        if !states.contains_key(operator) {
+            visit(operator, &adjacency, &mut states, &mut vec![], &mut cycles);
+        }
+    }
+    cycles
+}
+
+/// 广度优先搜索，从根operator出发可以到达的所有operator
+fn reachable_operators(roots: &BTreeSet<OperatorKey>, edges: &[Edge]) -> BTreeSet<OperatorKey> {
+    let mut reachable = roots.clone();
+    let mut queue: VecDeque<_> = roots.iter().cloned().collect();
+    while let Some(operator) = queue.pop_front() {
+        for edge in edges.iter().filter(|e| e.producer == operator) {
+            if reachable.insert(edge.consumer.clone()) {
+                queue.push_back(edge.consumer.clone());
+            }
+        }
+    }
+    reachable
+}
+
+/// 计算节点级别的拓扑顺序，相同层级的节点保持描述文件中的顺序
+/// 返回拓扑顺序，以及因为互相依赖而无法排序的节点(它们已经按描述文件顺序追加在拓扑顺序末尾)
+fn topological_order(nodes: &[NormalNode], edges: &[Edge]) -> (Vec<NodeId>, Vec<NodeId>) {
+    let index: BTreeMap<&NodeId, usize> =
+        nodes.iter().enumerate().map(|(i, n)| (&n.id, i)).collect();
+    let mut successors: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); nodes.len()];
+    for edge in edges.iter().filter(|e| !e.allow_cycle) {
+        if let (Some(&producer), Some(&consumer)) =
+            (index.get(&edge.producer.0), index.get(&edge.consumer.0))
+        {
+            // 节点内部operator之间的依赖不影响节点的顺序
+            if producer != consumer {
+                successors[producer].insert(consumer);
+            }
+        }
+    }
+    let mut in_degree = vec![0usize; nodes.len()];
+    for consumers in &successors {
+        for &consumer in consumers {
+            in_degree[consumer] += 1;
+        }
+    }
+
+    // Kahn 算法，使用有序集合保证结果稳定
+    let mut ready: BTreeSet<usize> = (0..nodes.len()).filter(|&i| in_degree[i] == 0).collect();
+    let mut order = vec![];
+    while let Some(current) = ready.pop_first() {
+        order.push(current);
+        for &consumer in &successors[current] {
+            in_degree[consumer] -= 1;
+            if in_degree[consumer] == 0 {
+                ready.insert(consumer);
+            }
+        }
+    }
+
+    let remaining: Vec<_> = (0..nodes.len()).filter(|i| !order.contains(i)).collect();
+    let order = order
+        .into_iter()
+        .chain(remaining.iter().copied())
+        .map(|i| nodes[i].id.clone())
+        .collect();
+    let remaining = remaining.into_iter().map(|i| nodes[i].id.clone()).collect();
+    (order, remaining)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptor::descriptor::Descriptor;
+
+    fn analyze(dataflow: &str) -> Topology {
+        let descriptor: Descriptor = serde_yaml::from_str(dataflow).unwrap();
+        analyze_topology(&descriptor.resolve_node_defaults())
+    }
+
+    #[test]
+    fn test_topological_order() {
+        let topology = analyze(
+            r#"
+version: 1.0
+nodes:
+  - id: sink
+    shell: ./sink.sh
+    inputs:
+      status: operator/status
+  - id: operator
+    shell: ./operator.sh
+    inputs:
+      image: source/image
+    outputs:
+      - status
+  - id: source
+    shell: ./source.sh
+    inputs:
+      tick: dataflow/timer/millis/100
+    outputs:
+      - image
+      - unused
+"#,
+        );
+        assert!(topology.errors.is_empty());
+        let order: Vec<_> = topology.order.iter().map(|n| n.as_str()).collect();
+        assert_eq!(order, ["source", "operator", "sink"]);
+        assert_eq!(topology.warnings.len(), 1);
+        assert!(topology.warnings[0]
+            .message
+            .contains("source/source/unused"));
+    }
+
+    #[test]
+    fn test_cycle_detection() {
+        let dataflow = r#"
+version: 1.0
+nodes:
+  - id: a
+    shell: ./a.sh
+    inputs:
+      tick: dataflow/timer/secs/1
+      feedback: b/out
+    outputs:
+      - out
+  - id: b
+    shell: ./b.sh
+    inputs:
+      in: a/out
+    outputs:
+      - out
+"#;
+        let topology = analyze(dataflow);
+        assert_eq!(topology.errors.len(), 1);
+        assert!(topology.errors[0].message.contains("a/a -> b/b -> a/a"));
+
+        // 标记 allow_cycle 之后不再报错
+        let topology = analyze(&dataflow.replace(
+            "feedback: b/out",
+            "feedback:\n        source: b/out\n        allow_cycle: true",
+        ));
+        assert!(topology.errors.is_empty());
+        let order: Vec<_> = topology.order.iter().map(|n| n.as_str()).collect();
+        assert_eq!(order, ["a", "b"]);
+    }
+
+    #[test]
+    fn test_unreachable_node() {
+        let topology = analyze(
+            r#"
+version: 1.0
+nodes:
+  - id: a
+    shell: ./a.sh
+    inputs:
+      in: b/out
+    outputs:
+      - out
+  - id: b
+    shell: ./b.sh
+    inputs:
+      in:
+        source: a/out
+        allow_cycle: true
+    outputs:
+      - out
+"#,
+        );
+        assert!(topology.errors.is_empty());
+        assert!(topology
+            .warnings
+            .iter()
+            .any(|w| w.message.contains("node `a` is unreachable")));
+        assert!(topology
+            .warnings
+            .iter()
+            .any(|w| w.message.contains("node `b` is unreachable")));
+    }
+}
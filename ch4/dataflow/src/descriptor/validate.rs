@@ -1,11 +1,27 @@
-use crate::{adjust_executable_target_path, adjust_shared_library_path, source_is_url};
+use crate::{
+    adjust_executable_target_path, adjust_shared_library_path,
+    cache::{is_cached, validate_digest},
+    launch::build::collect_jobs,
+    source_is_url,
+};
 
-use super::descriptor::{
-    DataId, Deploy, Descriptor, Input, InputMapping, NormalNode, OperatorId, OperatorSource,
-    UserInputMapping,
+use super::{
+    builtin::{stdin_nodes, BuiltinOperator},
+    descriptor::{
+        ByteSize, DataId, Deploy, Descriptor, HumanDuration, Input, InputMapping, NormalNode,
+        OperatorId, OperatorSource, ReadinessSignal, UserInputMapping,
+    },
+    diagnostic::{Diagnostic, Location},
+    topology::analyze_topology,
 };
 use anyhow::{anyhow, bail, Context, Result};
-use std::{path::Path, process::Command};
+use log::warn;
+use std::{
+    collections::BTreeMap,
+    net::ToSocketAddrs,
+    path::Path,
+    process::{Command, Stdio},
+};
 
 /// 处理url，进行网络请求检查
 fn resolve_url(url: &str) -> Result<()> {
@@ -19,46 +35,253 @@ fn resolve_url(url: &str) -> Result<()> {
     }
 }
 
+/// 检查url，offline 时只检查本地的下载缓存，否则进行网络请求检查
+fn validate_url(url: &str, sha256: Option<&str>, offline: bool, kind: &str) -> Result<()> {
+    if offline {
+        if !is_cached(url, sha256) {
+            bail!("{kind} url `{url}` is not in the download cache, run `ctl prefetch` first");
+        }
+        Ok(())
+    } else {
+        resolve_url(url).with_context(|| format!("Could not find {kind} url `{url}`"))
+    }
+}
+
 /// 校验dataflow
 /// 当build为True时，表示需要进行build，所以对于可执行文件的检查可以放宽
+/// 当offline为True时，不会发起网络请求
+/// 警告只打印，存在错误时校验失败
 pub(crate) fn validate_dataflow(
     dataflow: &Descriptor,
     working_dir: &Path,
     build: bool,
+    offline: bool,
 ) -> Result<()> {
+    let (errors, warnings): (Vec<_>, Vec<_>) =
+        check_dataflow(dataflow, working_dir, build, offline)
+            .into_iter()
+            .partition(Diagnostic::is_error);
+    for warning in &warnings {
+        warn!("{warning}");
+    }
+    if !errors.is_empty() {
+        bail!(
+            "{}",
+            errors
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// 检查dataflow，收集所有的诊断信息，而不是遇到第一个错误就返回
+pub(crate) fn check_dataflow(
+    dataflow: &Descriptor,
+    working_dir: &Path,
+    build: bool,
+    offline: bool,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
     let nodes = dataflow.resolve_node_defaults();
     // 检查描述文件的 deploy
-    validate_deploy(dataflow.deploy.clone())
-        .with_context(|| anyhow!("dataflow deploy validate error"))?;
+    if let Err(e) = validate_deploy(dataflow.deploy.clone()) {
+        diagnostics.push(Diagnostic::error(
+            "E001",
+            Location::Deploy { node: None },
+            format!("dataflow deploy is invalid: {e}"),
+        ));
+    }
     for node in &nodes {
         // 检查每一个节点的 deploy
-        validate_deploy(node.deploy.clone())
-            .with_context(|| anyhow!("node {:?} deploy validate error", node.id))?;
+        if let Err(e) = validate_deploy(node.deploy.clone()) {
+            diagnostics.push(Diagnostic::error(
+                "E001",
+                Location::Deploy {
+                    node: Some(node.id.clone()),
+                },
+                format!("node `{}` deploy is invalid: {e}", node.id),
+            ));
+        }
 
+        // 检查就绪和存活检查的配置
+        if let Err(e) = validate_health(node) {
+            diagnostics.push(Diagnostic::error(
+                "E011",
+                Location::Node {
+                    node: node.id.clone(),
+                },
+                format!("health check of node `{}` is invalid: {e}", node.id),
+            ));
+        }
+
+        // 节点的deploy中关闭了url校验时，同样只检查本地的下载缓存
+        let offline = offline || node.deploy.validate_urls == Some(false);
         // 对每一个节点的每一个op进行校验
         for operator_definition in &node.kind.operators {
             // 检查每一个op 的source 是否存在
-            validate_source(&operator_definition.config.source, working_dir, build).with_context(
-                || {
-                    anyhow!(
-                        "failed to check source:{:?} ,work dir is:{:?}",
-                        operator_definition.config.source,
+            let source = &operator_definition.config.source;
+            let sha256 = operator_definition.config.sha256.as_deref();
+            let params = operator_definition.config.params.as_ref();
+            if let Err(e) = validate_source(source, sha256, params, working_dir, build, offline) {
+                diagnostics.push(Diagnostic::error(
+                    "E002",
+                    Location::Source {
+                        node: node.id.clone(),
+                        operator: operator_definition.id.clone(),
+                    },
+                    format!(
+                        "failed to check {} source `{}` in work dir {:?}: {e:#}",
+                        source.kind(),
+                        source.to_string(),
                         working_dir
-                    )
-                },
-            )?;
+                    ),
+                ));
+            }
+            // 检查每一个op 的构建命令
+            if let Some(build) = &operator_definition.config.build {
+                if build.is_empty() {
+                    diagnostics.push(Diagnostic::error(
+                        "E007",
+                        Location::Operator {
+                            node: node.id.clone(),
+                            operator: operator_definition.id.clone(),
+                        },
+                        format!(
+                            "build command of operator `{}/{}` is empty",
+                            node.id, operator_definition.id
+                        ),
+                    ));
+                }
+            }
+            // 检查参数的格式，变量在运行时才展开，这里只检查语法
+            if let Some(args) = &operator_definition.config.args {
+                if let Err(e) = args.argv(&|_| Some(String::new())) {
+                    diagnostics.push(Diagnostic::error(
+                        "E009",
+                        Location::Operator {
+                            node: node.id.clone(),
+                            operator: operator_definition.id.clone(),
+                        },
+                        format!(
+                            "args of operator `{}/{}` are invalid: {e}",
+                            node.id, operator_definition.id
+                        ),
+                    ));
+                }
+            }
+            // 检查节点和op合并后的资源限制
+            let resources = node.resources.clone().unwrap_or_default().merge(
+                &operator_definition
+                    .config
+                    .resources
+                    .clone()
+                    .unwrap_or_default(),
+            );
+            if let Err(e) = resources.validate() {
+                diagnostics.push(Diagnostic::error(
+                    "E010",
+                    Location::Operator {
+                        node: node.id.clone(),
+                        operator: operator_definition.id.clone(),
+                    },
+                    format!(
+                        "resources of operator `{}/{}` are invalid: {e}",
+                        node.id, operator_definition.id
+                    ),
+                ));
+            }
+            // 只有shell operator 支持通过标准输入输出交换数据
+            if operator_definition.config.io.is_some()
+                && !matches!(source, OperatorSource::Shell(_))
+            {
+                diagnostics.push(Diagnostic::error(
+                    "E008",
+                    Location::Operator {
+                        node: node.id.clone(),
+                        operator: operator_definition.id.clone(),
+                    },
+                    format!(
+                        "`io` of operator `{}/{}` is only supported for shell sources",
+                        node.id, operator_definition.id
+                    ),
+                ));
+            }
+            // 检查内置operator的参数以及输入输出的数量
+            let builtin = match source {
+                OperatorSource::Builtin(name) => {
+                    BuiltinOperator::parse(name, operator_definition.config.params.as_ref())
+                        .and_then(|builtin| {
+                            builtin.validate(&operator_definition.config.run_config)
+                        })
+                }
+                _ if operator_definition.config.params.is_some() => {
+                    Err(anyhow!("`params` is only supported for builtin sources"))
+                }
+                _ => Ok(()),
+            };
+            if let Err(e) = builtin {
+                diagnostics.push(Diagnostic::error(
+                    "E013",
+                    Location::Operator {
+                        node: node.id.clone(),
+                        operator: operator_definition.id.clone(),
+                    },
+                    format!(
+                        "operator `{}/{}` is invalid: {e:#}",
+                        node.id, operator_definition.id
+                    ),
+                ));
+            }
             // 检查每一个op 的inputs 是否存在
             for (input_id, input) in &operator_definition.config.run_config.inputs {
-                validate_input(
+                let location = Location::Input {
+                    node: node.id.clone(),
+                    operator: operator_definition.id.clone(),
+                    input: input_id.clone(),
+                };
+                if let Err((code, message)) = validate_input(
                     input,
                     &nodes,
-                    &format!("{}/{}/{input_id}", operator_definition.id, node.id),
-                )?;
+                    &format!("{}/{}/{input_id}", node.id, operator_definition.id),
+                ) {
+                    diagnostics.push(Diagnostic::error(code, location, message));
+                }
             }
         }
     }
 
-    Ok(())
+    // launch 的标准输入只能交给一个节点
+    let stdin_nodes = stdin_nodes(&nodes);
+    if stdin_nodes.len() > 1 {
+        let names: Vec<_> = stdin_nodes.iter().map(|id| format!("`{id}`")).collect();
+        diagnostics.push(Diagnostic::error(
+            "E015",
+            Location::Dataflow,
+            format!(
+                "stdin_source is used by nodes {}, but stdin can only be passed to one node",
+                names.join(", ")
+            ),
+        ));
+    }
+
+    // 检查构建依赖是否存在以及是否成环
+    if let Err(e) = collect_jobs(&nodes) {
+        diagnostics.push(Diagnostic::error(
+            "E014",
+            Location::Dataflow,
+            format!("build dependencies are invalid: {e}"),
+        ));
+    }
+
+    // 对整个图进行拓扑分析
+    let topology = analyze_topology(&nodes);
+    diagnostics.extend(topology.errors);
+    diagnostics.extend(topology.warnings);
+
+    diagnostics
 }
 
 /// 检查deploy
@@ -70,16 +293,108 @@ fn validate_deploy(deploy: Deploy) -> Result<()> {
     } else {
         return Err(anyhow!("node has no endpoints defined"));
     }
+    if let Some(rotation) = deploy.log_rotation {
+        if rotation.max_size() == ByteSize(0) {
+            bail!("log_rotation.max_size must be greater than 0");
+        }
+        if rotation.max_age.is_some_and(|age| age.0.is_zero()) {
+            bail!("log_rotation.max_age must be greater than 0");
+        }
+    }
+    if let Some(metrics) = deploy.metrics {
+        metrics.listen.to_socket_addrs().with_context(|| {
+            format!("metrics.listen `{}` is not a valid address", metrics.listen)
+        })?;
+    }
+    if let Some(tracing) = deploy.tracing {
+        if tracing.otlp.is_none() && tracing.file.is_none() {
+            bail!("tracing needs an `otlp` endpoint or a trace `file`");
+        }
+        if let Some(otlp) = tracing.otlp {
+            if !source_is_url(&otlp) {
+                bail!("tracing.otlp `{otlp}` must be a http or https url");
+            }
+        }
+    }
+    Ok(())
+}
+/// 检查节点的就绪和存活检查
+fn validate_health(node: &NormalNode) -> Result<()> {
+    if let Some(readiness) = &node.readiness {
+        if readiness.timeout().is_zero() {
+            bail!("readiness.timeout must be greater than 0");
+        }
+        let has_outputs = node
+            .kind
+            .operators
+            .iter()
+            .any(|operator| !operator.config.run_config.outputs.is_empty());
+        if readiness.signal == ReadinessSignal::FirstOutput && !has_outputs {
+            bail!("readiness signal `first_output` needs the node to have outputs");
+        }
+        // launch 等待节点就绪之后才启动上游的节点和定时器，有输入的节点不会产生第一条输出
+        let has_inputs = node
+            .kind
+            .operators
+            .iter()
+            .any(|operator| !operator.config.run_config.inputs.is_empty());
+        if readiness.signal == ReadinessSignal::FirstOutput && has_inputs {
+            bail!(
+                "readiness signal `first_output` needs the node to have no inputs, \
+                 upstream nodes and timers are started after it becomes ready"
+            );
+        }
+        if let Some(operators) = &readiness.operators {
+            if readiness.signal != ReadinessSignal::Sdk {
+                bail!("readiness.operators can only be used with readiness signal `sdk`");
+            }
+            // 内置operator不会报告就绪
+            let reporting = node.heartbeat_operators();
+            if let Some(operator) = operators.iter().find(|id| !reporting.contains(*id)) {
+                bail!("readiness.operators: node has no non-builtin operator `{operator}`");
+            }
+        }
+        if readiness.signal == ReadinessSignal::Sdk && node.ready_operators().is_empty() {
+            bail!("readiness signal `sdk` needs an operator that is not builtin to report ready");
+        }
+    }
+    if let Some(liveness) = &node.liveness {
+        if liveness.interval().is_zero() {
+            bail!("liveness.interval must be greater than 0");
+        }
+        if liveness.timeout() <= liveness.interval() {
+            bail!(
+                "liveness.timeout ({}) must be longer than liveness.interval ({})",
+                HumanDuration(liveness.timeout()),
+                HumanDuration(liveness.interval())
+            );
+        }
+    }
     Ok(())
 }
+
 /// 检查各种source是否存在
 /// build 如果为True，说明需要进行build，所以对于可执行文件的检查可以放宽
-fn validate_source(source: &OperatorSource, working_dir: &Path, build: bool) -> Result<()> {
+/// sha256 只能用于url类型的source
+/// params 是内置operator的参数，用于检查读写的文件
+fn validate_source(
+    source: &OperatorSource,
+    sha256: Option<&str>,
+    params: Option<&BTreeMap<String, serde_yaml::Value>>,
+    working_dir: &Path,
+    build: bool,
+    offline: bool,
+) -> Result<()> {
+    if let Some(digest) = sha256 {
+        if !source_is_url(&source.to_string()) {
+            bail!("`sha256` is only supported for url sources");
+        }
+        validate_digest(digest)?;
+    }
     match source {
         OperatorSource::SharedLibrary(path) => {
             if source_is_url(path) {
-                resolve_url(&path)
-                    .with_context(|| format!("Could not find shared library url `{}`", path))?;
+                validate_url(path, sha256, offline, "shared library")?;
             } else {
                 // 调整共享库lib的路径，再判断是否存在
                 let path = adjust_shared_library_path(Path::new(&path))?;
@@ -90,16 +405,14 @@ fn validate_source(source: &OperatorSource, working_dir: &Path, build: bool) ->
         }
         OperatorSource::PythonModule(path) => {
             if source_is_url(path) {
-                resolve_url(&path)
-                    .with_context(|| format!("Could not find Python library url `{}`", path))?;
+                validate_url(path, sha256, offline, "Python library")?;
             } else if !working_dir.join(path).exists() && !build {
                 bail!("no Python library at `{path}`");
             }
         }
         OperatorSource::WasmModule(path) => {
             if source_is_url(path) {
-                resolve_url(&path)
-                    .with_context(|| format!("Could not find WASM library url `{}`", path))?;
+                validate_url(path, sha256, offline, "WASM library")?;
             } else if !working_dir.join(path).exists() && !build {
                 bail!("no WASM library at `{path}`");
             }
@@ -120,8 +433,10 @@ fn validate_source(source: &OperatorSource, working_dir: &Path, build: bool) ->
                 Command::new("which")
             };
             // 执行命令，并且获取状态
+            // 只关心命令是否存在，不输出查找的结果
             let status = command
                 .arg(first_cmd)
+                .stdout(Stdio::null())
                 .status()
                 .with_context(|| format!("Can not exec command: `{}`", first_cmd))?;
 
@@ -129,10 +444,15 @@ fn validate_source(source: &OperatorSource, working_dir: &Path, build: bool) ->
                 bail!("Could not find first command: `{first_cmd}` of shell: `{shell}`");
             }
         }
+        // 内置operator只检查读写的文件，参数在之后单独检查
+        OperatorSource::Builtin(name) => {
+            if let Ok(builtin) = BuiltinOperator::parse(name, params) {
+                builtin.validate_paths(working_dir)?;
+            }
+        }
         OperatorSource::ExeTarget(target) => {
             if source_is_url(target) {
-                resolve_url(&target)
-                    .with_context(|| format!("Could not find target url `{}`", target))?;
+                validate_url(target, sha256, offline, "target")?;
             } else {
                 // build 为 true时，说明，后面会进行build，只需要判断是否是一个合法的target的名字即可
                 // 这里，假如build为True，并且target中没有空格，那么就不需要进行判断
@@ -154,13 +474,33 @@ fn validate_source(source: &OperatorSource, working_dir: &Path, build: bool) ->
 }
 
 /// 检查各种input是否存在
-fn validate_input(input: &Input, nodes: &[NormalNode], input_id_str: &str) -> Result<()> {
+/// 失败时返回诊断代码和错误信息
+fn validate_input(
+    input: &Input,
+    nodes: &[NormalNode],
+    input_id_str: &str,
+) -> Result<(), (&'static str, String)> {
     match &input.mapping {
-        InputMapping::Timer { interval: _ } => {}
+        InputMapping::Timer(timer) => {
+            timer.validate().map_err(|e| {
+                (
+                    "E012",
+                    format!(
+                        "timer `{}` mapped to input `{input_id_str}` is invalid: {e}",
+                        input.mapping
+                    ),
+                )
+            })?;
+        }
         InputMapping::User(UserInputMapping { source, output }) => {
             // 根据 source 从 nodes中找到对应的节点
             let source_node = nodes.iter().find(|n| &n.id == source).ok_or_else(|| {
-                anyhow!("source node `{source}` mapped to input `{input_id_str}` does not exist",)
+                (
+                    "E003",
+                    format!(
+                        "source node `{source}` mapped to input `{input_id_str}` does not exist"
+                    ),
+                )
             })?;
             // 根据 output 从 source_node 中找到对应 operator 的 output
             let (operator_id, output) = output.split_once('/').unwrap_or_default();
@@ -173,17 +513,23 @@ fn validate_input(input: &Input, nodes: &[NormalNode], input_id_str: &str) -> Re
                 .iter()
                 .find(|o| o.id == operator_id)
                 .ok_or_else(|| {
-                    anyhow!(
-                        "source operator `{source}/{operator_id}` used \
-                        for input `{input_id_str}` does not exist",
+                    (
+                        "E004",
+                        format!(
+                            "source operator `{source}/{operator_id}` used \
+                            for input `{input_id_str}` does not exist",
+                        ),
                     )
                 })?;
-            // 如果找不到就添加异常到上下文
+            // 如果找不到就返回异常
             if !operator.config.run_config.outputs.contains(&output) {
-                bail!(
-                    "output `{source}/{operator_id}/{output}` mapped to \
-                    input `{input_id_str}` does not exist",
-                );
+                return Err((
+                    "E005",
+                    format!(
+                        "output `{source}/{operator_id}/{output}` mapped to \
+                        input `{input_id_str}` does not exist",
+                    ),
+                ));
             }
         }
     };
@@ -207,6 +553,63 @@ mod tests {
         let working_dir = binding.parent().unwrap();
         println!("working_dir>>> {:#?}", working_dir);
         println!("\n\n\n");
-        validate_dataflow(&des, &working_dir, true).unwrap();
+        validate_dataflow(&des, &working_dir, true, false).unwrap();
+    }
+
+    #[test]
+    fn test_first_output_readiness() {
+        let descriptor: Descriptor = serde_yaml::from_str(
+            r#"
+version: 1.0
+nodes:
+  - id: source
+    shell: ./source
+    outputs: [data]
+    readiness: { signal: first_output }
+  - id: filter
+    shell: ./filter
+    inputs:
+      data: source/data
+    outputs: [data]
+    readiness: { signal: first_output }
+"#,
+        )
+        .unwrap();
+        let nodes = descriptor.resolve_node_defaults();
+        validate_health(&nodes[0]).unwrap();
+        let error = validate_health(&nodes[1]).unwrap_err();
+        assert!(error.to_string().contains("no inputs"));
+    }
+
+    #[test]
+    fn test_stdin_source_nodes() {
+        let text = r#"
+version: 1.0
+nodes:
+  - id: console
+    operators:
+      - id: read
+        builtin: stdin_source
+        outputs: [line]
+  - id: printer
+    builtin: stdout_sink
+    inputs:
+      line: console/read/line
+"#;
+        let descriptor: Descriptor = serde_yaml::from_str(text).unwrap();
+        let stdin_error = |descriptor: &Descriptor| {
+            check_dataflow(descriptor, Path::new("."), false, true)
+                .into_iter()
+                .any(|diagnostic| diagnostic.code == "E015")
+        };
+        assert_eq!(stdin_nodes(&descriptor.resolve_node_defaults()).len(), 1);
+        assert!(!stdin_error(&descriptor));
+
+        let text = text.replace(
+            "builtin: stdout_sink",
+            "builtin: stdin_source\n    outputs: [line]",
+        );
+        let descriptor: Descriptor = serde_yaml::from_str(&text).unwrap();
+        assert!(stdin_error(&descriptor));
     }
 }
@@ -1,85 +1,210 @@
-use super::descriptor::Descriptor;
-use anyhow::{Context, Result};
+use super::{descriptor::Descriptor, expand::Overrides, format::Format};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
 use log::debug;
 use std::{
     fs::File,
     io::Write,
     path::{Path, PathBuf},
+    process::{Command, Stdio},
 };
 use webbrowser;
 
 /// HTML template for rendering mermaid graphs.
 const MERMAID_TEMPLATE: &str = include_str!("mermaid-template.html");
+/// HTML template for embedding a pre-rendered SVG graph, needs no network access.
+const SVG_TEMPLATE: &str = include_str!("svg-template.html");
+
+/// 数据流图的输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GraphFormat {
+    /// mermaid flowchart
+    Mermaid,
+    /// graphviz dot
+    Dot,
+    /// 通过本地graphviz渲染的svg
+    Svg,
+    /// 完全解析之后的数据流图，json格式
+    Json,
+}
 
 /// Create a visualization of the given dataflow file.
-pub fn visualize(dataflow: PathBuf, mermaid: bool, open: bool) -> Result<()> {
-    if mermaid {
-        // 生成mermaid图
-        let visualized = visualize_as_mermaid(&dataflow)?;
-        println!("{visualized}");
-        println!(
-            "Paste the above output on https://mermaid.live/ or in a \
-            ```mermaid code block on GitHub to display it."
-        );
-    } else {
-        // 将mermaid图嵌入到html
-        let html = visualize_as_html(&dataflow)?;
-        // 获取当前的工作目录
-        let working_dir = std::env::current_dir().expect("failed to get current working dir");
-        // 生成文件名
-        let graph_filename = match dataflow.file_stem().and_then(|n| n.to_str()) {
-            Some(name) => format!("{name}-graph"),
-            None => "graph".into(),
-        };
-        // 这个逻辑就是说当文件名重复时给他增加后缀
-        // 我觉得不如随机加一点后缀
-        // 或者使用时间戳
-        let mut extra = 0;
-        let path = loop {
-            let adjusted_file_name = if extra == 0 {
-                format!("{graph_filename}.html")
+/// format 为 None 时生成html文件，offline 为 true 时html中嵌入本地渲染的svg，不再依赖cdn
+/// resolved 为 true 时输出展开include、模板、profile和变量之后的描述文件
+pub fn visualize(
+    dataflow: PathBuf,
+    format: Option<GraphFormat>,
+    open: bool,
+    offline: bool,
+    resolved: bool,
+    overrides: &Overrides,
+) -> Result<()> {
+    let descriptor = read_descriptor(&dataflow, overrides)?;
+    if resolved {
+        // 使用和描述文件相同的格式输出
+        let text = Format::from_path(&dataflow).to_string(&descriptor)?;
+        println!("{text}");
+        return Ok(());
+    }
+    match format {
+        Some(GraphFormat::Mermaid) => {
+            // 生成mermaid图
+            let visualized = visualize_as_mermaid(&descriptor)?;
+            println!("{visualized}");
+            println!(
+                "Paste the above output on https://mermaid.live/ or in a \
+                ```mermaid code block on GitHub to display it."
+            );
+        }
+        Some(GraphFormat::Dot) => {
+            // 生成dot图
+            let visualized = visualize_as_dot(&descriptor)?;
+            println!("{visualized}");
+        }
+        Some(GraphFormat::Svg) => {
+            // 使用本地的graphviz渲染svg
+            let visualized = render_svg(&visualize_as_dot(&descriptor)?)?;
+            println!("{visualized}");
+        }
+        Some(GraphFormat::Json) => {
+            // 导出json格式的数据流图
+            println!("{}", descriptor.visualize_as_json()?);
+        }
+        None => {
+            // 将图嵌入到html
+            let html = if offline {
+                visualize_as_offline_html(&descriptor)?
             } else {
-                format!("{graph_filename}.{extra}.html")
+                visualize_as_html(&descriptor)?
+            };
+            // 获取当前的工作目录
+            let working_dir = std::env::current_dir().expect("failed to get current working dir");
+            // 生成文件名
+            let graph_filename = match dataflow.file_stem().and_then(|n| n.to_str()) {
+                Some(name) => format!("{name}-graph"),
+                None => "graph".into(),
+            };
+            // 这个逻辑就是说当文件名重复时给他增加后缀
+            // 我觉得不如随机加一点后缀
+            // 或者使用时间戳
+            let mut extra = 0;
+            let path = loop {
+                let adjusted_file_name = if extra == 0 {
+                    format!("{graph_filename}.html")
+                } else {
+                    format!("{graph_filename}.{extra}.html")
+                };
+                let path = working_dir.join(&adjusted_file_name);
+                if path.exists() {
+                    extra += 1;
+                } else {
+                    break path;
+                }
             };
-            let path = working_dir.join(&adjusted_file_name);
-            if path.exists() {
-                extra += 1;
-            } else {
-                break path;
-            }
-        };
 
-        // 创建文件并写入html
-        let mut file = File::create(&path).context("failed to create graph HTML file")?;
-        file.write_all(html.as_bytes())?;
+            // 创建文件并写入html
+            let mut file = File::create(&path).context("failed to create graph HTML file")?;
+            file.write_all(html.as_bytes())?;
 
-        println!(
-            "View graph by opening the following in your browser:\n  file://{}",
-            path.display()
-        );
+            println!(
+                "View graph by opening the following in your browser:\n  file://{}",
+                path.display()
+            );
 
-        // 是否打开浏览器
-        if open {
-            webbrowser::open(path.as_os_str().to_str().unwrap())?;
+            // 是否打开浏览器
+            if open {
+                webbrowser::open(path.as_os_str().to_str().unwrap())?;
+            }
         }
     }
     Ok(())
 }
 
 /// 根据dataflow文件生成包含 mermaid图 的html
-fn visualize_as_html(dataflow: &Path) -> Result<String> {
-    let mermaid = visualize_as_mermaid(dataflow)?;
+fn visualize_as_html(descriptor: &Descriptor) -> Result<String> {
+    let mermaid = visualize_as_mermaid(descriptor)?;
     Ok(MERMAID_TEMPLATE.replacen("____insert____", &mermaid, 1))
 }
 
+/// 根据dataflow文件生成包含 svg图 的html，不需要访问网络
+fn visualize_as_offline_html(descriptor: &Descriptor) -> Result<String> {
+    let svg = render_svg(&visualize_as_dot(descriptor)?)?;
+    Ok(SVG_TEMPLATE.replacen("____insert____", &svg, 1))
+}
+
 /// 根据dataflow文件生成mermaid图
-fn visualize_as_mermaid(dataflow: &Path) -> Result<String> {
-    let descriptor = Descriptor::blocking_read(dataflow)
-        .with_context(|| format!("failed to read dataflow at `{}`", dataflow.display()))?;
-    debug!("descriptor: {:#?}", descriptor);
+fn visualize_as_mermaid(descriptor: &Descriptor) -> Result<String> {
     let visualized = descriptor
         .visualize_as_mermaid()
         .context("failed to visualize descriptor")?;
 
     Ok(visualized)
 }
+
+/// 根据dataflow文件生成dot图
+fn visualize_as_dot(descriptor: &Descriptor) -> Result<String> {
+    let visualized = descriptor
+        .visualize_as_dot()
+        .context("failed to visualize descriptor")?;
+
+    Ok(visualized)
+}
+
+/// 读取并解析dataflow文件
+fn read_descriptor(dataflow: &Path, overrides: &Overrides) -> Result<Descriptor> {
+    let descriptor = Descriptor::blocking_read_with_overrides(dataflow, overrides)
+        .with_context(|| format!("failed to read dataflow at `{}`", dataflow.display()))?;
+    debug!("descriptor: {:#?}", descriptor);
+    Ok(descriptor)
+}
+
+/// 调用本地的graphviz `dot` 命令将dot图渲染为svg
+fn render_svg(dot: &str) -> Result<String> {
+    let dot_path = which::which("dot")
+        .context("graphviz `dot` command not found, install graphviz to render svg offline")?;
+    let mut child = Command::new(dot_path)
+        .arg("-Tsvg")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("failed to run graphviz `dot`")?;
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("failed to take stdin of graphviz `dot`"))?
+        .write_all(dot.as_bytes())
+        .context("failed to write dot graph to graphviz")?;
+    let output = child
+        .wait_with_output()
+        .context("failed to wait graphviz `dot`")?;
+    if !output.status.success() {
+        bail!(
+            "graphviz `dot` failed to render svg: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    String::from_utf8(output.stdout).context("graphviz `dot` returned invalid UTF8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::fixture;
+
+    #[test]
+    fn test_offline_html() {
+        let descriptor = Descriptor::blocking_read(&fixture("camera.yml")).unwrap();
+        let html = visualize_as_offline_html(&descriptor);
+        // 没有安装graphviz时给出明确的错误
+        if which::which("dot").is_err() {
+            let error = format!("{:#}", html.unwrap_err());
+            assert!(error.contains("install graphviz"), "{error}");
+            return;
+        }
+        let html = html.unwrap();
+        assert!(!html.contains("____insert____"));
+        assert!(html.contains("<svg"));
+        assert!(html.contains("limiter (builtin: batch)"));
+    }
+}
@@ -1,3 +1,7 @@
+use std::fmt;
+
+use crate::runtime::resources::ResourceLimitExceeded;
+
 #[derive(Debug)]
 pub enum Event {
     // NewDaemonConnection(TcpStream),
@@ -9,4 +13,18 @@ pub enum Event {
     // DaemonHeartbeatInterval,
     CtrlC,
     Logged,
+    /// operator 超出了资源限制
+    ResourceLimitExceeded(ResourceLimitExceeded),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::CtrlC => write!(f, "ctrl-c"),
+            Event::Logged => write!(f, "logged"),
+            Event::ResourceLimitExceeded(exceeded) => {
+                write!(f, "resource limit exceeded event: {exceeded}")
+            }
+        }
+    }
 }
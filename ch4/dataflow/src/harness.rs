@@ -0,0 +1,502 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    clock::{self, ClockDriver},
+    communication::{memory::MemoryBus, Backend, Envelope, Metadata},
+    descriptor::descriptor::{ClockMode, Descriptor, HumanDuration, InputMapping, NodeKind},
+    runtime::{node::spawn_operators, timer},
+};
+use anyhow::{anyhow, bail, Context, Result};
+use futures::{stream::select_all, StreamExt};
+use log::debug;
+use tokio::{
+    sync::{oneshot, OwnedMutexGuard},
+    task::JoinHandle,
+};
+
+/// mock 的处理函数，参数为输入id和数据，返回要发布的output id和数据
+type MockHandler = Box<dyn FnMut(&str, &[u8]) -> Vec<(String, Vec<u8>)> + Send>;
+
+/// 节点的替身，在进程内代替节点处理输入、产生输出
+/// 输入和输出的id相对于节点：单op节点为 `input`，多op节点为 `operator/input`
+pub struct Mock {
+    handler: MockHandler,
+}
+
+impl Mock {
+    /// 只接收输入、不产生输出的替身，用于替换数据源或者不关心的下游
+    pub fn silent() -> Self {
+        Self::new(|_, _| vec![])
+    }
+
+    /// 每收到一个输入调用一次 handler，返回的数据依次发布到对应的output
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: FnMut(&str, &[u8]) -> Vec<(String, Vec<u8>)> + Send + 'static,
+    {
+        Self {
+            handler: Box::new(handler),
+        }
+    }
+}
+
+/// 数据流的集成测试工具，所有节点都在当前进程中通过内存总线通信
+/// 被替换的节点由 mock 处理，其余节点的operator正常启动
+/// 设置了 `deploy.clock: simulated` 时，定时器只在 `advance` 推进模拟时钟时触发
+/// ```no_run
+/// # use dataflow::harness::{Harness, Mock};
+/// # async fn test() -> anyhow::Result<()> {
+/// let dataflow = Harness::load("dataflow.yml")?
+///     .mock("camera", Mock::silent())
+///     .start()
+///     .await?;
+/// dataflow.inject("camera/image", b"frame")?;
+/// dataflow.expect_eq("detector/boxes", b"[]").await?;
+/// dataflow.stop().await
+/// # }
+/// ```
+pub struct Harness {
+    descriptor: Descriptor,
+    working_dir: PathBuf,
+    mocks: BTreeMap<String, Mock>,
+    timeout: Duration,
+}
+
+impl Harness {
+    /// 等待输出的默认超时时间
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// 读取描述文件，工作目录为描述文件所在的目录
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let descriptor = Descriptor::blocking_read(path)
+            .with_context(|| format!("failed to read dataflow at `{}`", path.display()))?;
+        let working_dir = path
+            .canonicalize()
+            .context("failed to canonicalize dataflow path")?
+            .parent()
+            .ok_or_else(|| anyhow!("dataflow path has no parent dir"))?
+            .to_owned();
+        Ok(Self::new(descriptor, working_dir))
+    }
+
+    pub fn new(descriptor: Descriptor, working_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            descriptor,
+            working_dir: working_dir.into(),
+            mocks: BTreeMap::new(),
+            timeout: Self::DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// 使用 mock 替换节点，节点的operator不会被启动
+    pub fn mock(mut self, node_id: &str, mock: Mock) -> Self {
+        self.mocks.insert(node_id.to_owned(), mock);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 修改工作目录，operator中的相对路径相对于该目录
+    pub fn with_working_dir(mut self, working_dir: impl Into<PathBuf>) -> Self {
+        self.working_dir = working_dir.into();
+        self
+    }
+
+    /// 启动数据流，返回之后所有节点的输入都已经订阅
+    pub async fn start(self) -> Result<RunningDataflow> {
+        let Self {
+            descriptor,
+            working_dir,
+            mut mocks,
+            timeout,
+        } = self;
+        if let Some(node_id) = mocks.keys().find(|id| {
+            !descriptor
+                .nodes
+                .iter()
+                .any(|node| node.id.as_str() == id.as_str())
+        }) {
+            bail!("cannot mock unknown node `{node_id}`");
+        }
+        let bus = MemoryBus::new();
+        let backend = Backend::Memory(bus.clone());
+        let nodes = descriptor.resolve_node_defaults();
+        // 模拟时钟是进程级别的，开启之后当前进程中的时间戳都使用模拟时间
+        let (clock, simulation) = match descriptor.deploy.clock {
+            Some(ClockMode::Simulated) => {
+                let simulation = clock::exclusive().await;
+                let driver = ClockDriver::connect(&descriptor, &backend)?.with_timeout(timeout);
+                (Some(Arc::new(Mutex::new(driver))), Some(simulation))
+            }
+            _ => (None, None),
+        };
+
+        // 先订阅所有的输出，启动之后产生的消息都能被收到
+        let mut outputs = BTreeMap::new();
+        for node in &nodes {
+            for operator in &node.kind.operators {
+                for output in &operator.config.run_config.outputs {
+                    let topic = format!("{}/{}/{output}", node.id, operator.id);
+                    outputs.insert(topic.clone(), bus.subscribe(&topic));
+                }
+            }
+        }
+
+        let mut tasks = vec![];
+        for node in &nodes {
+            let Some(mock) = mocks.remove(node.id.as_str()) else {
+                for (operator_id, task) in spawn_operators(node, &backend, &working_dir)
+                    .await
+                    .with_context(|| format!("failed to start node `{}`", node.id))?
+                {
+                    tasks.push((format!("operator `{}/{operator_id}`", node.id), task));
+                }
+                continue;
+            };
+            let single = descriptor
+                .nodes
+                .iter()
+                .any(|n| n.id == node.id && matches!(n.kind, NodeKind::Operator(_)));
+            let mut inputs = vec![];
+            for operator in &node.kind.operators {
+                for (input_id, input) in &operator.config.run_config.inputs {
+                    let input_id = match single {
+                        true => input_id.to_string(),
+                        false => format!("{}/{input_id}", operator.id),
+                    };
+                    inputs.push((input_id, bus.subscribe(&input.mapping.topic())));
+                }
+            }
+            debug!("Mock node {} with inputs {:?}", node.id, inputs);
+            let task = tokio::spawn(run_mock(
+                mock,
+                node.id.to_string(),
+                inputs,
+                descriptor.clone(),
+                bus.clone(),
+            ));
+            tasks.push((format!("mock of node `{}`", node.id), task));
+        }
+
+        // 所有节点启动之后再启动定时器，与launch相同
+        let (stop_timer, stopped) = oneshot::channel::<()>();
+        let deploy = descriptor.deploy.clone();
+        let timer = tokio::spawn(async move {
+            timer::start(&nodes, &deploy, &backend, async {
+                let _ = stopped.await;
+            })
+            .await
+        });
+
+        Ok(RunningDataflow {
+            descriptor,
+            bus,
+            outputs,
+            tasks,
+            timer: Some((stop_timer, timer)),
+            clock,
+            _simulation: simulation,
+            timeout,
+        })
+    }
+}
+
+/// 运行 mock，直到总线关闭
+async fn run_mock(
+    mut mock: Mock,
+    node_id: String,
+    inputs: Vec<(String, flume::Receiver<Envelope>)>,
+    descriptor: Descriptor,
+    bus: MemoryBus,
+) -> Result<()> {
+    let mut inputs = select_all(inputs.into_iter().map(|(input_id, rx)| {
+        rx.into_stream()
+            .map(move |envelope| (input_id.clone(), envelope))
+            .boxed()
+    }));
+    while let Some((input_id, envelope)) = inputs.next().await {
+        for (output, data) in (mock.handler)(&input_id, &envelope.data) {
+            let topic = descriptor
+                .output_topic(&format!("{node_id}/{output}"))
+                .with_context(|| format!("mock of node `{node_id}` sent to an invalid output"))?;
+            bus.publish(&topic, &data, &Metadata::new());
+        }
+    }
+    Ok(())
+}
+
+/// 运行中的数据流，输出的写法与输入映射相同，如 `node/output` 或 `node/operator/output`
+pub struct RunningDataflow {
+    descriptor: Descriptor,
+    bus: MemoryBus,
+    /// 所有输出的topic到接收端的映射
+    outputs: BTreeMap<String, flume::Receiver<Envelope>>,
+    tasks: Vec<(String, JoinHandle<Result<()>>)>,
+    timer: Option<(oneshot::Sender<()>, JoinHandle<Result<()>>)>,
+    /// 使用模拟时钟时推进时钟的驱动
+    clock: Option<Arc<Mutex<ClockDriver>>>,
+    /// 使用模拟时钟时独占进程的模拟时钟，在恢复系统时间之后释放
+    _simulation: Option<OwnedMutexGuard<()>>,
+    timeout: Duration,
+}
+
+impl RunningDataflow {
+    /// 向一个输出注入数据，订阅该输出的节点都会收到
+    /// 通常是被替换的节点的输出，也可以是 `dataflow/timer/...` 这样的定时器
+    pub fn inject(&self, output: &str, data: impl AsRef<[u8]>) -> Result<()> {
+        let topic = match output.starts_with("dataflow/") {
+            true => output
+                .parse::<InputMapping>()
+                .map_err(|e| anyhow!("invalid output `{output}`: {e}"))?
+                .topic(),
+            false => self.descriptor.output_topic(output)?,
+        };
+        debug!("Inject {} bytes into {topic}", data.as_ref().len());
+        self.bus.publish(&topic, data.as_ref(), &Metadata::new());
+        Ok(())
+    }
+
+    /// 等待输出的下一条消息，超时返回错误
+    pub async fn expect(&self, output: &str) -> Result<Vec<u8>> {
+        let receiver = self.receiver(output)?;
+        match tokio::time::timeout(self.timeout, receiver.recv_async()).await {
+            Ok(Ok(envelope)) => Ok(envelope.data),
+            Ok(Err(_)) => bail!("dataflow stopped before `{output}` sent a message"),
+            Err(_) => bail!(
+                "no message from `{output}` within {}",
+                HumanDuration(self.timeout)
+            ),
+        }
+    }
+
+    /// 等待输出的下一条消息，并检查与期望的数据相同
+    pub async fn expect_eq(&self, output: &str, expected: impl AsRef<[u8]>) -> Result<()> {
+        let actual = self.expect(output).await?;
+        if actual != expected.as_ref() {
+            bail!(
+                "unexpected message from `{output}`\n  expected: {:?}\n    actual: {:?}",
+                String::from_utf8_lossy(expected.as_ref()),
+                String::from_utf8_lossy(&actual)
+            );
+        }
+        Ok(())
+    }
+
+    /// 检查输出在 duration 之内没有产生消息
+    pub async fn expect_silence(&self, output: &str, duration: Duration) -> Result<()> {
+        let receiver = self.receiver(output)?;
+        if let Ok(Ok(envelope)) = tokio::time::timeout(duration, receiver.recv_async()).await {
+            bail!(
+                "expected no message from `{output}` within {}, got {:?}",
+                HumanDuration(duration),
+                String::from_utf8_lossy(&envelope.data)
+            );
+        }
+        Ok(())
+    }
+
+    /// 推进模拟时钟，定时器按时间顺序发布其间所有的tick后返回推进之后的模拟时间
+    /// 节点对tick的处理是异步的，之后通过 `expect` 等待节点的输出
+    pub async fn advance(&self, by: Duration) -> Result<SystemTime> {
+        let clock = self.clock.clone().ok_or_else(|| {
+            anyhow!("dataflow does not use a simulated clock, set `deploy.clock: simulated`")
+        })?;
+        // ClockDriver 是阻塞的，不能占用运行时的线程
+        tokio::task::spawn_blocking(move || clock.lock().unwrap().advance(by))
+            .await
+            .context("clock driver panicked")?
+    }
+
+    /// 取出输出已经收到的所有消息
+    pub fn drain(&self, output: &str) -> Result<Vec<Vec<u8>>> {
+        Ok(self
+            .receiver(output)?
+            .drain()
+            .map(|envelope| envelope.data)
+            .collect())
+    }
+
+    /// 停止数据流，返回运行期间失败的operator或mock的错误
+    pub async fn stop(mut self) -> Result<()> {
+        let mut result = Ok(());
+        if let Some((stop_timer, timer)) = self.timer.take() {
+            let _ = stop_timer.send(());
+            result = match timer.await {
+                Ok(timer_result) => timer_result.context("timer failed"),
+                Err(e) => Err(anyhow!("timer failed: {e}")),
+            };
+        }
+        for (name, task) in self.tasks.drain(..) {
+            // 还在运行的任务直接结束，只收集已经结束的任务的错误
+            if !task.is_finished() {
+                task.abort();
+                continue;
+            }
+            let task_result = match task.await {
+                Ok(task_result) => task_result,
+                Err(e) => Err(anyhow!(e)),
+            };
+            if let (Ok(()), Err(e)) = (&result, task_result) {
+                result = Err(e.context(format!("{name} failed")));
+            }
+        }
+        self.bus.close();
+        result
+    }
+
+    fn receiver(&self, output: &str) -> Result<&flume::Receiver<Envelope>> {
+        let topic = self.descriptor.output_topic(output)?;
+        self.outputs
+            .get(&topic)
+            .ok_or_else(|| anyhow!("output `{output}` is not subscribed"))
+    }
+}
+
+impl Drop for RunningDataflow {
+    fn drop(&mut self) {
+        // 关闭总线后阻塞接收的线程才能退出，测试的运行时才能结束
+        self.bus.close();
+        // 模拟时钟是进程级别的，恢复系统时间，避免影响同一进程中之后的测试
+        // stop 消耗 self，同样会执行到这里
+        if self.clock.is_some() {
+            clock::reset();
+        }
+        for (_, task) in &self.tasks {
+            task.abort();
+        }
+        if let Some((_, timer)) = &self.timer {
+            timer.abort();
+        }
+    }
+}
+
+/// 测试使用的描述文件，位于 `fixtures` 目录
+#[cfg(test)]
+pub(crate) fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures")
+        .join(name)
+}
+
+/// 读取 `fixtures` 中的描述文件，其中的 `source` 节点由不产生输出的 mock 替换
+#[cfg(test)]
+pub(crate) fn fixture_harness(name: &str) -> Harness {
+    Harness::load(fixture(name))
+        .unwrap()
+        .mock("source", Mock::silent())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_harness() {
+        let dataflow = fixture_harness("doubler.yml")
+            .mock(
+                "sink",
+                Mock::new(|input, data| {
+                    vec![("seen".to_string(), [input.as_bytes(), b":", data].concat())]
+                }),
+            )
+            .start()
+            .await
+            .unwrap();
+
+        dataflow.inject("source/value", "21").unwrap();
+        dataflow.expect_eq("doubler/doubled", "42").await.unwrap();
+        dataflow.expect_eq("sink/seen", "doubled:42").await.unwrap();
+        dataflow
+            .expect_silence("sink/seen", Duration::from_millis(100))
+            .await
+            .unwrap();
+
+        assert!(dataflow.inject("doubler/unknown", "1").is_err());
+        assert!(dataflow.expect("sink").await.is_err());
+        assert!(dataflow.advance(Duration::from_secs(1)).await.is_err());
+        dataflow.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_simulated_clock() {
+        let dataflow = Harness::load(fixture("clock.yml"))
+            .unwrap()
+            .start()
+            .await
+            .unwrap();
+        let batch_len = |data: Vec<u8>| {
+            serde_json::from_slice::<serde_json::Value>(&data)
+                .unwrap()
+                .as_array()
+                .map_or(0, Vec::len)
+        };
+
+        // 推进1秒，millis/100 在 0ms 到 1000ms 之间触发11次，secs/5 在开始时触发一次
+        let start = dataflow.advance(Duration::ZERO).await.unwrap();
+        let now = dataflow.advance(Duration::from_secs(1)).await.unwrap();
+        assert_eq!(now.duration_since(start).unwrap(), Duration::from_secs(1));
+        assert_eq!(batch_len(dataflow.expect("fast/out").await.unwrap()), 10);
+        dataflow.expect("slow/out").await.unwrap();
+        for output in ["fast/out", "slow/out"] {
+            dataflow
+                .expect_silence(output, Duration::from_millis(100))
+                .await
+                .unwrap();
+        }
+
+        // 再推进4秒，累计触发51次，输出第2到第5批，secs/5 在5秒时再触发一次
+        dataflow.advance(Duration::from_secs(4)).await.unwrap();
+        for _ in 0..4 {
+            assert_eq!(batch_len(dataflow.expect("fast/out").await.unwrap()), 10);
+        }
+        dataflow.expect("slow/out").await.unwrap();
+        for output in ["fast/out", "slow/out"] {
+            dataflow
+                .expect_silence(output, Duration::from_millis(100))
+                .await
+                .unwrap();
+        }
+        // 停止之后恢复系统时间，不影响之后的测试
+        assert!(clock::is_simulated());
+        dataflow.stop().await.unwrap();
+        assert!(!clock::is_simulated());
+    }
+
+    #[tokio::test]
+    async fn test_simulated_windows() {
+        let dataflow = fixture_harness("windows.yml").start().await.unwrap();
+        let seq = |data: Vec<u8>| {
+            serde_json::from_slice::<serde_json::Value>(&data).unwrap()["seq"].clone()
+        };
+
+        // 1秒内的11个tick中，只有0ms和1000ms的tick通过throttle
+        dataflow.advance(Duration::from_secs(1)).await.unwrap();
+        assert_eq!(seq(dataflow.expect("limiter/out").await.unwrap()), 0);
+        assert_eq!(seq(dataflow.expect("limiter/out").await.unwrap()), 10);
+
+        // 注入的消息在模拟时间经过1秒之后才通过debounce，与实际经过的时间无关
+        dataflow.inject("source/data", "a").unwrap();
+        dataflow.advance(Duration::from_millis(500)).await.unwrap();
+        dataflow
+            .expect_silence("quiet/out", Duration::from_millis(100))
+            .await
+            .unwrap();
+        dataflow.advance(Duration::from_millis(500)).await.unwrap();
+        dataflow.expect_eq("quiet/out", "a").await.unwrap();
+        assert_eq!(seq(dataflow.expect("limiter/out").await.unwrap()), 20);
+        dataflow
+            .expect_silence("limiter/out", Duration::from_millis(100))
+            .await
+            .unwrap();
+        dataflow.stop().await.unwrap();
+    }
+}
@@ -1,54 +1,593 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
-use crate::descriptor::descriptor::NormalNode;
-use anyhow::{anyhow, Context, Result};
-use log::{debug, info};
-use tokio::process::Command;
+use crate::{
+    cache::{cache_dir, file_digest},
+    descriptor::descriptor::{BuildCommand, NormalNode},
+    shell_command,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use futures::{stream::FuturesUnordered, StreamExt};
+use log::{debug, info, warn};
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+};
+
+/// 构建失败时，错误信息中包含的输出行数
+const BUILD_OUTPUT_TAIL: usize = 50;
+
+/// 一个构建任务，相同的构建命令只会执行一次
+#[derive(Debug)]
+pub(crate) struct BuildJob {
+    /// 构建命令
+    command: BuildCommand,
+    /// 构建时的环境变量，来自节点和operator
+    envs: BTreeMap<String, String>,
+    /// 使用该构建命令的operator，格式为 node/operator
+    operators: Vec<String>,
+    /// 声明的输入文件，为空时每次都会构建
+    inputs: BTreeSet<String>,
+    /// 需要先完成的构建任务
+    deps: BTreeSet<usize>,
+}
+
+/// 构建任务的结果
+#[derive(Debug)]
+enum BuildStatus {
+    /// 执行了构建命令
+    Built(Duration),
+    /// 输入没有变化，跳过了构建
+    UpToDate,
+    /// 构建失败
+    Failed(Duration, String),
+    /// 依赖的构建失败，没有执行
+    Skipped,
+}
+
+impl BuildStatus {
+    fn is_done(&self) -> bool {
+        matches!(self, BuildStatus::Built(_) | BuildStatus::UpToDate)
+    }
+}
+
+impl std::fmt::Display for BuildStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildStatus::Built(elapsed) => write!(f, "built in {:.2}s", elapsed.as_secs_f64()),
+            BuildStatus::UpToDate => write!(f, "up to date"),
+            BuildStatus::Failed(elapsed, _) => {
+                write!(f, "failed after {:.2}s", elapsed.as_secs_f64())
+            }
+            BuildStatus::Skipped => write!(f, "skipped, dependency failed"),
+        }
+    }
+}
 
 /// 构建一个节点，执行该节点所有operator的构建命令
 pub async fn build(node: &NormalNode, working_dir: &PathBuf) -> Result<()> {
     info!("Build Node {:#?} ", node.id);
-    for operator in &node.kind.operators {
-        debug!(
-            "Build `{}/{}` Command: {:#?}",
-            node.id,
-            operator.id,
-            operator.config.build.as_deref()
-        );
-        run_build_command(operator.config.build.as_deref(), working_dir)
-            .await
-            .with_context(|| {
-                format!(
-                    "build command failed for operator `{}/{}`",
-                    node.id, operator.id
-                )
-            })?;
-    }
+    build_dataflow(std::slice::from_ref(node), working_dir, None).await?;
     info!("Build Node Success");
     Ok(())
 }
 
-/// 运行构建的命令
-async fn run_build_command(build: Option<&str>, working_dir: &Path) -> Result<()> {
-    if let Some(build) = build {
-        let mut split = build.split_whitespace();
-        let mut cmd = Command::new(
-            split
-                .next()
-                .ok_or_else(|| anyhow!("build command is empty"))?,
+/// 构建整个dataflow
+/// 相同的构建命令只执行一次，`build_deps` 中声明的构建先完成
+/// 没有依赖关系的构建并行执行，最多同时执行 jobs 个，默认为cpu的数量
+pub async fn build_dataflow(
+    nodes: &[NormalNode],
+    working_dir: &Path,
+    jobs: Option<usize>,
+) -> Result<()> {
+    let build_jobs = collect_jobs(nodes)?;
+    if build_jobs.is_empty() {
+        return Ok(());
+    }
+    let concurrency = jobs
+        .or_else(|| std::thread::available_parallelism().map(|n| n.get()).ok())
+        .unwrap_or(1)
+        .max(1);
+    info!(
+        "Build {} command(s) with up to {concurrency} in parallel",
+        build_jobs.len()
+    );
+    let status = run_jobs(&build_jobs, working_dir, concurrency).await?;
+
+    // 输出每一个operator的构建结果
+    let mut errors = vec![];
+    for (job, status) in build_jobs.iter().zip(&status) {
+        for operator in &job.operators {
+            println!("build {operator:<32} {status}");
+        }
+        if let BuildStatus::Failed(_, error) = status {
+            errors.push(format!(
+                "build command `{}` failed for operator {}: {error}",
+                job.command,
+                job.operators.join(", ")
+            ));
+        }
+    }
+    if !errors.is_empty() {
+        bail!("{}", errors.join("\n"));
+    }
+    Ok(())
+}
+
+/// 收集所有的构建任务，以及它们之间的依赖关系
+/// 依赖只来自 `build_deps`，数据流中的上下游关系不影响构建的顺序
+/// 相同的构建命令合并为一个任务，但合并之后任务之间不能互相依赖，否则分为多个任务
+pub(crate) fn collect_jobs(nodes: &[NormalNode]) -> Result<Vec<BuildJob>> {
+    // 声明的依赖，以及需要构建的operator，保持描述文件中的顺序
+    let mut declared: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let mut builds = vec![];
+    for node in nodes {
+        for operator in &node.kind.operators {
+            let key = format!("{}/{}", node.id, operator.id);
+            let mut deps = BTreeSet::new();
+            for dep in operator.config.build_deps.iter().flatten() {
+                let matched = resolve_build_dep(nodes, dep);
+                if matched.is_empty() {
+                    bail!("unknown operator `{dep}` in build_deps of `{key}`");
+                }
+                deps.extend(matched);
+            }
+            declared.insert(key.clone(), deps);
+            if let Some(command) = operator.config.build.as_ref().filter(|c| !c.is_empty()) {
+                // 与运行时一致，节点的环境变量覆盖operator的环境变量
+                let envs: BTreeMap<String, String> = operator
+                    .config
+                    .envs
+                    .iter()
+                    .chain(node.envs.iter())
+                    .flatten()
+                    .map(|(k, v)| (k.clone(), v.to_string()))
+                    .collect();
+                let inputs = operator.config.build_inputs.iter().flatten().cloned();
+                builds.push((key, command, envs, inputs.collect::<Vec<_>>()));
+            }
+        }
+    }
+
+    // 每个需要构建的operator依赖的需要构建的operator，不需要构建的operator的依赖会传递下去
+    let building: BTreeSet<&String> = builds.iter().map(|(key, ..)| key).collect();
+    let mut deps_of: BTreeMap<&String, BTreeSet<&String>> = BTreeMap::new();
+    for (key, ..) in &builds {
+        let mut deps = BTreeSet::new();
+        let mut visited = BTreeSet::new();
+        let mut stack = vec![key];
+        while let Some(current) = stack.pop() {
+            for dep in declared.get(current).into_iter().flatten() {
+                if !visited.insert(dep) {
+                    continue;
+                }
+                match building.contains(dep) {
+                    true => {
+                        deps.insert(dep);
+                    }
+                    false => stack.push(dep),
+                }
+            }
+        }
+        deps_of.insert(key, deps);
+    }
+
+    // 按照依赖的顺序分配任务，分配一个operator时它依赖的operator都已经分配
+    let mut jobs: Vec<BuildJob> = vec![];
+    let mut job_of: BTreeMap<&String, usize> = BTreeMap::new();
+    while job_of.len() < builds.len() {
+        let Some((key, command, envs, inputs)) = builds.iter().find(|(key, ..)| {
+            !job_of.contains_key(key) && deps_of[key].iter().all(|dep| job_of.contains_key(dep))
+        }) else {
+            let cycle = builds
+                .iter()
+                .map(|(key, ..)| key)
+                .filter(|key| !job_of.contains_key(key))
+                .map(|key| format!("`{key}`"))
+                .collect::<Vec<_>>();
+            bail!("build_deps of {} depend on each other", cycle.join(", "));
+        };
+        let deps: BTreeSet<usize> = deps_of[key].iter().map(|dep| job_of[dep]).collect();
+        // 合并到相同命令的任务中，合并之后不能形成环
+        let index = jobs.iter().enumerate().position(|(index, job)| {
+            &job.command == *command
+                && &job.envs == envs
+                && !deps
+                    .iter()
+                    .any(|&dep| dep != index && depends_on(&jobs, dep, index))
+        });
+        let index = match index {
+            Some(index) => index,
+            None => {
+                jobs.push(BuildJob {
+                    command: (*command).clone(),
+                    envs: envs.clone(),
+                    operators: vec![],
+                    inputs: BTreeSet::new(),
+                    deps: BTreeSet::new(),
+                });
+                jobs.len() - 1
+            }
+        };
+        let job = &mut jobs[index];
+        job.operators.push(key.clone());
+        job.inputs.extend(inputs.iter().cloned());
+        job.deps
+            .extend(deps.into_iter().filter(|&dep| dep != index));
+        job_of.insert(key, index);
+    }
+    Ok(jobs)
+}
+
+/// `build_deps` 中的一项对应的operator，`node` 表示节点中所有的operator
+fn resolve_build_dep(nodes: &[NormalNode], dep: &str) -> Vec<String> {
+    let (node_id, operator_id) = match dep.split_once('/') {
+        Some((node_id, operator_id)) => (node_id, Some(operator_id)),
+        None => (dep, None),
+    };
+    nodes
+        .iter()
+        .filter(|node| node.id.as_str() == node_id)
+        .flat_map(|node| node.kind.operators.iter())
+        .filter(|operator| operator_id.is_none_or(|id| operator.id.as_str() == id))
+        .map(|operator| format!("{node_id}/{}", operator.id))
+        .collect()
+}
+
+/// 任务 from 是否直接或间接依赖任务 to
+fn depends_on(jobs: &[BuildJob], from: usize, to: usize) -> bool {
+    let mut visited = BTreeSet::new();
+    let mut stack = vec![from];
+    while let Some(current) = stack.pop() {
+        if current == to {
+            return true;
+        }
+        if visited.insert(current) {
+            stack.extend(jobs[current].deps.iter().copied());
+        }
+    }
+    false
+}
+
+/// 按照依赖关系执行所有的构建任务
+/// 依赖失败的任务会被跳过
+async fn run_jobs(
+    jobs: &[BuildJob],
+    working_dir: &Path,
+    concurrency: usize,
+) -> Result<Vec<BuildStatus>> {
+    let mut status: Vec<Option<BuildStatus>> = jobs.iter().map(|_| None).collect();
+    let mut started = vec![false; jobs.len()];
+    let mut running = FuturesUnordered::new();
+    loop {
+        // 一直调度到没有新的任务可以开始
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (index, job) in jobs.iter().enumerate() {
+                if started[index] {
+                    continue;
+                }
+                let finished = |dep: &usize| status[*dep].is_some();
+                let done = |dep: &usize| status[*dep].as_ref().is_some_and(BuildStatus::is_done);
+                if job.deps.iter().any(|dep| finished(dep) && !done(dep)) {
+                    started[index] = true;
+                    status[index] = Some(BuildStatus::Skipped);
+                    changed = true;
+                } else if running.len() < concurrency && job.deps.iter().all(done) {
+                    started[index] = true;
+                    changed = true;
+                    info!("Build `{}` for {}", job.command, job.operators.join(", "));
+                    running.push(async move { (index, run_job(job, working_dir).await) });
+                }
+            }
+        }
+        match running.next().await {
+            Some((index, result)) => {
+                debug!("Build `{}` {result}", jobs[index].command);
+                status[index] = Some(result);
+            }
+            None => break,
+        }
+    }
+    if started.iter().any(|started| !started) {
+        bail!(
+            "build commands depend on each other: {}",
+            jobs.iter()
+                .zip(&started)
+                .filter(|(_, started)| !**started)
+                .map(|(job, _)| format!("`{}`", job.command))
+                .collect::<Vec<_>>()
+                .join(", ")
         );
-        cmd.args(split);
-        cmd.current_dir(working_dir);
-        let exit_status = cmd
-            .status()
-            .await
-            .with_context(|| format!("failed to run `{}`", build))?;
-        if exit_status.success() {
-            Ok(())
-        } else {
-            Err(anyhow!("build command returned an error code"))
+    }
+    Ok(status.into_iter().flatten().collect())
+}
+
+/// 执行一个构建任务，输入没有变化时跳过
+async fn run_job(job: &BuildJob, working_dir: &Path) -> BuildStatus {
+    let start = Instant::now();
+    let fingerprint = match fingerprint(job, working_dir) {
+        Ok(fingerprint) => fingerprint,
+        Err(e) => return BuildStatus::Failed(start.elapsed(), format!("{e:#}")),
+    };
+    let stamp = stamp_path(working_dir, job);
+    if let Some(fingerprint) = &fingerprint {
+        if std::fs::read_to_string(&stamp).ok().as_ref() == Some(fingerprint) {
+            return BuildStatus::UpToDate;
         }
-    } else {
+    }
+    let label = job.operators.join(",");
+    match run_build_command(job, working_dir, &label).await {
+        Ok(()) => {
+            if let Some(fingerprint) = fingerprint {
+                if let Err(e) = write_stamp(&stamp, &fingerprint) {
+                    warn!("failed to record build of `{}`: {e:#}", job.command);
+                }
+            }
+            BuildStatus::Built(start.elapsed())
+        }
+        Err(e) => BuildStatus::Failed(start.elapsed(), format!("{e:#}")),
+    }
+}
+
+/// 构建任务的唯一标识，包括构建命令和环境变量
+fn job_key(job: &BuildJob) -> String {
+    serde_json::to_string(&(&job.command, &job.envs)).expect("failed to serialize build job")
+}
+
+/// 记录构建任务上一次成功构建时的指纹
+/// 放在缓存目录下，按dataflow所在目录区分，不写入用户的工作目录
+fn stamp_path(working_dir: &Path, job: &BuildJob) -> PathBuf {
+    let dataflow = working_dir.to_string_lossy();
+    cache_dir()
+        .join("stamps")
+        .join(hex::encode(Sha256::digest(dataflow.as_bytes())))
+        .join(hex::encode(Sha256::digest(job_key(job).as_bytes())))
+}
+
+fn write_stamp(stamp: &Path, fingerprint: &str) -> Result<()> {
+    if let Some(parent) = stamp.parent() {
+        std::fs::create_dir_all(parent).context("failed to create stamp dir")?;
+    }
+    std::fs::write(stamp, fingerprint).context("failed to write stamp")
+}
+
+/// 计算构建任务的指纹，包括构建命令和所有输入文件的内容
+/// 没有声明输入时返回None，表示每次都需要构建
+fn fingerprint(job: &BuildJob, working_dir: &Path) -> Result<Option<String>> {
+    if job.inputs.is_empty() {
+        return Ok(None);
+    }
+    let base = glob::Pattern::escape(
+        working_dir
+            .to_str()
+            .ok_or_else(|| anyhow!("working dir is not valid UTF8"))?,
+    );
+    let mut files = BTreeSet::new();
+    for input in &job.inputs {
+        let pattern = format!("{base}/{input}");
+        for entry in glob::glob(&pattern)
+            .with_context(|| format!("invalid build input pattern `{input}`"))?
+        {
+            let path = entry.context("failed to read build input")?;
+            if path.is_file() {
+                files.insert(path);
+            }
+        }
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(job_key(job).as_bytes());
+    for file in files {
+        hasher.update(file.to_string_lossy().as_bytes());
+        hasher.update(file_digest(&file)?.as_bytes());
+    }
+    Ok(Some(hex::encode(hasher.finalize())))
+}
+
+/// 运行构建的命令，输出的每一行都加上operator作为前缀
+/// 失败时错误信息中包含最后的输出
+async fn run_build_command(job: &BuildJob, working_dir: &Path, label: &str) -> Result<()> {
+    let mut cmd = match &job.command {
+        BuildCommand::Shell(script) => shell_command(script, &[]),
+        BuildCommand::Structured(build) => {
+            let (program, args) = build
+                .cmd
+                .split_first()
+                .ok_or_else(|| anyhow!("build command is empty"))?;
+            let mut cmd = Command::new(program);
+            cmd.args(args);
+            cmd
+        }
+    };
+    cmd.envs(&job.envs);
+    match &job.command {
+        BuildCommand::Structured(build) => {
+            for (k, v) in build.envs.iter().flatten() {
+                cmd.env(k, v.to_string());
+            }
+            cmd.current_dir(match &build.cwd {
+                Some(cwd) => working_dir.join(cwd),
+                None => working_dir.to_owned(),
+            });
+        }
+        BuildCommand::Shell(_) => {
+            cmd.current_dir(working_dir);
+        }
+    }
+    debug!("Build command {:?}", cmd);
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run `{}`", job.command))?;
+    let stdout = child.stdout.take().expect("failed to take stdout");
+    let stderr = child.stderr.take().expect("failed to take stderr");
+    // 保留最后的输出，用于失败时的错误信息
+    let tail = Mutex::new(VecDeque::with_capacity(BUILD_OUTPUT_TAIL));
+    let keep = |line: &str| {
+        let mut tail = tail.lock().unwrap();
+        if tail.len() == BUILD_OUTPUT_TAIL {
+            tail.pop_front();
+        }
+        tail.push_back(line.to_owned());
+    };
+    let forward_stdout = async {
+        let mut lines = BufReader::new(stdout).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            println!("[{label}] {line}");
+            keep(&line);
+        }
+    };
+    let forward_stderr = async {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            eprintln!("[{label}] {line}");
+            keep(&line);
+        }
+    };
+    let (exit_status, _, _) = tokio::join!(child.wait(), forward_stdout, forward_stderr);
+    let exit_status = exit_status.with_context(|| format!("failed to run `{}`", job.command))?;
+    if exit_status.success() {
         Ok(())
+    } else {
+        let tail = tail.into_inner().unwrap();
+        Err(anyhow!(
+            "build command returned {exit_status}{}",
+            if tail.is_empty() {
+                String::new()
+            } else {
+                format!(
+                    ", last {} line(s) of output:\n{}",
+                    tail.len(),
+                    Vec::from(tail).join("\n")
+                )
+            }
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptor::descriptor::Descriptor;
+
+    #[test]
+    fn test_collect_jobs() {
+        let descriptor: Descriptor = serde_yaml::from_str(
+            r#"
+version: 1.0
+deploy:
+  endpoints: ["tcp/127.0.0.1:7447"]
+nodes:
+  - id: source
+    shell: ./source
+    build: cargo build -p source
+    outputs: [data]
+  - id: filter
+    shell: ./filter
+    build_deps: [source]
+    inputs:
+      data: source/data
+    outputs: [data]
+  - id: sink
+    shell: ./sink
+    build: cargo build -p sink
+    build_deps: [filter/filter]
+    inputs:
+      data: filter/data
+  - id: other
+    shell: ./other
+    build: cargo build -p source
+    outputs: [data]
+  - id: structured
+    shell: ./structured
+    build:
+      cmd: [make, CFLAGS=-O2 -g]
+      cwd: sub
+      envs:
+        DEBUG: 1
+"#,
+        )
+        .unwrap();
+        let jobs = collect_jobs(&descriptor.resolve_node_defaults()).unwrap();
+        assert_eq!(jobs.len(), 3);
+        // 相同的构建命令只会执行一次
+        assert_eq!(jobs[0].operators, vec!["source/source", "other/other"]);
+        // 依赖会经过不需要构建的operator传递
+        assert_eq!(jobs[1].deps, BTreeSet::from([0]));
+        assert_eq!(
+            jobs[2].command.to_string(),
+            r#"make "CFLAGS=-O2 -g" (in sub)"#
+        );
+    }
+
+    fn jobs_of(yaml: &str) -> Result<Vec<BuildJob>> {
+        let descriptor: Descriptor = serde_yaml::from_str(yaml).unwrap();
+        collect_jobs(&descriptor.resolve_node_defaults())
+    }
+
+    #[test]
+    fn test_collect_jobs_without_cycle() {
+        // 数据流中的上下游关系不影响构建，x 和 z 的构建命令相同
+        let jobs = jobs_of(
+            r#"
+version: 1.0
+nodes:
+  - { id: x, shell: ./x, build: make a, outputs: [data] }
+  - { id: y, shell: ./y, build: make b, inputs: { data: x/data }, outputs: [data] }
+  - { id: z, shell: ./z, build: make a, inputs: { data: y/data } }
+"#,
+        )
+        .unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].operators, vec!["x/x", "z/z"]);
+        assert!(jobs.iter().all(|job| job.deps.is_empty()));
+
+        // 合并 x 和 z 会导致 a -> b -> a，因此不合并
+        let jobs = jobs_of(
+            r#"
+version: 1.0
+nodes:
+  - { id: x, shell: ./x, build: make a, build_deps: [y] }
+  - { id: y, shell: ./y, build: make b, build_deps: [z] }
+  - { id: z, shell: ./z, build: make a }
+"#,
+        )
+        .unwrap();
+        assert_eq!(jobs.len(), 3);
+        assert_eq!(jobs[0].operators, vec!["z/z"]);
+        assert_eq!(jobs[1].deps, BTreeSet::from([0]));
+        assert_eq!(jobs[2].deps, BTreeSet::from([1]));
+
+        let error = jobs_of(
+            r#"
+version: 1.0
+nodes:
+  - { id: x, shell: ./x, build: make a, build_deps: [y] }
+  - { id: y, shell: ./y, build: make b, build_deps: [x] }
+  - { id: z, shell: ./z, build: make a, build_deps: [nowhere] }
+"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("unknown operator `nowhere`"));
+
+        let error = jobs_of(
+            r#"
+version: 1.0
+nodes:
+  - { id: x, shell: ./x, build: make a, build_deps: [y] }
+  - { id: y, shell: ./y, build: make b, build_deps: [x] }
+"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("depend on each other"));
     }
 }
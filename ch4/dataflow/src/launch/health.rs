@@ -0,0 +1,174 @@
+use std::time::Instant;
+
+use crate::{
+    communication::{pub_sub::ZenohCommunicationLayer, PubSubCommunicationLayer},
+    descriptor::descriptor::{HumanDuration, NormalNode},
+    runtime::{HEARTBEAT_LINE, READY_LINE},
+};
+use anyhow::{anyhow, bail, Result};
+use log::{error, info, warn};
+
+/// 节点通过标准输出发送给launch的信号
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum NodeSignal {
+    Ready,
+    Heartbeat,
+}
+
+impl NodeSignal {
+    /// 识别输出行中的信号，信号行不会写入日志
+    pub(crate) fn parse(line: &str) -> Option<NodeSignal> {
+        match line.trim() {
+            READY_LINE => Some(NodeSignal::Ready),
+            HEARTBEAT_LINE => Some(NodeSignal::Heartbeat),
+            _ => None,
+        }
+    }
+}
+
+/// 订阅节点的所有输出，收到第一条数据时发送就绪信号
+/// 需要在节点启动之前调用，避免错过第一条输出
+pub(crate) fn watch_first_output(
+    node: &NormalNode,
+    signals: flume::Sender<NodeSignal>,
+) -> Result<()> {
+    let endpoints = node
+        .deploy
+        .endpoints
+        .clone()
+        .ok_or_else(|| anyhow!("node has no endpoints defined"))?;
+    let mut communication = ZenohCommunicationLayer::init(
+        endpoints,
+        "peer".to_string(),
+        format!("dataflow/launch/{}", node.id),
+    )?;
+    // 节点所有operator的输出都以节点id为前缀
+    let topic = format!("{}/**", node.id);
+    let mut subscriber = communication
+        .subscribe(&topic)
+        .map_err(|e| anyhow!("failed to subscribe topic:{topic}: {e}"))?;
+    // 接收者是阻塞的，使用独立的线程，避免节点一直没有输出时阻塞运行时的退出
+    std::thread::spawn(move || {
+        let _communication = communication;
+        if let Ok(Some(_)) = subscriber.recv() {
+            let _ = signals.send(NodeSignal::Ready);
+        }
+    });
+    Ok(())
+}
+
+/// 等待节点就绪，超时或者节点在就绪前退出时返回错误
+/// 没有设置就绪检查的节点直接返回
+pub(crate) async fn wait_ready(
+    node: &NormalNode,
+    signals: &flume::Receiver<NodeSignal>,
+    handle: &mut tokio::task::JoinHandle<Result<()>>,
+) -> Result<()> {
+    let Some(readiness) = &node.readiness else {
+        return Ok(());
+    };
+    let timeout = readiness.timeout();
+    info!(
+        "Waiting for node {} to be ready (signal: {}, timeout: {})",
+        node.id,
+        readiness.signal,
+        HumanDuration(timeout)
+    );
+    let started = Instant::now();
+    let ready = async {
+        loop {
+            match signals.recv_async().await {
+                Ok(NodeSignal::Ready) => return true,
+                Ok(NodeSignal::Heartbeat) => continue,
+                // 节点的输出已经关闭，说明节点正在退出
+                Err(_) => return false,
+            }
+        }
+    };
+    tokio::select! {
+        ready = ready => {
+            if ready {
+                info!("node {} is ready after {:?}", node.id, started.elapsed());
+                return Ok(());
+            }
+            let result = handle.await;
+            bail!("node `{}` exited before it became ready: {}", node.id, describe_exit(result))
+        }
+        result = &mut *handle => {
+            bail!("node `{}` exited before it became ready: {}", node.id, describe_exit(result))
+        }
+        _ = tokio::time::sleep(timeout) => {
+            bail!(
+                "node `{}` did not become ready within {} (readiness signal: {}), see `ctl logs --node {}`",
+                node.id,
+                HumanDuration(timeout),
+                readiness.signal,
+                node.id
+            )
+        }
+    }
+}
+
+/// 节点退出原因的描述
+fn describe_exit(result: Result<Result<()>, tokio::task::JoinError>) -> String {
+    match result {
+        Ok(Ok(())) => "it finished without reporting ready".to_owned(),
+        Ok(Err(e)) => format!("{e:#}"),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// 检查节点的心跳，超时没有收到心跳时报告节点失去响应，恢复后再次报告
+/// 没有设置存活检查的节点直接返回
+pub(crate) fn watch_liveness(node: &NormalNode, signals: flume::Receiver<NodeSignal>) {
+    let Some(liveness) = &node.liveness else {
+        return;
+    };
+    let timeout = liveness.timeout();
+    let node_id = node.id.clone();
+    tokio::spawn(async move {
+        let mut lost = false;
+        loop {
+            match tokio::time::timeout(timeout, signals.recv_async()).await {
+                Ok(Ok(_)) => {
+                    if lost {
+                        info!("node {node_id} is alive again");
+                        lost = false;
+                    }
+                }
+                // 节点的输出已经关闭，节点已经退出
+                Ok(Err(_)) => break,
+                Err(_) => {
+                    if !lost {
+                        error!(
+                            "node {node_id} missed heartbeats for {}, it may be unresponsive",
+                            HumanDuration(timeout)
+                        );
+                        lost = true;
+                    }
+                }
+            }
+        }
+        if lost {
+            warn!("node {node_id} exited while unresponsive");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(
+            NodeSignal::parse("@dataflow:ready\n"),
+            Some(NodeSignal::Ready)
+        );
+        assert_eq!(
+            NodeSignal::parse("@dataflow:heartbeat"),
+            Some(NodeSignal::Heartbeat)
+        );
+        assert_eq!(NodeSignal::parse("ready"), None);
+    }
+}
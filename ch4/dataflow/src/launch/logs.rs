@@ -0,0 +1,528 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Instant,
+};
+
+use crate::{
+    descriptor::{
+        descriptor::{Descriptor, LogFormat, LogRotation},
+        expand::Overrides,
+    },
+    runtime::actuator::OPERATOR_LOG_PREFIX,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use log::Level;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::{fs::File, io::AsyncWriteExt};
+
+/// 节点日志目录中当前正在写入的文件名，轮转后的文件为 `node.log.1`、`node.log.2` ...
+pub const LOG_FILE_NAME: &str = "node.log";
+
+/// 日志来自子进程的哪一个输出流
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+impl fmt::Display for LogStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogStream::Stdout => write!(f, "stdout"),
+            LogStream::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+/// 一条结构化的日志记录，日志文件中每行一条json
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogRecord {
+    /// rfc3339 格式的时间
+    pub timestamp: String,
+    pub node: String,
+    /// 由operator输出时的operator id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operator: Option<String>,
+    pub stream: LogStream,
+    /// 能从日志中识别出级别时的级别
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<String>,
+    pub message: String,
+    /// json 日志中除了级别和消息以外的字段
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Map<String, Value>>,
+}
+
+impl LogRecord {
+    /// 从子进程的一段输出构造日志记录，识别日志级别
+    pub fn new(
+        node: &str,
+        stream: LogStream,
+        operator: Option<String>,
+        message: &str,
+    ) -> LogRecord {
+        let message = message.trim_end();
+        LogRecord {
+            timestamp: humantime::format_rfc3339_millis(std::time::SystemTime::now()).to_string(),
+            node: node.to_owned(),
+            operator,
+            stream,
+            level: parse_level(message).map(|level| level.to_string()),
+            message: message.to_owned(),
+            fields: None,
+        }
+    }
+
+    /// 解析一行json日志，消息和级别从常用的字段中读取，其余字段原样保留
+    /// 不是json对象时返回None
+    pub fn from_json(
+        node: &str,
+        stream: LogStream,
+        operator: Option<String>,
+        line: &str,
+    ) -> Option<LogRecord> {
+        let Ok(Value::Object(mut fields)) = serde_json::from_str(line) else {
+            return None;
+        };
+        let mut take = |keys: &[&str]| {
+            keys.iter().find_map(|key| match fields.remove(*key)? {
+                Value::String(value) => Some(value),
+                value => Some(value.to_string()),
+            })
+        };
+        let message = take(&["message", "msg"]).unwrap_or_default();
+        let level = take(&["level", "severity", "levelname"])
+            .and_then(|level| Level::from_str(&level).ok())
+            .map(|level| level.to_string());
+        let mut record = LogRecord::new(node, stream, operator, &message);
+        record.level = level;
+        record.fields = (!fields.is_empty()).then_some(fields);
+        Some(record)
+    }
+
+    /// 日志的来源，`node` 或者 `node/operator`
+    pub fn source(&self) -> String {
+        match &self.operator {
+            Some(operator) => format!("{}/{operator}", self.node),
+            None => self.node.clone(),
+        }
+    }
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:<5} {} {}: {}",
+            self.timestamp,
+            self.level.as_deref().unwrap_or("-"),
+            self.source(),
+            self.stream,
+            self.message
+        )?;
+        for (key, value) in self.fields.iter().flatten() {
+            write!(f, " {key}={value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// 拆分operator转发的输出行前缀，得到operator id和原始的行
+pub(crate) fn split_operator(line: &str) -> (Option<&str>, &str) {
+    match line
+        .strip_prefix(OPERATOR_LOG_PREFIX)
+        .and_then(|rest| rest.split_once("] "))
+    {
+        Some((operator, rest)) => (Some(operator), rest),
+        None => (None, line),
+    }
+}
+
+/// 正在合并的多行日志
+struct PendingRecord {
+    operator: Option<String>,
+    lines: Vec<String>,
+    /// 是否是python的traceback，需要合并到最后的异常行为止
+    traceback: bool,
+}
+
+/// 将子进程的输出行合并为日志记录
+/// 缩进的行、空行以及 `Caused by:` 等行属于上一条日志，python 的 traceback 合并到最后的异常行
+pub(crate) struct LineGrouper {
+    node: String,
+    stream: LogStream,
+    format: LogFormat,
+    pending: Option<PendingRecord>,
+}
+
+impl LineGrouper {
+    pub(crate) fn new(node: &str, stream: LogStream, format: LogFormat) -> LineGrouper {
+        LineGrouper {
+            node: node.to_owned(),
+            stream,
+            format,
+            pending: None,
+        }
+    }
+
+    /// 处理一行输出，返回已经完整的日志记录
+    pub(crate) fn push(&mut self, line: &str) -> Vec<LogRecord> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (operator, body) = split_operator(line);
+        let mut records = vec![];
+        if let Some(pending) = &mut self.pending {
+            if pending.operator.as_deref() == operator && is_continuation(body, pending.traceback) {
+                pending.lines.push(body.to_owned());
+                // traceback 中没有缩进的行是最后的异常信息
+                if pending.traceback && !body.starts_with([' ', '\t']) && !body.is_empty() {
+                    records.extend(self.flush());
+                }
+                return records;
+            }
+            records.extend(self.flush());
+        }
+        if self.format == LogFormat::Json {
+            let operator = operator.map(str::to_owned);
+            if let Some(record) = LogRecord::from_json(&self.node, self.stream, operator, body) {
+                records.push(record);
+                return records;
+            }
+        }
+        self.pending = Some(PendingRecord {
+            operator: operator.map(str::to_owned),
+            lines: vec![body.to_owned()],
+            traceback: body.starts_with("Traceback (most recent call last)"),
+        });
+        records
+    }
+
+    /// 结束正在合并的日志，在输出流空闲或者结束时调用
+    pub(crate) fn flush(&mut self) -> Option<LogRecord> {
+        let pending = self.pending.take()?;
+        let mut record = LogRecord::new(
+            &self.node,
+            self.stream,
+            pending.operator,
+            &pending.lines.join("\n"),
+        );
+        // traceback 中没有级别信息，作为错误处理
+        if pending.traceback {
+            record.level = Some(Level::Error.to_string());
+        }
+        Some(record)
+    }
+}
+
+/// 判断一行是否属于上一条日志
+fn is_continuation(body: &str, traceback: bool) -> bool {
+    traceback
+        || body.is_empty()
+        || body.starts_with([' ', '\t'])
+        || body.starts_with("Caused by:")
+        || body.starts_with("Stack backtrace:")
+}
+
+/// 识别日志的级别，支持 env_logger 的 `[<time> INFO  target] ..` 和以级别开头的行
+fn parse_level(message: &str) -> Option<Level> {
+    let first_line = message.lines().next().unwrap_or_default();
+    let header = match first_line.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => first_line.split([':', ' ']).next().unwrap_or_default(),
+    };
+    header
+        .split_whitespace()
+        .find_map(|token| Level::from_str(token).ok())
+}
+
+/// 支持按大小和时间轮转的日志文件
+pub(crate) struct RotatingLog {
+    dir: PathBuf,
+    rotation: LogRotation,
+    file: File,
+    size: u64,
+    opened_at: Instant,
+}
+
+impl RotatingLog {
+    /// 打开节点的日志目录，上一次运行留下的日志会先被轮转，每次运行从新的文件开始
+    pub(crate) async fn open(dir: &Path, rotation: LogRotation) -> Result<RotatingLog> {
+        check_log_dir(dir)?;
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("failed to create log dir `{}`", dir.display()))?;
+        let current = dir.join(LOG_FILE_NAME);
+        if tokio::fs::metadata(&current)
+            .await
+            .is_ok_and(|metadata| metadata.len() > 0)
+        {
+            rotate_files(dir, rotation.keep()).await?;
+        }
+        Ok(RotatingLog {
+            dir: dir.to_owned(),
+            file: create_file(&current).await?,
+            rotation,
+            size: 0,
+            opened_at: Instant::now(),
+        })
+    }
+
+    /// 写入一条日志，超过限制时先轮转
+    pub(crate) async fn write(&mut self, record: &LogRecord) -> Result<()> {
+        let mut line = serde_json::to_string(record).context("failed to serialize log record")?;
+        line.push('\n');
+        let too_large = self.size > 0 && self.size + line.len() as u64 > self.rotation.max_size().0;
+        let too_old = self
+            .rotation
+            .max_age
+            .is_some_and(|age| self.opened_at.elapsed() >= age.0);
+        if too_large || too_old {
+            self.file.flush().await?;
+            rotate_files(&self.dir, self.rotation.keep()).await?;
+            self.file = create_file(&self.dir.join(LOG_FILE_NAME)).await?;
+            self.size = 0;
+            self.opened_at = Instant::now();
+        }
+        self.file.write_all(line.as_bytes()).await?;
+        self.file.flush().await?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// 检查节点的日志目录，之前的版本中 `deploy.log` 是一个日志文件，现在是目录
+pub(crate) fn check_log_dir(dir: &Path) -> Result<()> {
+    if dir.is_file() {
+        bail!(
+            "log path `{}` is an existing file, `deploy.log` is now a directory \
+             containing rotated log files; remove the file or set `deploy.log` to a directory",
+            dir.display()
+        );
+    }
+    Ok(())
+}
+
+async fn create_file(path: &Path) -> Result<File> {
+    File::create(path)
+        .await
+        .with_context(|| format!("failed to create log file `{}`", path.display()))
+}
+
+/// 将 `node.log.N` 依次后移一位，超过保留数量的文件被删除，最后 `node.log` 变为 `node.log.1`
+async fn rotate_files(dir: &Path, keep: usize) -> Result<()> {
+    let rotated = |index: usize| dir.join(format!("{LOG_FILE_NAME}.{index}"));
+    let _ = tokio::fs::remove_file(rotated(keep.max(1))).await;
+    for index in (1..keep).rev() {
+        let from = rotated(index);
+        if tokio::fs::metadata(&from).await.is_ok() {
+            tokio::fs::rename(&from, rotated(index + 1)).await?;
+        }
+    }
+    let current = dir.join(LOG_FILE_NAME);
+    if keep == 0 {
+        tokio::fs::remove_file(&current).await?;
+    } else {
+        tokio::fs::rename(&current, rotated(1))
+            .await
+            .with_context(|| format!("failed to rotate log file `{}`", current.display()))?;
+    }
+    Ok(())
+}
+
+/// 节点日志目录中的所有日志文件，从旧到新排列
+pub(crate) fn log_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<(usize, PathBuf)> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let index = match name.strip_prefix(LOG_FILE_NAME)? {
+                "" => 0,
+                suffix => suffix.strip_prefix('.')?.parse().ok()?,
+            };
+            Some((index, entry.path()))
+        })
+        .collect();
+    files.sort_by_key(|(index, _)| std::cmp::Reverse(*index));
+    files.into_iter().map(|(_, path)| path).collect()
+}
+
+/// `ctl logs` 的过滤条件
+#[derive(Debug, Default)]
+pub struct LogFilter {
+    pub node: Option<String>,
+    pub operator: Option<String>,
+    pub stream: Option<LogStream>,
+    /// 最低的日志级别，设置后没有级别的记录会被过滤掉
+    pub level: Option<Level>,
+    /// 只输出最后的n条
+    pub tail: Option<usize>,
+}
+
+impl LogFilter {
+    fn matches(&self, record: &LogRecord) -> bool {
+        self.node.as_ref().is_none_or(|node| &record.node == node)
+            && self
+                .operator
+                .as_ref()
+                .is_none_or(|operator| record.operator.as_ref() == Some(operator))
+            && self.stream.is_none_or(|stream| record.stream == stream)
+            && self.level.is_none_or(|min| {
+                record
+                    .level
+                    .as_deref()
+                    .and_then(|level| Level::from_str(level).ok())
+                    .is_some_and(|level| level <= min)
+            })
+    }
+}
+
+/// 读取dataflow所有节点的日志，按照时间排序后输出
+/// json 为 true 时原样输出日志记录
+/// overrides 需要与launch时一致，profile可能修改了日志目录
+pub fn logs(dataflow: PathBuf, filter: LogFilter, json: bool, overrides: &Overrides) -> Result<()> {
+    let mut descriptor = Descriptor::blocking_read_with_overrides(&dataflow, overrides)
+        .with_context(|| format!("failed to read dataflow at `{}`", dataflow.display()))?;
+    let working_dir = dataflow
+        .canonicalize()
+        .context("failed to canonicalize dataflow path")?
+        .parent()
+        .ok_or_else(|| anyhow!("dataflow path has no parent dir"))?
+        .to_owned();
+    descriptor.set_default_log_dir(&dataflow);
+    let nodes = descriptor.resolve_node_defaults();
+    if let Some(node) = &filter.node {
+        if !nodes.iter().any(|n| n.id.as_str() == node) {
+            bail!("node `{node}` is not defined in the dataflow");
+        }
+    }
+
+    let mut records = vec![];
+    for node in &nodes {
+        if filter
+            .node
+            .as_ref()
+            .is_some_and(|id| id != node.id.as_str())
+        {
+            continue;
+        }
+        let dir = working_dir.join(node.deploy.log.clone().unwrap());
+        check_log_dir(&dir)?;
+        for file in log_files(&dir) {
+            let content = std::fs::read_to_string(&file)
+                .with_context(|| format!("failed to read log file `{}`", file.display()))?;
+            for line in content.lines().filter(|line| !line.trim().is_empty()) {
+                let record: LogRecord = serde_json::from_str(line).with_context(|| {
+                    format!("invalid log record in `{}`: {line}", file.display())
+                })?;
+                if filter.matches(&record) {
+                    records.push(record);
+                }
+            }
+        }
+    }
+    // rfc3339 格式的时间可以直接按字符串排序，排序是稳定的，同一时间的记录保持原有顺序
+    records.sort_by_key(|record| record.timestamp.clone());
+    let skip = filter
+        .tail
+        .map_or(0, |tail| records.len().saturating_sub(tail));
+    for record in records.iter().skip(skip) {
+        if json {
+            println!("{}", serde_json::to_string(record)?);
+        } else {
+            println!("{record}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptor::descriptor::ByteSize;
+
+    #[test]
+    fn test_log_record() {
+        let mut grouper = LineGrouper::new("node", LogStream::Stderr, LogFormat::Text);
+        assert!(grouper
+            .push("[operator op] [2024-01-01T00:00:00Z WARN  dataflow] slow\n")
+            .is_empty());
+        // 来自另一个operator的行结束上一条日志
+        let records = grouper.push("ERROR: failed");
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].operator.as_deref(), Some("op"));
+        assert_eq!(records[0].level.as_deref(), Some("WARN"));
+        assert_eq!(
+            records[0].message,
+            "[2024-01-01T00:00:00Z WARN  dataflow] slow"
+        );
+        let record = grouper.flush().unwrap();
+        assert_eq!(record.operator, None);
+        assert_eq!(record.level.as_deref(), Some("ERROR"));
+        assert!(grouper.flush().is_none());
+
+        // traceback 合并到最后的异常行
+        let mut records = vec![];
+        for line in [
+            "Traceback (most recent call last):",
+            "  File \"op.py\", line 1, in <module>",
+            "ValueError: bad",
+            "next",
+        ] {
+            records.extend(grouper.push(line));
+        }
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].message.lines().count(), 3);
+        assert_eq!(records[0].level.as_deref(), Some("ERROR"));
+        assert_eq!(grouper.flush().unwrap().message, "next");
+
+        // json 日志中的其余字段原样保留
+        let mut grouper = LineGrouper::new("node", LogStream::Stdout, LogFormat::Json);
+        let records = grouper.push(r#"{"level":"info","msg":"ready","port":80}"#);
+        assert_eq!(records[0].level.as_deref(), Some("INFO"));
+        assert_eq!(records[0].message, "ready");
+        assert_eq!(
+            records[0].to_string().split_once(": ").unwrap().1,
+            "ready port=80"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("dataflow-logs-{}", std::process::id()));
+        let rotation = LogRotation {
+            max_size: Some(ByteSize(200)),
+            max_age: None,
+            keep: Some(2),
+        };
+        let mut log = RotatingLog::open(&dir, rotation.clone()).await.unwrap();
+        for i in 0..10 {
+            let record = LogRecord::new("node", LogStream::Stdout, None, &format!("line {i}"));
+            log.write(&record).await.unwrap();
+        }
+        // 只保留两个历史文件，最新的记录在当前文件中
+        let files = log_files(&dir);
+        assert_eq!(files.len(), 3);
+        assert!(files[2].ends_with(LOG_FILE_NAME));
+        let current = std::fs::read_to_string(&files[2]).unwrap();
+        assert!(current.contains("line 9"));
+        // 重新打开时，上一次的日志会被轮转
+        RotatingLog::open(&dir, rotation).await.unwrap();
+        assert!(
+            std::fs::read_to_string(dir.join(format!("{LOG_FILE_NAME}.1")))
+                .unwrap()
+                .contains("line 9")
+        );
+        // 旧版本中 deploy.log 是一个文件
+        let error = RotatingLog::open(&files[2], LogRotation::default())
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("is an existing file"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,32 +1,55 @@
 pub mod build;
+pub mod health;
+pub mod logs;
 pub mod node;
 use crate::{
-    descriptor::descriptor::{Descriptor, NormalNode},
+    cache::prefetch,
+    descriptor::descriptor::{Descriptor, LogFormat, LogRotation, NormalNode, ReadinessSignal},
+    descriptor::{builtin::stdin_nodes, expand::Overrides, DataflowId},
     event::Event,
-    runtime::timer::{self},
-    DATAFLOW_DESCRIPTION_ENV,
+    launch::{
+        health::{wait_ready, watch_first_output, watch_liveness, NodeSignal},
+        logs::{check_log_dir, split_operator, LineGrouper, LogRecord, LogStream, RotatingLog},
+    },
+    metrics,
+    runtime::{
+        resources::ResourceLimitExceeded,
+        timer::{self},
+    },
+    trace, DATAFLOW_DESCRIPTION_ENV, DATAFLOW_ID_ENV,
 };
 use anyhow::{anyhow, Context, Result};
 use futures::{stream::FuturesUnordered, StreamExt};
 use log::{debug, error, info};
-use regex::Regex;
 use serde_yaml;
-use std::{env, path::PathBuf, process::Stdio};
-use tokio::{
-    fs::File,
-    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader},
-};
+use std::{env, path::PathBuf, process::Stdio, time::Duration};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+/// 输出流空闲超过该时间时，结束正在合并的多行日志
+const LOG_IDLE_FLUSH: Duration = Duration::from_millis(100);
 
 /// 根据描述文件启动所有的节点
-pub async fn launch(dataflow: PathBuf, build: bool) -> Result<()> {
+/// offline 为 false 时，会先统一下载所有url类型的source到本地缓存
+/// build 为 true 时，会在启动节点之前统一构建，jobs 为同时执行的构建命令数量
+/// quiet 为 true 时，节点的输出只写入日志文件，不转发到控制台
+/// overrides 为命令行选择的profile和覆盖的变量
+pub async fn launch(
+    dataflow: PathBuf,
+    build: bool,
+    offline: bool,
+    jobs: Option<usize>,
+    quiet: bool,
+    overrides: &Overrides,
+) -> Result<()> {
     info!("Launch DataFlow");
-    // 读取描述文件并解析
-    let descriptor = Descriptor::blocking_read(&dataflow).with_context(|| {
-        format!(
-            "launch dataflow failed to read dataflow at `{}`",
-            dataflow.display()
-        )
-    })?;
+    // 读取描述文件并解析，节点通过环境变量得到合并profile之后的描述文件
+    let mut descriptor = Descriptor::blocking_read_with_overrides(&dataflow, overrides)
+        .with_context(|| {
+            format!(
+                "launch dataflow failed to read dataflow at `{}`",
+                dataflow.display()
+            )
+        })?;
     // 获取描述文件定义的的工作目录
     let working_dir = dataflow
         .canonicalize()
@@ -34,14 +57,56 @@ pub async fn launch(dataflow: PathBuf, build: bool) -> Result<()> {
         .parent()
         .ok_or_else(|| anyhow!("launch dataflow failed that dataflow path has no parent dir"))?
         .to_owned();
+    // 每个dataflow的日志默认写入以描述文件名命名的目录，每个节点一个子目录
+    descriptor.set_default_log_dir(&dataflow);
+    info!(
+        "Logs of dataflow are written to {}",
+        working_dir
+            .join(descriptor.deploy.log.clone().unwrap())
+            .display()
+    );
+    // 统一预先下载url类型的source，之后的校验和各个节点都只访问本地缓存
+    if !offline {
+        prefetch(&descriptor)
+            .await
+            .context("launch dataflow failed to prefetch url sources")?;
+    }
     // 对描述文件进行校验
     descriptor
-        .validate(&working_dir, build)
+        .validate(&working_dir, build, true)
         .context("launch dataflow failed to validate dataflow")?;
-    // 处理所有节点的默认值
-    let nodes = descriptor.resolve_node_defaults();
+    // 处理所有节点的默认值，并按照拓扑顺序排列，生产者在前
+    let order = descriptor.topology().order;
+    let mut nodes = descriptor.resolve_node_defaults();
+    nodes.sort_by_key(|n| order.iter().position(|id| id == &n.id));
+    // 在启动任何节点之前检查日志目录，旧版本的 `deploy.log` 可能是一个文件
+    let log_dirs = nodes.iter().map(|node| node.deploy.log.clone().unwrap());
+    for dir in descriptor.deploy.log.clone().into_iter().chain(log_dirs) {
+        check_log_dir(&working_dir.join(dir)).context("launch dataflow failed to check log dir")?;
+    }
+    // 统一构建所有的operator，节点启动时不再重复构建
+    if build {
+        build::build_dataflow(&nodes, &working_dir, jobs)
+            .await
+            .context("launch dataflow failed to build operators")?;
+    }
+    // 在启动节点之前开启指标的http端点，节点的指标通过标准输出汇报给launch
+    if let Some(config) = &descriptor.deploy.metrics {
+        metrics::serve(&config.listen)
+            .await
+            .context("launch dataflow failed to serve metrics")?;
+    }
+    // 开启追踪时，launch汇总所有节点的span并导出
+    let dataflow_id = DataflowId::new_v4();
+    if let Some(config) = &descriptor.deploy.tracing {
+        info!("Tracing dataflow {dataflow_id}");
+        trace::enable(dataflow_id);
+        trace::export(config, &working_dir)
+            .await
+            .context("launch dataflow failed to export traces")?;
+    }
     // 启动所有的节点
-    launch_nodes(&nodes, &descriptor, &working_dir, build).await?;
+    launch_nodes(&nodes, &descriptor, &working_dir, dataflow_id, quiet).await?;
     info!("Launch Nodes Success");
     Ok(())
 }
@@ -51,24 +116,69 @@ async fn launch_nodes(
     nodes: &Vec<NormalNode>,
     descriptor: &Descriptor,
     working_dir: &PathBuf,
-    build: bool,
+    dataflow_id: DataflowId,
+    quiet: bool,
 ) -> Result<()> {
     info!("Launch Nodes");
-    timer::start(&nodes, &descriptor.deploy).await?;
-
     let mut tasks = FuturesUnordered::new();
-    for node in nodes {
+    // 校验保证最多只有一个节点使用 stdin_source，launch 的标准输入交给这个节点
+    let stdin_node = stdin_nodes(nodes).into_iter().next().cloned();
+    // 按照拓扑的逆序启动，消费者先启动并就绪，生产者启动时下游的订阅已经存在
+    for node in nodes.iter().rev() {
         let node_id = node.id.clone();
-        let result = spawn_node(node.clone(), descriptor, working_dir, build)
+        let (signal_tx, signal_rx) = flume::unbounded();
+        if node
+            .readiness
+            .as_ref()
+            .is_some_and(|readiness| readiness.signal == ReadinessSignal::FirstOutput)
+        {
+            watch_first_output(node, signal_tx.clone()).with_context(|| {
+                format!("launch nodes failed to watch outputs of node {node_id}")
+            })?;
+        }
+        let mut result = spawn_node(
+            node.clone(),
+            descriptor,
+            working_dir,
+            dataflow_id,
+            quiet,
+            stdin_node.as_ref() == Some(&node_id),
+            signal_tx,
+        )
+        .await
+        .with_context(|| format!("launch nodes failed to spawn runtime node {node_id}"))?;
+        wait_ready(node, &signal_rx, &mut result)
             .await
-            .with_context(|| format!("launch nodes failed to spawn runtime node {node_id}"))?;
+            .context("launch nodes failed to wait for node readiness")?;
+        watch_liveness(node, signal_rx);
         tasks.push(result);
     }
-
-    while let Some(task_result) = tasks.next().await {
-        if let Err(e) = task_result {
-            error!("launch nodes failed to join one async task of nodes: {}", e);
+    // 所有节点启动之后再启动定时器，避免定时消息在订阅者就绪之前丢失
+    // 定时器一直运行到所有节点退出
+    let (stopped_tx, stopped_rx) = tokio::sync::oneshot::channel::<()>();
+    let backend = timer::backend(&descriptor.deploy)?;
+    let timer = timer::start(nodes, &descriptor.deploy, &backend, async {
+        let _ = stopped_rx.await;
+    });
+    let wait_nodes = async move {
+        while let Some(task_result) = tasks.next().await {
+            match task_result {
+                Err(e) => error!("launch nodes failed to join one async task of nodes: {}", e),
+                Ok(Err(e)) => error!("{e:#}"),
+                Ok(Ok(())) => {}
+            }
+        }
+        info!("All nodes exited, stopping timers");
+        let _ = stopped_tx.send(());
+    };
+    tokio::pin!(timer, wait_nodes);
+    tokio::select! {
+        // 定时器启动失败时立即返回，没有timer时直接结束
+        result = &mut timer => {
+            result.context("launch nodes failed to run timers")?;
+            wait_nodes.await;
         }
+        () = &mut wait_nodes => timer.await.context("launch nodes failed to run timers")?,
     }
 
     info!("Launch Nodes Success");
@@ -78,11 +188,15 @@ async fn launch_nodes(
 /// 开启子进程执行节点启动任务
 /// 开启两个异步任务分别处理标准输出和标准错误
 /// 再开启一个异步任务
+/// stdin 为 true 时节点继承 launch 的标准输入，否则标准输入为空
 async fn spawn_node(
     node: NormalNode,
     descriptor: &Descriptor,
     working_dir: &PathBuf,
-    build: bool,
+    dataflow_id: DataflowId,
+    quiet: bool,
+    stdin: bool,
+    signals: flume::Sender<NodeSignal>,
 ) -> Result<tokio::task::JoinHandle<Result<()>>> {
     debug!("Spawn Node log: {:#?}", node.deploy.log);
     debug!(
@@ -98,19 +212,19 @@ async fn spawn_node(
         DATAFLOW_DESCRIPTION_ENV,
         serde_yaml::to_string(descriptor).context("failed to serialize descriptor")?,
     );
-    command.args([
-        "start",
-        "--node",
-        node.id.as_str(),
-        if build == true { "--build" } else { "" },
-    ]);
+    command.env(DATAFLOW_ID_ENV, dataflow_id.to_string());
+    // url类型的source已经在launch时下载到了缓存，节点只需要检查本地缓存
+    // 构建也已经在launch时完成，节点不需要再次构建
+    command.args(["start", "--node", node.id.as_str(), "--offline"]);
     // 因为是通过环境变量设置的descriptor，所以这里还需要设置该命令的工作目录为描述文件的工作目录
     command.current_dir(working_dir);
 
     debug!("Spawn Node command: {:#?}", command);
     // 启动一个子进程，这里设置了子进程的标准输入输出，可以在后面通过当前进程获取子进程的标准输入输出来控制子进程
+    // launch 退出时结束所有的节点，如等待就绪失败时
     let mut child = command
-        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .stdin(if stdin { Stdio::inherit() } else { Stdio::null() })
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
@@ -118,73 +232,93 @@ async fn spawn_node(
 
     // 日志通道
     let (tx, log_message_rx) = flume::bounded(10);
+    // 事件通道，日志写入完成和节点汇报的超出资源限制
+    let (event_tx, event_rx) = flume::unbounded();
 
-    // 子进程标准输入的处理
+    // 子进程标准输出和标准错误的处理，按行合并为日志记录后发送到日志通道
+    let log_format = node.deploy.log_format.unwrap_or_default();
     let child_stdout = BufReader::new(child.stdout.take().expect("failed to take stdout"));
-    let log_file_path = node.deploy.log.clone().unwrap();
     let node_id_clone = node.id.clone();
     let stdout_tx = tx.clone();
+    let stdout_signals = signals.clone();
+    let stdout_events = event_tx.clone();
     tokio::spawn(async move {
-        let log_level_pattern: Regex =
-            Regex::new(r"TRACE|INFO|DEBUG|WARN|ERROR").expect("failed to create regex");
         child_log_writer(
             node_id_clone.as_str(),
-            log_file_path.to_str().unwrap(),
+            LogStream::Stdout,
+            log_format,
             child_stdout,
             stdout_tx,
-            move |buffer| log_level_pattern.is_match(buffer) && !buffer.ends_with("\n\n"),
+            stdout_signals,
+            stdout_events,
         )
         .await
-        .expect("failed to handler stdout to log")
     });
-
-    // 子进程标准错误输入的处理
     let child_stderr = BufReader::new(child.stderr.take().expect("failed to take stderr"));
-    let log_file_path = node.deploy.log.clone().unwrap();
     let node_id_clone = node.id.clone();
-    let stderr_tx = tx.clone();
+    let stderr_tx = tx;
+    let stderr_events = event_tx.clone();
     tokio::spawn(async move {
         child_log_writer(
             node_id_clone.as_str(),
-            log_file_path.to_str().unwrap(),
+            LogStream::Stderr,
+            log_format,
             child_stderr,
             stderr_tx,
-            |buffer| buffer.starts_with("Traceback"),
+            signals,
+            stderr_events,
         )
         .await
-        .expect("failed to handler stderr to log")
     });
 
-    // 事件通道
-    let (event_tx, event_rx) = flume::bounded(1);
-
     // 日志落盘的异步任务
-    let node_id_clone = node.id.clone();
-    let log_file_path = node.deploy.log.clone().unwrap();
+    let log_dir = working_dir.join(node.deploy.log.clone().unwrap());
+    let rotation = node.deploy.log_rotation.clone().unwrap_or_default();
     tokio::spawn(async move {
-        write_logs_to_file(
-            node_id_clone.as_str(),
-            log_file_path,
-            log_message_rx,
-            event_tx,
-        )
-        .await
-        .expect("failed to write logs to file")
+        write_logs_to_file(log_dir, rotation, log_message_rx, event_tx, quiet)
+            .await
+            .expect("failed to write logs to file")
     });
 
     // 等待子进程结束的异步任务
     let node_id_clone = node.id.clone();
     let result = tokio::spawn(async move {
         let status = child.wait().await.context("child process failed")?;
+        let outcome = match status.code() {
+            Some(0) => "success",
+            Some(_) => "failure",
+            None => "signal",
+        };
+        metrics::inc(
+            &metrics::PROCESS_EXITS,
+            &[
+                ("process", "node"),
+                ("node", node_id_clone.as_str()),
+                ("outcome", outcome),
+            ],
+            1,
+        );
         if status.success() {
             info!("node {} finished", node_id_clone);
-            match event_rx.recv_async().await {
-                Ok(_) => Ok(()),
-                Err(e) => Err(anyhow!(
-                    "runtime node {} event failed receive: {}",
-                    node_id_clone,
-                    e
-                )), // 在格式化字符串中使用变量
+            // 等待日志写入完成，期间收集节点汇报的超出资源限制
+            let mut exceeded = vec![];
+            loop {
+                match event_rx.recv_async().await {
+                    Ok(Event::ResourceLimitExceeded(e)) => exceeded.push(e),
+                    Ok(_) => break,
+                    Err(e) => {
+                        return Err(anyhow!(
+                            "runtime node {} event failed receive: {}",
+                            node_id_clone,
+                            e
+                        )) // 在格式化字符串中使用变量
+                    }
+                }
+            }
+            match exceeded.into_iter().next() {
+                Some(exceeded) => Err(anyhow::Error::new(exceeded)
+                    .context(format!("runtime node {node_id_clone} exceeded resource limits"))),
+                None => Ok(()),
             }
         } else if let Some(code) = status.code() {
             Err(anyhow!(
@@ -202,53 +336,98 @@ async fn spawn_node(
     Ok(result)
 }
 
-/// 子进程的标准输出流管理，将流读取到`String buffer`中，按照`should_continue`的条件，选择将其发送到`tx`channel中
-async fn child_log_writer<F, T>(
+/// 子进程输出流的管理，逐行读取并合并多行的日志，完整的日志记录发送到`tx`channel中
+/// 输出流空闲一段时间后，正在合并的日志也会被发送，避免最后一条日志迟迟不能落盘
+/// 就绪和心跳信号发送到`signals`中，指标快照和span交给launch汇总，都不写入日志
+/// 超出资源限制的汇报作为事件发送到`events`中
+async fn child_log_writer<T>(
     node_id: &str,
-    log: &str,
+    stream: LogStream,
+    format: LogFormat,
     mut child_stream: tokio::io::BufReader<T>,
-    tx: flume::Sender<String>,
-    should_continue: F,
-) -> Result<(), Box<dyn std::error::Error>>
-where
-    F: Fn(&str) -> bool + Send + 'static,
+    tx: flume::Sender<LogRecord>,
+    signals: flume::Sender<NodeSignal>,
+    events: flume::Sender<Event>,
+) where
     T: AsyncRead + Unpin,
 {
-    let mut buffer = String::new();
-    while let Ok(bytes_read) = child_stream.read_line(&mut buffer).await {
-        if bytes_read == 0 {
-            break;
-        }
-        if should_continue(&buffer) {
-            continue;
+    let mut grouper = LineGrouper::new(node_id, stream, format);
+    let mut buffer = vec![];
+    loop {
+        // read_until 被超时取消时，已经读到的内容保留在buffer中，下一次继续读取
+        let records =
+            match tokio::time::timeout(LOG_IDLE_FLUSH, child_stream.read_until(b'\n', &mut buffer))
+                .await
+            {
+                Ok(Ok(0)) => break,
+                Ok(Ok(_)) => {
+                    let line = String::from_utf8_lossy(&buffer).into_owned();
+                    buffer.clear();
+                    let content = split_operator(&line).1;
+                    match NodeSignal::parse(content) {
+                        Some(signal) => {
+                            let _ = signals.try_send(signal);
+                            vec![]
+                        }
+                        None if metrics::receive_report(node_id, content) => vec![],
+                        None if trace::receive_report(node_id, content) => vec![],
+                        None => match ResourceLimitExceeded::parse_report(content) {
+                            Some(exceeded) => {
+                                let event = Event::ResourceLimitExceeded(exceeded);
+                                error!("node {node_id} reported {event}");
+                                let _ = events.send(event);
+                                vec![]
+                            }
+                            None => grouper.push(&line),
+                        },
+                    }
+                }
+                Ok(Err(e)) => {
+                    error!("Failed to read {stream} of node {node_id}: {e}");
+                    break;
+                }
+                Err(_) => grouper.flush().into_iter().collect(),
+            };
+        for record in records {
+            if let Err(e) = tx.send_async(record).await {
+                error!("Failed to send logs from {node_id}: {e}");
+            }
         }
-
-        if let Err(e) = tx.send_async(buffer.clone()).await {
-            error!("Failed to send logs from {} to {:?}: {}", node_id, log, e);
+    }
+    // 没有换行结尾的最后一行
+    if !buffer.is_empty() {
+        for record in grouper.push(&String::from_utf8_lossy(&buffer)) {
+            let _ = tx.send_async(record).await;
         }
-
-        buffer.clear();
     }
-
-    Ok(())
+    if let Some(record) = grouper.flush() {
+        let _ = tx.send_async(record).await;
+    }
 }
 
-/// 将日志写入到文件中，从`log_message_rx`中接受msg，and write it to `log_file_path`
+/// 将日志写入到节点的日志目录中，从`log_message_rx`中接受日志记录并写入轮转的日志文件
+/// quiet 为 false 时，同时以节点id为前缀转发到launch的控制台
 async fn write_logs_to_file(
-    node_id: &str,
-    log_file_path: std::path::PathBuf,
-    log_message_rx: flume::Receiver<String>,
+    log_dir: PathBuf,
+    rotation: LogRotation,
+    log_message_rx: flume::Receiver<LogRecord>,
     event_rx: flume::Sender<Event>,
+    quiet: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut log_file = File::create(log_file_path)
+    let mut log_file = RotatingLog::open(&log_dir, rotation)
         .await
-        .context("Failed to create log file")?;
-    while let Ok(message) = log_message_rx.recv_async().await {
-        log_file.write_all(message.as_bytes()).await?;
-        let formatted: String = message.lines().map(|l| format!("      {l}\n")).collect();
-        debug!("{} logged:\n{formatted}", node_id);
-        log_file.sync_all().await?;
-        log_file.flush().await?;
+        .context("Failed to open log file")?;
+    while let Ok(record) = log_message_rx.recv_async().await {
+        log_file.write(&record).await?;
+        if !quiet {
+            let source = record.source();
+            for line in record.message.lines() {
+                match record.stream {
+                    LogStream::Stdout => println!("{source} | {line}"),
+                    LogStream::Stderr => eprintln!("{source} | {line}"),
+                }
+            }
+        }
     }
     // 发送日志写入完成的事件
     event_rx.send_async(Event::Logged).await.context(anyhow!(
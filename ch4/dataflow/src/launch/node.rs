@@ -1,9 +1,10 @@
 use crate::{
     dataflow_description_from_env,
-    descriptor::descriptor::Descriptor,
+    descriptor::{descriptor::Descriptor, expand::Overrides, DataflowId},
     launch,
     runtime::timer::TIMER_NODE_ID,
     runtime::{node, timer},
+    trace, DATAFLOW_ID_ENV,
 };
 use anyhow::{anyhow, Context, Result};
 use log::{debug, info};
@@ -12,6 +13,7 @@ use std::{env, path::PathBuf};
 
 /// 根据描述文件及节点id启动执行的节点
 /// 其中dataflow 和 working_dir 两个参数是可选的,并且最少传递一个
+/// overrides 只用于从文件读取的描述文件，环境变量中的描述文件已经由launch合并过
 pub async fn start(
     // 描述文件路径,不传的话会尝试从环境变量获取
     dataflow: Option<PathBuf>,
@@ -19,6 +21,10 @@ pub async fn start(
     node_id: String,
     // 是否执行build
     build: bool,
+    // 是否只使用本地的下载缓存
+    offline: bool,
+    // 命令行选择的profile和覆盖的变量
+    overrides: &Overrides,
 ) -> Result<()> {
     info!(
         "Start Node dataflow: {:#?} node_id: {:?}",
@@ -28,7 +34,7 @@ pub async fn start(
     let (descriptor, working_dir) = match dataflow {
         Some(path) => {
             // 读取描述文件并解析
-            let descriptor = Descriptor::blocking_read(&path)
+            let descriptor = Descriptor::blocking_read_with_overrides(&path, overrides)
                 .with_context(|| format!("failed to read dataflow at `{}`", path.display()))?;
             // 获取描述文件定义的的工作目录
             let working_dir = path
@@ -54,9 +60,18 @@ pub async fn start(
 
     // 对描述文件进行校验
     descriptor
-        .validate(&working_dir, build)
+        .validate(&working_dir, build, offline)
         .context("failed to validate dataflow")?;
 
+    // 由launch启动时使用launch生成的dataflow id，单独启动时生成一个新的
+    if descriptor.deploy.tracing.is_some() {
+        let dataflow_id = env::var(DATAFLOW_ID_ENV)
+            .ok()
+            .and_then(|id| id.parse().ok())
+            .unwrap_or_else(DataflowId::new_v4);
+        trace::enable(dataflow_id);
+    }
+
     // 处理所有节点的默认值
     let nodes = descriptor.resolve_node_defaults();
     debug!(
@@ -67,8 +82,9 @@ pub async fn start(
     match node_id.as_str() {
         TIMER_NODE_ID => {
             debug!("Launch TimerNode {:?}", TIMER_NODE_ID);
-            // 启动定时器节点
-            timer::start(&nodes, &descriptor.deploy).await?;
+            // 启动定时器节点，单独启动时一直运行到进程退出
+            let backend = timer::backend(&descriptor.deploy)?;
+            timer::start(&nodes, &descriptor.deploy, &backend, std::future::pending()).await?;
         }
         _ => {
             // 找到我们需要处理的那个节点
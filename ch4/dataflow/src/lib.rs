@@ -2,24 +2,30 @@ use anyhow::{anyhow, Context, Result};
 use descriptor::descriptor::Descriptor;
 use event::Event;
 use flume::{bounded, Receiver};
-use log::{error, info};
-#[cfg(unix)]
-use std::os::unix::prelude::PermissionsExt;
+use log::error;
+use sha2::{Digest, Sha256};
 use std::{
     env::consts::{DLL_PREFIX, DLL_SUFFIX, EXE_SUFFIX},
     path::{Path, PathBuf},
     sync::Arc,
 };
 use tokio::io::AsyncWriteExt;
+pub mod cache;
 pub mod cli;
+pub mod clock;
 pub mod communication;
 pub mod descriptor;
 pub mod event;
+pub mod harness;
 pub mod launch;
+pub mod metrics;
 pub mod runtime;
+pub mod trace;
 
 /// 用于存储数据流描述文件的环境变量
 pub const DATAFLOW_DESCRIPTION_ENV: &str = "DATAFLOW_DESCRIPTION";
+/// launch 为每次运行生成的dataflow id，通过该环境变量传递给节点
+pub const DATAFLOW_ID_ENV: &str = "DATAFLOW_ID";
 /// 从环境变量中读取数据流描述文件内容
 pub fn dataflow_description_from_env() -> Result<Descriptor> {
     let descriptor: Descriptor = {
@@ -101,24 +107,33 @@ pub(crate) fn adjust_executable_target_path(path: &Path) -> Result<std::path::Pa
     Ok(path)
 }
 
+/// 构造通过平台shell执行脚本的命令，args 作为脚本的参数原样传递
+/// windows 下使用 `cmd /C`，参数追加在命令之后
+/// 其他平台使用 `sh -c <script> sh <args...>`，脚本通过 `"$@"` 或 `$1` 读取参数
+pub(crate) fn shell_command(script: &str, args: &[String]) -> tokio::process::Command {
+    if cfg!(target_os = "windows") {
+        let mut command = tokio::process::Command::new("cmd");
+        command.args(["/C", script]).args(args);
+        command
+    } else {
+        // 参数作为位置参数传递，不修改脚本，参数中的空格和引号也不会被shell再次解析
+        let mut command = tokio::process::Command::new("sh");
+        command.args(["-c", script, "sh"]).args(args);
+        command
+    }
+}
+
 /// 判断source 来源是不是url 类型
 pub(crate) fn source_is_url(path: &str) -> bool {
     path.starts_with("http://") || path.starts_with("https://")
 }
 
-/// 从url下载文件到target_path
-pub(crate) async fn download_file<T>(url: T, target_path: &Path) -> Result<()>
+/// 从url下载文件到target_path，边下载边计算内容的sha256并返回
+/// 只负责写入，缓存、校验和权限由调用方处理
+pub(crate) async fn download_file<T>(url: T, target_path: &Path) -> Result<String>
 where
     T: reqwest::IntoUrl + std::fmt::Display + Copy,
 {
-    if target_path.exists() {
-        info!(
-            "Download file: {} using cache: {:?}",
-            url,
-            target_path.to_str()
-        );
-        return Ok(());
-    }
     // 有可能文件夹不存在
     if let Some(parent) = target_path.parent() {
         tokio::fs::create_dir_all(parent)
@@ -127,26 +142,42 @@ where
     }
 
     // 使用get请求url
-    let response = reqwest::get(url)
+    let mut response = reqwest::get(url)
         .await
         .with_context(|| format!("failed to request operator from `{url}`"))?
-        .bytes()
-        .await
-        .context("failed to read operator from `{uri}`")?;
+        .error_for_status()
+        .with_context(|| format!("failed to request operator from `{url}`"))?;
     let mut file = tokio::fs::File::create(target_path)
         .await
         .context("failed to create target file")?;
-    // 将url response 写入到文件中
-    file.write_all(&response)
+    let mut hasher = Sha256::new();
+    // 将url response 分块写入到文件中
+    while let Some(chunk) = response
+        .chunk()
         .await
-        .context("failed to write downloaded operator to file")?;
+        .with_context(|| format!("failed to read operator from `{url}`"))?
+    {
+        hasher.update(&chunk);
+        file.write_all(&chunk)
+            .await
+            .context("failed to write downloaded operator to file")?;
+    }
     file.sync_all().await.context("failed to `sync_all`")?;
 
-    // 如果是unix，需要设置权限
-    #[cfg(unix)]
-    file.set_permissions(std::fs::Permissions::from_mode(0o764))
-        .await
-        .context("failed to make downloaded file")?;
+    Ok(hex::encode(hasher.finalize()))
+}
 
-    Ok(())
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shell_command_args() {
+        // 复合语句以及结尾的注释都不影响参数的传递
+        let script = "for arg in \"$@\"; do echo \"[$arg]\"; done; echo \"$1\" # comment";
+        let args = ["a b".to_owned(), "'c'".to_owned()];
+        let output = shell_command(script, &args).output().await.unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "[a b]\n['c']\na b\n");
+    }
 }
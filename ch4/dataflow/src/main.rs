@@ -1,12 +1,22 @@
 use anyhow::Result;
+use clap::Parser;
 use dataflow::{
-    cli::{Args, Command},
+    cache::{self, prefetch_dataflow},
+    cli::{Args, CacheCommand, Command},
+    clock::advance_clock,
     ctrlc_handler,
-    descriptor::visualize::visualize,
+    descriptor::{
+        check::check,
+        schema::schema,
+        visualize::{visualize, GraphFormat},
+    },
     event::Event,
-    launch::{launch, node::start},
+    launch::{
+        launch,
+        logs::{logs, LogFilter},
+        node::start,
+    },
 };
-use clap::Parser;
 use futures::StreamExt;
 
 #[tokio::main]
@@ -18,16 +28,88 @@ async fn main() -> Result<()> {
         Command::Show {
             dataflow,
             mermaid,
+            format,
             open,
-        } => visualize(dataflow, mermaid, open)?,
+            offline,
+            resolved,
+            overrides,
+        } => visualize(
+            dataflow,
+            if mermaid {
+                Some(GraphFormat::Mermaid)
+            } else {
+                format
+            },
+            open,
+            offline,
+            resolved,
+            &overrides,
+        )?,
+        // 检查描述文件，输出所有的诊断信息后直接退出
+        Command::Check {
+            dataflow,
+            format,
+            build,
+            offline,
+            overrides,
+        } => return check(dataflow, format, build, offline, &overrides),
+        // 输出描述文件的JSON Schema，完成后直接退出
+        Command::Schema { output } => return schema(output),
+        // 预先下载url类型的source，完成后直接退出
+        Command::Prefetch {
+            dataflow,
+            overrides,
+        } => return prefetch_dataflow(dataflow, &overrides).await,
+        // 管理下载缓存，完成后直接退出
+        Command::Cache { command } => {
+            return match command {
+                CacheCommand::Ls => cache::list(),
+                CacheCommand::Clean => cache::clean(),
+            }
+        }
         // launch 所有的进程
-        Command::Launch { dataflow, build } => launch(dataflow, build).await?,
+        Command::Launch {
+            dataflow,
+            build,
+            offline,
+            jobs,
+            quiet,
+            overrides,
+        } => launch(dataflow, build, offline, jobs, quiet, &overrides).await?,
+        // 输出日志，完成后直接退出
+        Command::Logs {
+            dataflow,
+            node,
+            operator,
+            stream,
+            level,
+            tail,
+            json,
+            overrides,
+        } => {
+            let filter = LogFilter {
+                node,
+                operator,
+                stream,
+                level,
+                tail,
+            };
+            return logs(dataflow, filter, json, &overrides);
+        }
+        // 推进模拟时钟，完成后直接退出
+        Command::Clock {
+            dataflow,
+            advance,
+            overrides,
+        } => return advance_clock(dataflow, advance.0, &overrides),
         // 启动一个节点
         Command::Start {
             dataflow,
             node,
             build,
-        } => start(dataflow, node, build).await?,
+            offline,
+            overrides,
+        } => start(dataflow, node, build, offline, &overrides).await?,
     }
 
     // 在主线程中，等待并监听 Ctrl+C 事件
@@ -56,12 +138,10 @@ mod tests {
             // "python_source_image".into(),
             "dataflow/timer".into(),
             false,
+            false,
+            &Default::default(),
         )
         .await
         .unwrap();
     }
-    #[tokio::test]
-    async fn test_launch() {
-        launch(PathBuf::from("./demo.yaml"), true).await.unwrap();
-    }
 }
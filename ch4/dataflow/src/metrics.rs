@@ -0,0 +1,436 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::clock;
+
+/// 节点进程向launch汇报指标快照时输出的行前缀，后面是快照的json
+pub const METRICS_LINE_PREFIX: &str = "@dataflow:metrics ";
+/// 节点进程汇报指标快照的间隔
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 延迟直方图的桶，单位为秒
+const LATENCY_BUCKETS: &[f64] = &[
+    0.0001, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// 指标的定义
+pub struct Metric {
+    pub name: &'static str,
+    pub help: &'static str,
+    /// 直方图的桶，其他类型的指标为空
+    pub buckets: &'static [f64],
+}
+
+pub const MESSAGES_SENT: Metric = Metric {
+    name: "dataflow_messages_sent_total",
+    help: "Messages published to a topic.",
+    buckets: &[],
+};
+pub const BYTES_SENT: Metric = Metric {
+    name: "dataflow_sent_bytes_total",
+    help: "Payload bytes published to a topic.",
+    buckets: &[],
+};
+pub const MESSAGES_RECEIVED: Metric = Metric {
+    name: "dataflow_messages_received_total",
+    help: "Messages received by an operator from a topic.",
+    buckets: &[],
+};
+pub const BYTES_RECEIVED: Metric = Metric {
+    name: "dataflow_received_bytes_total",
+    help: "Payload bytes received by an operator from a topic.",
+    buckets: &[],
+};
+pub const QUEUE_DEPTH: Metric = Metric {
+    name: "dataflow_queue_depth",
+    help: "Messages waiting in the receive queue of an operator input.",
+    buckets: &[],
+};
+pub const MESSAGES_DROPPED: Metric = Metric {
+    name: "dataflow_messages_dropped_total",
+    help: "Messages dropped instead of being delivered.",
+    buckets: &[],
+};
+pub const LATENCY: Metric = Metric {
+    name: "dataflow_latency_seconds",
+    help: "End-to-end latency from publishing a message to receiving it.",
+    buckets: LATENCY_BUCKETS,
+};
+pub const TIMER_TICKS: Metric = Metric {
+    name: "dataflow_timer_ticks_total",
+    help: "Ticks emitted by a timer.",
+    buckets: &[],
+};
+pub const TIMER_MISSED_TICKS: Metric = Metric {
+    name: "dataflow_timer_missed_ticks_total",
+    help: "Scheduled ticks that a timer fired late or not at all.",
+    buckets: &[],
+};
+pub const TIMER_RESTARTS: Metric = Metric {
+    name: "dataflow_timer_restarts_total",
+    help: "Timer tasks restarted after a panic.",
+    buckets: &[],
+};
+pub const PROCESS_EXITS: Metric = Metric {
+    name: "dataflow_process_exits_total",
+    help: "Node and operator processes that exited, by outcome.",
+    buckets: &[],
+};
+
+/// 指标的值
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Value {
+    Counter(u64),
+    Gauge(i64),
+    Histogram(Histogram),
+}
+
+/// 直方图，counts 为每个桶各自的计数，最后一个为 +Inf
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    pub buckets: Vec<f64>,
+    pub counts: Vec<u64>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: &[f64]) -> Self {
+        Self {
+            buckets: buckets.to_vec(),
+            counts: vec![0; buckets.len() + 1],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        let index = self
+            .buckets
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.buckets.len());
+        self.counts[index] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// 一个带标签的指标序列
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Series {
+    pub name: String,
+    pub help: String,
+    pub labels: BTreeMap<String, String>,
+    pub value: Value,
+}
+
+/// 序列的key，指标名和标签
+type SeriesKey = (&'static str, BTreeMap<String, String>);
+
+/// 指标的注册表，本进程记录的指标和各个节点进程汇报的快照
+#[derive(Default)]
+struct Registry {
+    local: Mutex<BTreeMap<SeriesKey, Series>>,
+    nodes: Mutex<BTreeMap<String, Vec<Series>>>,
+}
+
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::default);
+
+/// 更新某个序列的值，序列不存在时先用 init 创建
+fn update(metric: &Metric, labels: &[(&str, &str)], init: Value, f: impl FnOnce(&mut Value)) {
+    let labels: BTreeMap<String, String> = labels
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    let mut local = REGISTRY.local.lock().unwrap();
+    let series = local
+        .entry((metric.name, labels.clone()))
+        .or_insert_with(|| Series {
+            name: metric.name.to_owned(),
+            help: metric.help.to_owned(),
+            labels,
+            value: init,
+        });
+    f(&mut series.value);
+}
+
+/// 计数器增加 by
+pub fn inc(metric: &Metric, labels: &[(&str, &str)], by: u64) {
+    update(metric, labels, Value::Counter(0), |value| {
+        if let Value::Counter(count) = value {
+            *count += by;
+        }
+    });
+}
+
+/// 设置仪表的值
+pub fn set(metric: &Metric, labels: &[(&str, &str)], to: i64) {
+    update(metric, labels, Value::Gauge(0), |value| {
+        if let Value::Gauge(gauge) = value {
+            *gauge = to;
+        }
+    });
+}
+
+/// 向直方图中记录一个观测值
+pub fn observe(metric: &Metric, labels: &[(&str, &str)], value: f64) {
+    update(
+        metric,
+        labels,
+        Value::Histogram(Histogram::new(metric.buckets)),
+        |histogram| {
+            if let Value::Histogram(histogram) = histogram {
+                histogram.observe(value);
+            }
+        },
+    );
+}
+
+/// 记录从发送时间到现在的延迟，时钟回拨导致的负值被忽略
+/// 消息的时间戳来自 `clock::now`，开启模拟时钟时同样使用模拟时间计算
+pub fn observe_latency(labels: &[(&str, &str)], sent: SystemTime) {
+    if let Ok(latency) = clock::now().duration_since(sent) {
+        observe(&LATENCY, labels, latency.as_secs_f64());
+    }
+}
+
+/// 本进程记录的所有指标
+pub fn snapshot() -> Vec<Series> {
+    REGISTRY.local.lock().unwrap().values().cloned().collect()
+}
+
+/// 节点进程汇报指标快照的一行
+pub fn report_line() -> String {
+    format!(
+        "{METRICS_LINE_PREFIX}{}",
+        serde_json::to_string(&snapshot()).unwrap()
+    )
+}
+
+/// 接收节点进程汇报的一行快照，替换该节点之前的快照
+/// 不是快照的行返回 false
+pub(crate) fn receive_report(node: &str, line: &str) -> bool {
+    let Some(json) = line.trim().strip_prefix(METRICS_LINE_PREFIX.trim_end()) else {
+        return false;
+    };
+    match serde_json::from_str::<Vec<Series>>(json) {
+        Ok(mut series) => {
+            for series in &mut series {
+                series
+                    .labels
+                    .entry("node".to_owned())
+                    .or_insert_with(|| node.to_owned());
+            }
+            REGISTRY
+                .nodes
+                .lock()
+                .unwrap()
+                .insert(node.to_owned(), series);
+        }
+        Err(e) => warn!("ignored invalid metrics report of node {node}: {e}"),
+    }
+    true
+}
+
+/// 以prometheus的文本格式输出所有的指标，同名的序列合并在一起
+pub fn render() -> String {
+    let mut families: BTreeMap<String, Vec<Series>> = BTreeMap::new();
+    let local = snapshot();
+    let nodes = REGISTRY.nodes.lock().unwrap().clone();
+    for series in local.into_iter().chain(nodes.into_values().flatten()) {
+        families
+            .entry(series.name.clone())
+            .or_default()
+            .push(series);
+    }
+    let mut out = String::new();
+    for (name, series) in families {
+        let kind = match series[0].value {
+            Value::Counter(_) => "counter",
+            Value::Gauge(_) => "gauge",
+            Value::Histogram(_) => "histogram",
+        };
+        let _ = writeln!(out, "# HELP {name} {}", series[0].help);
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        for series in &series {
+            let labels = &series.labels;
+            match &series.value {
+                Value::Counter(count) => {
+                    let _ = writeln!(out, "{name}{} {count}", format_labels(labels, None));
+                }
+                Value::Gauge(gauge) => {
+                    let _ = writeln!(out, "{name}{} {gauge}", format_labels(labels, None));
+                }
+                Value::Histogram(histogram) => {
+                    // 桶的计数是累计的
+                    let mut cumulative = 0;
+                    for (i, count) in histogram.counts.iter().enumerate() {
+                        cumulative += count;
+                        let le = histogram
+                            .buckets
+                            .get(i)
+                            .map(|bound| bound.to_string())
+                            .unwrap_or_else(|| "+Inf".to_owned());
+                        let _ = writeln!(
+                            out,
+                            "{name}_bucket{} {cumulative}",
+                            format_labels(labels, Some(&le))
+                        );
+                    }
+                    let _ = writeln!(
+                        out,
+                        "{name}_sum{} {}",
+                        format_labels(labels, None),
+                        histogram.sum
+                    );
+                    let _ = writeln!(
+                        out,
+                        "{name}_count{} {}",
+                        format_labels(labels, None),
+                        histogram.count
+                    );
+                }
+            }
+        }
+    }
+    out
+}
+
+/// 格式化标签，le 为直方图桶的上界
+fn format_labels(labels: &BTreeMap<String, String>, le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| {
+            let v = v
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{k}=\"{v}\"")
+        })
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{le}\""));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+/// 在 listen 地址上启动http服务，通过 `/metrics` 提供指标
+/// 监听失败时返回错误，之后的请求在后台处理
+pub async fn serve(listen: &str) -> Result<()> {
+    let listener = TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("failed to listen metrics endpoint on `{listen}`"))?;
+    info!(
+        "Serving metrics at http://{}/metrics",
+        listener.local_addr()?
+    );
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = respond(stream).await {
+                            debug!("metrics request from {peer} failed: {e}");
+                        }
+                    });
+                }
+                Err(e) => warn!("failed to accept metrics request: {e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// 处理一个http请求，只支持 `GET /metrics`
+async fn respond(mut stream: TcpStream) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.split();
+    let mut lines = BufReader::new(reader).lines();
+    let request = lines.next_line().await?.unwrap_or_default();
+    // 读完请求头，请求体被忽略
+    while let Some(header) = lines.next_line().await? {
+        if header.is_empty() {
+            break;
+        }
+    }
+    let mut parts = request.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let path = path.split('?').next().unwrap_or_default();
+    let (status, body) = match (method, path) {
+        ("GET", "/metrics") => ("200 OK", render()),
+        (_, "/metrics") => ("405 Method Not Allowed", "method not allowed\n".to_owned()),
+        _ => ("404 Not Found", "not found\n".to_owned()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    writer.write_all(response.as_bytes()).await?;
+    writer.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        const TEST: Metric = Metric {
+            name: "dataflow_test_total",
+            help: "Test counter.",
+            buckets: &[],
+        };
+        const TEST_LATENCY: Metric = Metric {
+            name: "dataflow_test_seconds",
+            help: "Test histogram.",
+            buckets: &[0.1, 1.0],
+        };
+        inc(&TEST, &[("topic", "a/\"b\"")], 2);
+        inc(&TEST, &[("topic", "a/\"b\"")], 1);
+        observe(&TEST_LATENCY, &[], 0.05);
+        observe(&TEST_LATENCY, &[], 0.5);
+        observe(&TEST_LATENCY, &[], 5.0);
+
+        // 节点的快照加上节点标签
+        let report = serde_json::to_string(&vec![Series {
+            name: TEST.name.to_owned(),
+            help: TEST.help.to_owned(),
+            labels: BTreeMap::from([("topic".to_owned(), "x".to_owned())]),
+            value: Value::Counter(7),
+        }])
+        .unwrap();
+        assert!(receive_report(
+            "n",
+            &format!("{METRICS_LINE_PREFIX}{report}\n")
+        ));
+        assert!(!receive_report("n", "hello"));
+
+        let text = render();
+        assert!(text.contains("# TYPE dataflow_test_total counter\n"));
+        assert!(text.contains("dataflow_test_total{topic=\"a/\\\"b\\\"\"} 3\n"));
+        assert!(text.contains("dataflow_test_total{node=\"n\",topic=\"x\"} 7\n"));
+        assert!(text.contains("dataflow_test_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(text.contains("dataflow_test_seconds_bucket{le=\"1\"} 2\n"));
+        assert!(text.contains("dataflow_test_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("dataflow_test_seconds_count 3\n"));
+    }
+}
@@ -0,0 +1,690 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    clock,
+    communication::Envelope,
+    descriptor::{
+        builtin::{BuiltinOperator, DEFAULT_ROTATE_KEEP, DEFAULT_WATCH_INTERVAL},
+        descriptor::{DataId, NormalOperatorDefinition},
+    },
+    metrics,
+    runtime::Runtime,
+    trace::TraceContext,
+};
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use serde_json::{json, Map, Value};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader},
+    sync::mpsc::Receiver,
+    time::{Instant, MissedTickBehavior},
+};
+
+use super::{
+    io::{forward_inputs, json_value},
+    OperatorActuator, OPERATOR_LOG_PREFIX,
+};
+
+/// join 最多保留的未配对的key，超过时丢弃最早的key
+const MAX_PENDING_KEYS: usize = 1024;
+
+/// 内置operator的执行器，在节点进程中运行，不启动子进程
+pub(crate) struct Builtin(
+    pub NormalOperatorDefinition,
+    pub BuiltinOperator,
+    pub Option<Runtime>,
+);
+
+impl OperatorActuator for Builtin {
+    fn execute(&mut self, working_dir: &PathBuf) -> Result<tokio::task::JoinHandle<Result<()>>> {
+        let mut runtime = self
+            .2
+            .take()
+            .with_context(|| format!("builtin operator {} already started", self.0.id))?;
+        let inputs = forward_inputs(&mut runtime)?;
+        let builtin = self.1.clone();
+        debug!("OperatorActuator Builtin {} run {builtin}", runtime.id());
+        let task = BuiltinTask {
+            operator_id: self.0.id.to_string(),
+            outputs: runtime.node_config().outputs.iter().cloned().collect(),
+            runtime,
+            working_dir: working_dir.clone(),
+        };
+        Ok(tokio::spawn(task.run(builtin, inputs)))
+    }
+}
+
+/// 运行中的内置operator
+struct BuiltinTask {
+    operator_id: String,
+    runtime: Runtime,
+    outputs: Vec<DataId>,
+    /// 文件类operator的相对路径基于这个目录
+    working_dir: PathBuf,
+}
+
+impl BuiltinTask {
+    /// 处理输入直到所有输入断开，source类的operator直到数据读完
+    async fn run(
+        mut self,
+        builtin: BuiltinOperator,
+        mut inputs: Receiver<(DataId, Envelope)>,
+    ) -> Result<()> {
+        match builtin {
+            BuiltinOperator::Merge {}
+            | BuiltinOperator::Passthrough {}
+            | BuiltinOperator::Tee {} => {
+                while let Some((_, envelope)) = inputs.recv().await {
+                    self.forward(&envelope);
+                }
+            }
+            BuiltinOperator::Map { field, fields } => {
+                while let Some((input_id, envelope)) = inputs.recv().await {
+                    let message = json_value(&envelope.data);
+                    let mapped = match (&field, &fields) {
+                        (Some(field), _) => select(&message, field).cloned(),
+                        (None, fields) => fields
+                            .iter()
+                            .flatten()
+                            .map(|field| {
+                                Some((field_name(field), select(&message, field)?.clone()))
+                            })
+                            .collect::<Option<Map<_, _>>>()
+                            .map(Value::Object),
+                    };
+                    let Some(mapped) = mapped else {
+                        debug!(
+                            "operator {} dropped message from {input_id} without the mapped fields",
+                            self.runtime.id()
+                        );
+                        self.dropped("map_field_missing");
+                        continue;
+                    };
+                    let parent = TraceContext::from_metadata(&envelope.metadata);
+                    self.send(mapped.to_string().as_bytes(), parent);
+                }
+            }
+            BuiltinOperator::Filter {
+                field,
+                equals,
+                min,
+                max,
+            } => {
+                let equals = equals.map(serde_json::to_value).transpose()?;
+                while let Some((_, envelope)) = inputs.recv().await {
+                    let message = json_value(&envelope.data);
+                    let passed = select(&message, &field).is_some_and(|value| {
+                        let number = value.as_f64();
+                        let conditions = equals.is_some() || min.is_some() || max.is_some();
+                        equals.as_ref().is_none_or(|equals| value == equals)
+                            && min.is_none_or(|min| number.is_some_and(|number| number >= min))
+                            && max.is_none_or(|max| number.is_some_and(|number| number <= max))
+                            && (conditions || !matches!(value, Value::Null | Value::Bool(false)))
+                    });
+                    if passed {
+                        self.forward(&envelope);
+                    } else {
+                        self.dropped("filtered");
+                    }
+                }
+            }
+            // 时间窗口使用 `clock::now`，开启模拟时钟时随模拟时间推进
+            BuiltinOperator::Throttle { interval } => {
+                let mut next = SystemTime::UNIX_EPOCH;
+                while let Some((_, envelope)) = inputs.recv().await {
+                    let now = message_time(&envelope);
+                    if now >= next {
+                        self.forward(&envelope);
+                        next = now + interval.0;
+                    } else {
+                        self.dropped("throttled");
+                    }
+                }
+            }
+            BuiltinOperator::Sample { interval } => {
+                let mut next = clock::now() + interval.0;
+                let mut latest = None;
+                loop {
+                    tokio::select! {
+                        biased;
+                        received = inputs.recv() => match received {
+                            Some((_, envelope)) => {
+                                if latest.replace(envelope).is_some() {
+                                    self.dropped("sampled");
+                                }
+                            }
+                            None => break,
+                        },
+                        () = clock::sleep_until(next) => {
+                            if let Some(envelope) = latest.take() {
+                                self.forward(&envelope);
+                            }
+                            // 错过的窗口直接跳过
+                            let now = clock::now();
+                            while next <= now {
+                                next += interval.0;
+                            }
+                        }
+                    }
+                }
+            }
+            BuiltinOperator::Debounce { interval } => {
+                let mut pending = None;
+                let mut deadline = SystemTime::UNIX_EPOCH;
+                loop {
+                    tokio::select! {
+                        biased;
+                        received = inputs.recv() => match received {
+                            Some((_, envelope)) => {
+                                deadline = message_time(&envelope) + interval.0;
+                                if pending.replace(envelope).is_some() {
+                                    self.dropped("debounced");
+                                }
+                            }
+                            None => break,
+                        },
+                        () = clock::sleep_until(deadline), if pending.is_some() => {
+                            if let Some(envelope) = pending.take() {
+                                self.forward(&envelope);
+                            }
+                        }
+                    }
+                }
+                // 输入结束时转发最后一条
+                if let Some(envelope) = pending {
+                    self.forward(&envelope);
+                }
+            }
+            BuiltinOperator::Batch { count, interval } => {
+                let mut items = vec![];
+                let mut deadline = SystemTime::UNIX_EPOCH;
+                loop {
+                    tokio::select! {
+                        biased;
+                        received = inputs.recv() => match received {
+                            Some((_, envelope)) => {
+                                // 窗口从第一条消息到达时开始
+                                if let (true, Some(interval)) = (items.is_empty(), interval) {
+                                    deadline = message_time(&envelope) + interval.0;
+                                }
+                                items.push(json_value(&envelope.data));
+                                if count.is_some_and(|count| items.len() >= count) {
+                                    self.send_json(Value::Array(std::mem::take(&mut items)));
+                                }
+                            }
+                            None => break,
+                        },
+                        () = clock::sleep_until(deadline), if interval.is_some() && !items.is_empty() => {
+                            self.send_json(Value::Array(std::mem::take(&mut items)));
+                        }
+                    }
+                }
+                if !items.is_empty() {
+                    self.send_json(Value::Array(items));
+                }
+            }
+            BuiltinOperator::ZipLatest {} => {
+                let input_count = self.runtime.node_config().inputs.len();
+                let mut latest = Map::new();
+                while let Some((input_id, envelope)) = inputs.recv().await {
+                    latest.insert(input_id.to_string(), json_value(&envelope.data));
+                    if latest.len() == input_count {
+                        self.send_json(Value::Object(latest.clone()));
+                    }
+                }
+            }
+            BuiltinOperator::Join { key } => {
+                let input_count = self.runtime.node_config().inputs.len();
+                let mut pending: BTreeMap<String, Map<String, Value>> = BTreeMap::new();
+                // 未配对的key按到达的顺序排列
+                let mut order = VecDeque::new();
+                while let Some((input_id, envelope)) = inputs.recv().await {
+                    let message = json_value(&envelope.data);
+                    let join_key = match message.get(&key) {
+                        Some(Value::String(value)) => value.clone(),
+                        Some(value) => value.to_string(),
+                        None => {
+                            warn!(
+                                "operator {} ignored message from {input_id} without key `{key}`",
+                                self.runtime.id()
+                            );
+                            self.dropped("join_key_missing");
+                            continue;
+                        }
+                    };
+                    let joined = pending.entry(join_key.clone()).or_insert_with(|| {
+                        order.push_back(join_key.clone());
+                        Map::new()
+                    });
+                    // 同一个输入重复的key只保留最新的消息
+                    if joined.insert(input_id.to_string(), message).is_some() {
+                        self.dropped("join_replaced");
+                    }
+                    if joined.len() == input_count {
+                        let joined = pending.remove(&join_key).unwrap_or_default();
+                        order.retain(|pending_key| pending_key != &join_key);
+                        self.send_json(Value::Object(joined));
+                    } else if pending.len() > MAX_PENDING_KEYS {
+                        if let Some(oldest) = order.pop_front() {
+                            pending.remove(&oldest);
+                            self.dropped("join_evicted");
+                        }
+                    }
+                }
+            }
+            BuiltinOperator::FileSource {
+                path,
+                interval,
+                chunk_size,
+                repeat,
+            } => {
+                let path = self.working_dir.join(path);
+                self.file_source(&path, interval.0, chunk_size, repeat)
+                    .await
+                    .with_context(|| format!("failed to read `{}`", path.display()))?;
+            }
+            BuiltinOperator::StdinSource {} => {
+                let mut lines = BufReader::new(tokio::io::stdin()).lines();
+                while let Some(line) = lines.next_line().await.context("failed to read stdin")? {
+                    self.send(line.as_bytes(), None);
+                }
+            }
+            BuiltinOperator::DirWatch {
+                path,
+                interval,
+                pattern,
+            } => {
+                let path = self.working_dir.join(path);
+                let pattern = pattern.as_deref().map(glob::Pattern::new).transpose()?;
+                let interval = interval.map_or(DEFAULT_WATCH_INTERVAL, |interval| interval.0);
+                self.dir_watch(&path, interval, pattern.as_ref())
+                    .await
+                    .with_context(|| format!("failed to watch `{}`", path.display()))?;
+            }
+            BuiltinOperator::FileSink {
+                path,
+                max_bytes,
+                keep,
+            } => {
+                let keep = keep.unwrap_or(DEFAULT_ROTATE_KEEP);
+                let mut sink = FileSink::open(self.working_dir.join(path), max_bytes, keep).await?;
+                while let Some((_, envelope)) = inputs.recv().await {
+                    if let Err(e) = sink.write(&envelope.data).await {
+                        warn!("operator {} failed to write: {e:#}", self.runtime.id());
+                        self.dropped("write_failed");
+                    }
+                }
+            }
+            BuiltinOperator::StdoutSink {} => {
+                while let Some((input_id, envelope)) = inputs.recv().await {
+                    // json展开为多行，每行都带上operator前缀
+                    let text = match serde_json::from_slice::<Value>(&envelope.data) {
+                        Ok(value) => serde_json::to_string_pretty(&value)?,
+                        Err(_) => String::from_utf8_lossy(&envelope.data).into_owned(),
+                    };
+                    for line in text.lines() {
+                        println!(
+                            "{OPERATOR_LOG_PREFIX}{}] {input_id} | {line}",
+                            self.operator_id
+                        );
+                    }
+                }
+            }
+        }
+        debug!("builtin operator {} finished", self.runtime.id());
+        Ok(())
+    }
+
+    /// 按固定间隔发送文件中的行或者块
+    async fn file_source(
+        &mut self,
+        path: &Path,
+        interval: Duration,
+        chunk_size: Option<usize>,
+        repeat: bool,
+    ) -> Result<()> {
+        let mut reader = BufReader::new(File::open(path).await?);
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut buf = vec![];
+        // 本轮是否发送过数据，空文件不重放
+        let mut sent = false;
+        loop {
+            buf.clear();
+            let read = match chunk_size {
+                Some(size) => {
+                    (&mut reader)
+                        .take(size as u64)
+                        .read_to_end(&mut buf)
+                        .await?
+                }
+                None => reader.read_until(b'\n', &mut buf).await?,
+            };
+            if read == 0 {
+                if !repeat || !sent {
+                    return Ok(());
+                }
+                reader.seek(SeekFrom::Start(0)).await?;
+                sent = false;
+                continue;
+            }
+            if chunk_size.is_none() {
+                while buf.last().is_some_and(|byte| matches!(byte, b'\n' | b'\r')) {
+                    buf.pop();
+                }
+            }
+            ticker.tick().await;
+            self.send(&buf, None);
+            sent = true;
+        }
+    }
+
+    /// 轮询目录，比较两次扫描的结果得到文件的变化
+    async fn dir_watch(
+        &mut self,
+        dir: &Path,
+        interval: Duration,
+        pattern: Option<&glob::Pattern>,
+    ) -> Result<()> {
+        // 启动时已经存在的文件不输出事件
+        let mut known = scan_dir(dir, pattern).await?;
+        let mut ticker = tokio::time::interval_at(Instant::now() + interval, interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            ticker.tick().await;
+            let current = match scan_dir(dir, pattern).await {
+                Ok(current) => current,
+                Err(e) => {
+                    warn!("operator {} failed to scan: {e:#}", self.runtime.id());
+                    continue;
+                }
+            };
+            for (path, state) in &current {
+                let event = match known.get(path) {
+                    None => "created",
+                    Some(known) if known != state => "modified",
+                    Some(_) => continue,
+                };
+                self.send_json(json!({ "event": event, "path": path }));
+            }
+            for path in known.keys().filter(|path| !current.contains_key(*path)) {
+                self.send_json(json!({ "event": "removed", "path": path }));
+            }
+            known = current;
+        }
+    }
+
+    /// 转发收到的消息，追踪上下文随消息传递
+    fn forward(&mut self, envelope: &Envelope) {
+        let parent = TraceContext::from_metadata(&envelope.metadata);
+        self.send(&envelope.data, parent);
+    }
+
+    fn send_json(&mut self, value: Value) {
+        self.send(value.to_string().as_bytes(), None);
+    }
+
+    /// 发送到所有的输出，发送失败时只记录日志
+    fn send(&mut self, data: &[u8], parent: Option<TraceContext>) {
+        for output in &self.outputs {
+            if let Err(e) = self.runtime.send_output_with_context(output, data, parent) {
+                warn!("operator {} failed to publish: {e:#}", self.runtime.id());
+                self.dropped("publish_failed");
+            }
+        }
+    }
+
+    fn dropped(&self, reason: &str) {
+        metrics::inc(
+            &metrics::MESSAGES_DROPPED,
+            &[("operator", self.runtime.id()), ("reason", reason)],
+            1,
+        );
+    }
+}
+
+/// 消息在时间窗口中的时间
+/// 模拟时钟下使用消息发布时的模拟时间，结果不受消息处理先后的影响
+fn message_time(envelope: &Envelope) -> SystemTime {
+    match (clock::is_simulated(), envelope.timestamp) {
+        (true, Some(timestamp)) => timestamp,
+        _ => clock::now(),
+    }
+}
+
+/// 取出json消息中的字段，以 `/` 开头时作为 json pointer
+fn select<'a>(message: &'a Value, field: &str) -> Option<&'a Value> {
+    match field.starts_with('/') {
+        true => message.pointer(field),
+        false => message.get(field),
+    }
+}
+
+/// map 输出的对象中字段的key，json pointer 使用最后一段
+fn field_name(field: &str) -> String {
+    match field.strip_prefix('/') {
+        Some(pointer) => pointer
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .replace("~1", "/")
+            .replace("~0", "~"),
+        None => field.to_owned(),
+    }
+}
+
+/// 扫描目录中的文件，返回路径以及修改时间和大小
+async fn scan_dir(
+    dir: &Path,
+    pattern: Option<&glob::Pattern>,
+) -> Result<BTreeMap<String, (SystemTime, u64)>> {
+    let mut files = BTreeMap::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let matched =
+            pattern.is_none_or(|pattern| pattern.matches(&entry.file_name().to_string_lossy()));
+        let metadata = entry.metadata().await?;
+        if matched && metadata.is_file() {
+            files.insert(
+                entry.path().display().to_string(),
+                (metadata.modified()?, metadata.len()),
+            );
+        }
+    }
+    Ok(files)
+}
+
+/// 追加写入的文件，超过大小之后轮转
+struct FileSink {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: Option<u64>,
+    keep: usize,
+}
+
+impl FileSink {
+    async fn open(path: PathBuf, max_bytes: Option<u64>, keep: usize) -> Result<Self> {
+        let file = Self::append(&path).await?;
+        let size = file.metadata().await?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_bytes,
+            keep,
+        })
+    }
+
+    async fn append(path: &Path) -> Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("failed to open `{}`", path.display()))
+    }
+
+    /// 写入一行，数据本身没有换行时补上
+    async fn write(&mut self, data: &[u8]) -> Result<()> {
+        let newline = !data.ends_with(b"\n");
+        let len = data.len() as u64 + u64::from(newline);
+        if self
+            .max_bytes
+            .is_some_and(|max_bytes| self.size > 0 && self.size + len > max_bytes)
+        {
+            self.rotate().await?;
+        }
+        self.file.write_all(data).await?;
+        if newline {
+            self.file.write_all(b"\n").await?;
+        }
+        self.file.flush().await?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// `path.{n-1}` 依次重命名为 `path.{n}`，超过 keep 的被覆盖
+    async fn rotate(&mut self) -> Result<()> {
+        let rotated = |index: usize| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{index}"));
+            PathBuf::from(path)
+        };
+        for index in (1..self.keep).rev() {
+            let from = rotated(index);
+            if tokio::fs::try_exists(&from).await? {
+                tokio::fs::rename(&from, rotated(index + 1)).await?;
+            }
+        }
+        tokio::fs::rename(&self.path, rotated(1)).await?;
+        self.file = Self::append(&self.path).await?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::harness::fixture_harness;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_builtin_operators() {
+        let dataflow = fixture_harness("builtins.yml").start().await.unwrap();
+
+        dataflow.inject("source/a", "1").unwrap();
+        dataflow.inject("source/a", "2").unwrap();
+        dataflow.expect_eq("glue/batch/out", "[1,2]").await.unwrap();
+        // b 没有数据之前不输出
+        dataflow.inject("source/b", "x").unwrap();
+        dataflow
+            .expect_eq("glue/zip/out", r#"{"a":2,"b":"x"}"#)
+            .await
+            .unwrap();
+        assert!(dataflow.drain("glue/zip/out").unwrap().is_empty());
+
+        dataflow.inject("source/left", r#"{"id":1}"#).unwrap();
+        dataflow.inject("source/right", r#"{"id":2}"#).unwrap();
+        dataflow
+            .inject("source/right", r#"{"id":1,"v":"r"}"#)
+            .unwrap();
+        dataflow
+            .expect_eq(
+                "glue/join/out",
+                r#"{"left":{"id":1},"right":{"id":1,"v":"r"}}"#,
+            )
+            .await
+            .unwrap();
+
+        for i in 0..3 {
+            dataflow.inject("source/fast", i.to_string()).unwrap();
+        }
+        dataflow.expect_eq("glue/throttle/out", "0").await.unwrap();
+        dataflow
+            .expect_silence("glue/throttle/out", Duration::from_millis(100))
+            .await
+            .unwrap();
+
+        // 缺少字段的消息被 map 和 filter 丢弃
+        for pose in [
+            r#"{"id":1,"pose":{"x":2}}"#,
+            r#"{"id":2}"#,
+            r#"{"id":3,"pose":{"x":1}}"#,
+        ] {
+            dataflow.inject("source/pose", pose).unwrap();
+        }
+        dataflow
+            .expect_eq("glue/pick/out", r#"{"id":1,"x":2}"#)
+            .await
+            .unwrap();
+        dataflow
+            .expect_eq("glue/pick/out", r#"{"id":3,"x":1}"#)
+            .await
+            .unwrap();
+        dataflow
+            .expect_eq("glue/near/out", r#"{"id":3,"pose":{"x":1}}"#)
+            .await
+            .unwrap();
+        dataflow.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_operators() {
+        let dir = std::env::temp_dir().join(format!("dataflow-builtin-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("watch")).unwrap();
+        std::fs::write(dir.join("in.txt"), "a\nb\r\nc").unwrap();
+        let dataflow = fixture_harness("files.yml")
+            .with_working_dir(&dir)
+            .start()
+            .await
+            .unwrap();
+
+        for line in ["a", "b", "c"] {
+            dataflow.expect_eq("files/reader/out", line).await.unwrap();
+        }
+        dataflow
+            .expect_silence("files/reader/out", Duration::from_millis(50))
+            .await
+            .unwrap();
+
+        // 每次写入都超过 max_bytes，最早的一行被轮转覆盖
+        for data in ["1", "22", "33", "44"] {
+            dataflow.inject("source/data", data).unwrap();
+        }
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap_or_default();
+        for _ in 0..100 {
+            if read("out.txt") == "44\n" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            [read("out.txt"), read("out.txt.1"), read("out.txt.2")],
+            ["44\n", "33\n", "22\n"]
+        );
+        assert!(!dir.join("out.txt.3").exists());
+
+        std::fs::write(dir.join("watch/ignored.log"), "x").unwrap();
+        std::fs::write(dir.join("watch/new.txt"), "x").unwrap();
+        let event: serde_json::Value =
+            serde_json::from_slice(&dataflow.expect("files/watcher/events").await.unwrap())
+                .unwrap();
+        assert_eq!(event["event"], "created");
+        assert!(event["path"].as_str().unwrap().ends_with("new.txt"));
+        std::fs::remove_file(dir.join("watch/new.txt")).unwrap();
+        let event: serde_json::Value =
+            serde_json::from_slice(&dataflow.expect("files/watcher/events").await.unwrap())
+                .unwrap();
+        assert_eq!(event["event"], "removed");
+
+        dataflow.stop().await.unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,66 +1,73 @@
 use std::path::{Path, PathBuf};
 
 use crate::{
-    adjust_executable_target_path, descriptor::descriptor::NormalOperatorDefinition, download_file,
-    source_is_url,
+    adjust_executable_target_path, cache::fetch, descriptor::descriptor::NormalOperatorDefinition,
+    runtime::resources::apply as apply_resources, source_is_url,
 };
 use anyhow::{anyhow, Context, Result};
 use log::debug;
+use std::process::Stdio;
 use tokio::process::Command;
 
-use super::OperatorActuator;
+use super::{forward_output, OperatorActuator};
 
 // 定义一个结构体来作为执行类型
-pub(crate) struct ExeTarget(pub NormalOperatorDefinition);
+// 第二个字段是解析后的参数，第三个字段是可执行文件的路径
+pub(crate) struct ExeTarget(pub NormalOperatorDefinition, pub Vec<String>, pub PathBuf);
+
+/// 可执行文件的本地路径
+/// 如果是url类型的source，需要先下载到本地
+/// 缓存中已经存在时(如launch时已经预先下载)不会再次下载
+/// 无论是否命中缓存，返回的文件都已经过sha256校验
+pub(crate) async fn program(operator: &NormalOperatorDefinition) -> Result<PathBuf> {
+    let target = operator.config.source.to_string();
+    if source_is_url(target.as_str()) {
+        fetch(&target, operator.config.sha256.as_deref(), false, true)
+            .await
+            .context("failed to download executable target operator")
+    } else {
+        // 否则直接构造可执行文件路径
+        adjust_executable_target_path(Path::new(&target))
+    }
+}
 
 /// 为可执行目标实现 `OperatorActuator` trait
 impl OperatorActuator for ExeTarget {
     fn execute(&mut self, working_dir: &PathBuf) -> Result<tokio::task::JoinHandle<Result<()>>> {
         let operator_id = self.0.id.to_string();
         let target = self.0.config.source.to_string();
-        let args = self
-            .0
-            .config
-            .args
-            .as_ref()
-            .map(String::as_str)
-            .unwrap_or_default();
 
-        let mut target_cmd = Command::new(
-            // 如果是url类型的source，需要先下载到本地
-            if source_is_url(target.as_str()) {
-                // 构造下载地址，
-                let adjust_target_path =
-                    adjust_executable_target_path(&Path::new("build").join(operator_id.as_str()))?;
-                tokio::runtime::Builder::new_current_thread()
-                    .enable_all()
-                    .build()?
-                    .block_on(download_file(
-                        target.as_str(),
-                        &working_dir.join(&adjust_target_path),
-                    ))
-                    .context("failed to download executable target operator")?;
-                adjust_target_path
-            } else {
-                // 否则直接构造可执行文件路径
-                adjust_executable_target_path(Path::new(&target))?
-            },
-        );
+        let mut target_cmd = Command::new(&self.2);
         if let Some(envs) = &self.0.config.envs {
             for (k, v) in envs.iter() {
                 target_cmd.env(k.as_str(), v.to_string().as_str());
             }
         }
         // 设置工作目录和命令参数
-        target_cmd.args(args.split_whitespace());
+        target_cmd.args(&self.1);
         target_cmd.current_dir(working_dir);
 
         debug!("OperatorActuator ExeTraget exec command {:?}", target_cmd);
+        // 应用资源限制，需要在启动进程之前设置
+        let resources = apply_resources(
+            &mut target_cmd,
+            &operator_id,
+            self.0.config.resources.as_ref(),
+        )?;
         let mut child = target_cmd
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .spawn()
             .with_context(|| format!("failed to run command `{}`", target))?;
+        // 输出加上operator前缀后转发，便于区分日志的来源
+        let stdout = child.stdout.take().expect("failed to take stdout");
+        let stderr = child.stderr.take().expect("failed to take stderr");
+        forward_output(operator_id.clone(), stdout, false, resources.watcher());
+        forward_output(operator_id.clone(), stderr, true, resources.watcher());
         let result = tokio::spawn(async move {
             let status = child.wait().await.context("child process failed")?;
+            // 超出资源限制时返回可识别的错误
+            resources.finish(&status)?;
             if status.success() {
                 println!("operator {operator_id} finished");
                 Ok(())
@@ -75,3 +82,41 @@ impl OperatorActuator for ExeTarget {
         Ok(result)
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::{
+        cache::{blob_path, file_digest, DATAFLOW_CACHE_DIR_ENV},
+        communication::{memory::MemoryBus, Backend},
+        runtime::actuator::executor,
+    };
+    use std::os::unix::fs::PermissionsExt;
+
+    /// `#[tokio::test]` 默认使用 current_thread 运行时，url类型的source不能阻塞运行时
+    #[tokio::test]
+    async fn test_url_target_on_current_thread() {
+        let dir = std::env::temp_dir().join(format!("dataflow-exe-{}", uuid::Uuid::new_v4()));
+        std::env::set_var(DATAFLOW_CACHE_DIR_ENV, dir.join("cache"));
+        // 预先放入缓存，不需要访问网络
+        let script = dir.join("tool");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&script, "#!/bin/sh\nexit 0\n").unwrap();
+        let digest = file_digest(&script).unwrap();
+        let cached = blob_path(&digest);
+        std::fs::create_dir_all(cached.parent().unwrap()).unwrap();
+        std::fs::copy(&script, &cached).unwrap();
+        std::fs::set_permissions(&cached, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let operator: NormalOperatorDefinition = serde_yaml::from_str(&format!(
+            "id: tool\nexe_target: http://127.0.0.1:9/tool\nsha256: {digest}"
+        ))
+        .unwrap();
+        let backend = Backend::Memory(MemoryBus::new());
+        let task = executor(&"node".to_owned().into(), &operator, &backend, &dir)
+            .await
+            .unwrap();
+        task.await.unwrap().unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,311 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    clock,
+    communication::{Backend, Envelope},
+    descriptor::descriptor::{DataId, NodeId, NormalOperatorDefinition, OperatorIo},
+    launch::health::NodeSignal,
+    metrics,
+    runtime::Runtime,
+    trace::{ActiveSpan, SpanKind, TraceContext},
+};
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{ChildStdin, ChildStdout},
+};
+
+/// jsonl 协议中的一行
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonLine {
+    id: String,
+    #[serde(default)]
+    data: Value,
+}
+
+/// 为设置了io的operator创建运行时，用于收发数据
+pub(crate) fn io_runtime(
+    node_id: &NodeId,
+    operator: &NormalOperatorDefinition,
+    backend: &Backend,
+) -> Result<Option<Runtime>> {
+    match operator.config.io {
+        Some(_) => operator_runtime(node_id, operator, backend).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// 创建operator的运行时，id为 `<node>/<operator>`，与输入映射的格式一致
+pub(crate) fn operator_runtime(
+    node_id: &NodeId,
+    operator: &NormalOperatorDefinition,
+    backend: &Backend,
+) -> Result<Runtime> {
+    let id = format!("{node_id}/{}", operator.id);
+    let envs: BTreeMap<String, String> = operator
+        .config
+        .envs
+        .iter()
+        .flatten()
+        .map(|(k, v)| (k.clone(), v.to_string()))
+        .collect();
+    let runtime = Runtime::init(
+        id.clone(),
+        operator.config.name.clone().unwrap_or_else(|| id.clone()),
+        operator.config.description.clone().unwrap_or_default(),
+        envs,
+        operator.config.run_config.clone(),
+        backend,
+    )
+    .with_context(|| format!("failed to init runtime of operator `{id}`"))?;
+    Ok(runtime)
+}
+
+/// 数据转为json，本身是json时直接使用，否则作为字符串
+pub(crate) fn json_value(data: &[u8]) -> Value {
+    serde_json::from_slice(data)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(data).into_owned()))
+}
+
+/// 订阅运行时的所有输入，收到的消息合并到一个通道中
+/// 接收者是阻塞的，每个输入放到单独的线程中，所有输入断开之后通道关闭
+pub(crate) fn forward_inputs(
+    runtime: &mut Runtime,
+) -> Result<tokio::sync::mpsc::Receiver<(DataId, Envelope)>> {
+    let (tx, rx) = tokio::sync::mpsc::channel::<(DataId, Envelope)>(16);
+    let input_ids: Vec<DataId> = runtime.node_config().inputs.keys().cloned().collect();
+    for input_id in input_ids {
+        let mut receiver = runtime.receiver(&input_id)?;
+        let tx = tx.clone();
+        tokio::task::spawn_blocking(move || {
+            while let Ok(Some(envelope)) = receiver.recv_envelope() {
+                if tx.blocking_send((input_id.clone(), envelope)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    Ok(rx)
+}
+
+/// 将收到的输入编码为写入子进程标准输入的一行
+pub(crate) fn encode_input(io: OperatorIo, id: &DataId, data: &[u8]) -> String {
+    match io {
+        OperatorIo::Lines => {
+            let data = String::from_utf8_lossy(data)
+                .replace('\\', "\\\\")
+                .replace('\n', "\\n");
+            format!("{id} {data}\n")
+        }
+        OperatorIo::Jsonl => {
+            // 数据本身是json时直接嵌入，否则作为字符串
+            let line = JsonLine {
+                id: id.to_string(),
+                data: json_value(data),
+            };
+            format!("{}\n", serde_json::to_string(&line).unwrap())
+        }
+    }
+}
+
+/// 解析子进程标准输出的一行，得到output id和数据
+pub(crate) fn decode_output(io: OperatorIo, line: &str) -> Result<(DataId, Vec<u8>)> {
+    match io {
+        OperatorIo::Lines => {
+            let (id, data) = line.split_once(' ').unwrap_or((line, ""));
+            let mut unescaped = String::with_capacity(data.len());
+            let mut chars = data.chars();
+            while let Some(c) = chars.next() {
+                match (c, chars.clone().next()) {
+                    ('\\', Some('n')) => {
+                        unescaped.push('\n');
+                        chars.next();
+                    }
+                    ('\\', Some('\\')) => {
+                        unescaped.push('\\');
+                        chars.next();
+                    }
+                    _ => unescaped.push(c),
+                }
+            }
+            Ok((DataId::from(id.to_owned()), unescaped.into_bytes()))
+        }
+        OperatorIo::Jsonl => {
+            let line: JsonLine = serde_json::from_str(line)
+                .context("expected a json line like {\"id\": .., \"data\": ..}")?;
+            let data = match line.data {
+                Value::Null => vec![],
+                Value::String(data) => data.into_bytes(),
+                data => serde_json::to_vec(&data)?,
+            };
+            Ok((DataId::from(line.id), data))
+        }
+    }
+}
+
+/// 连接子进程的标准输入输出与dataflow
+/// 每个收到的输入写入子进程的标准输入，子进程标准输出的每一行发布到对应的output
+/// 开启追踪时，输入写入后到下一个输入之前的输出都属于该输入的处理span
+pub(crate) fn bridge(
+    mut runtime: Runtime,
+    io: OperatorIo,
+    mut stdin: ChildStdin,
+    stdout: ChildStdout,
+) -> Result<()> {
+    // 没有输入时，子进程的标准输入会直接关闭
+    let mut rx = forward_inputs(&mut runtime)?;
+
+    let operator_id = runtime.id().clone();
+    let attributes: Vec<(&str, String)> = runtime
+        .span_attributes()
+        .into_iter()
+        .map(|(k, v)| (k, v.to_owned()))
+        .collect();
+    let topics: BTreeMap<DataId, String> = runtime
+        .node_config()
+        .inputs
+        .iter()
+        .map(|(id, input)| (id.clone(), input.mapping.topic()))
+        .collect();
+    // 当前输入的处理span，由写入输入的任务开始，由发布输出的任务读取
+    let processing: Arc<Mutex<Option<ActiveSpan>>> = Arc::default();
+    let current = processing.clone();
+    tokio::spawn(async move {
+        while let Some((input_id, envelope)) = rx.recv().await {
+            // 上一个输入的处理结束
+            current.lock().unwrap().take();
+            let topic = topics.get(&input_id).cloned().unwrap_or_default();
+            let mut span_attributes: Vec<(&str, &str)> =
+                attributes.iter().map(|(k, v)| (*k, v.as_str())).collect();
+            span_attributes.extend([
+                ("dataflow.data_id", input_id.as_str()),
+                ("dataflow.topic", &topic),
+            ]);
+            // 接收的span从消息发布开始，到写入子进程的标准输入结束
+            let receive = ActiveSpan::start(
+                format!("receive {topic}"),
+                SpanKind::Consumer,
+                TraceContext::from_metadata(&envelope.metadata),
+                &span_attributes,
+            )
+            .map(|span| match envelope.timestamp {
+                // 模拟时钟下消息的时间戳仍然是系统时间，不能作为span的开始
+                Some(timestamp) if !clock::is_simulated() => span.started_at(timestamp),
+                _ => span,
+            });
+            let line = encode_input(io, &input_id, &envelope.data);
+            let written = match stdin.write_all(line.as_bytes()).await {
+                Ok(()) => stdin.flush().await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = written {
+                warn!("operator {operator_id} stopped reading stdin: {e}");
+                // 没有写入的输入都被丢弃
+                metrics::inc(
+                    &metrics::MESSAGES_DROPPED,
+                    &[("operator", &operator_id), ("reason", "stdin_closed")],
+                    1 + rx.len() as u64,
+                );
+                break;
+            }
+            let context = receive.as_ref().map(ActiveSpan::context);
+            drop(receive);
+            // 没有输出时处理span在写入后就结束，有输出时延长到最后一个输出
+            let mut process = ActiveSpan::start(
+                format!("process {input_id}"),
+                SpanKind::Internal,
+                context,
+                &span_attributes,
+            );
+            if let Some(span) = &mut process {
+                span.mark_end();
+            }
+            *current.lock().unwrap() = process;
+        }
+        current.lock().unwrap().take();
+    });
+
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stdout).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.trim().is_empty() {
+                continue;
+            }
+            // 就绪和心跳信号不是数据，交给节点汇总
+            match NodeSignal::parse(&line) {
+                Some(NodeSignal::Ready) => {
+                    runtime.report_ready();
+                    continue;
+                }
+                Some(NodeSignal::Heartbeat) => {
+                    runtime.heartbeat();
+                    continue;
+                }
+                None => {}
+            }
+            // 输出属于当前的处理span，处理span延长到最后一个输出
+            let parent = processing.lock().unwrap().as_mut().map(|span| {
+                span.mark_end();
+                span.context()
+            });
+            let result = match decode_output(io, &line) {
+                Ok((output_id, data)) => runtime
+                    .send_output_with_context(&output_id, &data, parent)
+                    .map_err(|e| ("publish_failed", e)),
+                Err(e) => Err(("invalid_output", e)),
+            };
+            match result {
+                Ok(()) => debug!("operator {} published `{line}`", runtime.id()),
+                Err((reason, e)) => {
+                    warn!(
+                        "operator {} ignored stdout line `{line}`: {e:#}",
+                        runtime.id()
+                    );
+                    metrics::inc(
+                        &metrics::MESSAGES_DROPPED,
+                        &[("operator", runtime.id()), ("reason", reason)],
+                        1,
+                    );
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let id = DataId::from("value".to_owned());
+        // json数据直接嵌入，其他数据作为字符串
+        assert_eq!(
+            encode_input(OperatorIo::Jsonl, &id, br#"{"a":1}"#),
+            "{\"id\":\"value\",\"data\":{\"a\":1}}\n"
+        );
+        assert_eq!(
+            encode_input(OperatorIo::Jsonl, &id, b""),
+            "{\"id\":\"value\",\"data\":\"\"}\n"
+        );
+        let (output, data) =
+            decode_output(OperatorIo::Jsonl, r#"{"id":"out","data":[1,2]}"#).unwrap();
+        assert_eq!(output.as_str(), "out");
+        assert_eq!(data, b"[1,2]");
+        assert!(decode_output(OperatorIo::Jsonl, "not json").is_err());
+
+        // lines 协议中的换行会被转义，解析时还原
+        let line = encode_input(OperatorIo::Lines, &id, b"a\\b\nc");
+        assert_eq!(line, "value a\\\\b\\nc\n");
+        let (output, data) = decode_output(OperatorIo::Lines, line.trim_end()).unwrap();
+        assert_eq!(output, id);
+        assert_eq!(data, b"a\\b\nc");
+    }
+}
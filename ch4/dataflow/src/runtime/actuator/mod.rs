@@ -3,33 +3,131 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use crate::descriptor::descriptor::{Deploy, NormalOperatorDefinition, OperatorSource};
-use anyhow::Result;
+use crate::{
+    communication::Backend,
+    descriptor::{
+        builtin::BuiltinOperator,
+        descriptor::{NodeId, NormalOperatorDefinition, OperatorSource},
+    },
+    launch::health::NodeSignal,
+    runtime::{resources::LimitWatcher, signals},
+};
+use anyhow::{Context, Result};
 use log::debug;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
 
-use self::{exe_target::ExeTarget, shell::Shell};
+use self::{
+    builtin::Builtin,
+    exe_target::ExeTarget,
+    io::{io_runtime, operator_runtime},
+    shell::Shell,
+};
 
+pub mod builtin;
 pub mod exe_target;
+pub mod io;
 pub mod python_module;
 pub mod shared_library;
 pub mod shell;
 pub mod wasm_module;
 
+/// operator 输出行的前缀，launch 据此识别日志来自哪个operator
+pub(crate) const OPERATOR_LOG_PREFIX: &str = "[operator ";
+
+/// 将operator子进程的输出逐行转发到节点进程的标准输出或标准错误，并加上operator前缀
+/// 同时交给 watcher 识别可能由rlimit导致的错误，就绪和心跳信号交给节点汇总
+pub(crate) fn forward_output<R>(
+    operator_id: String,
+    stream: R,
+    stderr: bool,
+    watcher: LimitWatcher,
+) where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stream).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if let Some(signal) = NodeSignal::parse(&line) {
+                signals::report(&operator_id, signal);
+                continue;
+            }
+            watcher.observe(&line);
+            if stderr {
+                eprintln!("{OPERATOR_LOG_PREFIX}{operator_id}] {line}");
+            } else {
+                println!("{OPERATOR_LOG_PREFIX}{operator_id}] {line}");
+            }
+        }
+    });
+}
+
 /// 执行器trait
 pub(crate) trait OperatorActuator {
     fn execute(&mut self, working_dir: &PathBuf) -> Result<tokio::task::JoinHandle<Result<()>>>;
 }
 
+/// 解析operator的最终参数
+/// 变量依次从operator的环境变量、dataflow的内置变量和进程的环境变量中查找
+pub(crate) fn operator_argv(
+    node_id: &NodeId,
+    operator: &NormalOperatorDefinition,
+    working_dir: &Path,
+) -> Result<Vec<String>> {
+    let Some(args) = &operator.config.args else {
+        return Ok(vec![]);
+    };
+    let lookup = |name: &str| {
+        if let Some(value) = operator
+            .config
+            .envs
+            .as_ref()
+            .and_then(|envs| envs.get(name))
+        {
+            return Some(value.to_string());
+        }
+        match name {
+            "DATAFLOW_NODE_ID" => Some(node_id.to_string()),
+            "DATAFLOW_OPERATOR_ID" => Some(operator.id.to_string()),
+            "DATAFLOW_WORKING_DIR" => Some(working_dir.display().to_string()),
+            _ => env::var(name).ok(),
+        }
+    };
+    args.argv(&lookup).with_context(|| {
+        format!(
+            "failed to resolve args of operator `{node_id}/{}`",
+            operator.id
+        )
+    })
+}
+
 /// 构造operator的执行器
 pub(crate) async fn executor(
+    node_id: &NodeId,
     operator: &NormalOperatorDefinition,
-    deploy: &Deploy,
+    backend: &Backend,
     working_dir: &PathBuf,
 ) -> Result<tokio::task::JoinHandle<Result<()>>> {
+    let argv = operator_argv(node_id, operator, working_dir)?;
+    debug!("OperatorActuator {node_id}/{} argv {:?}", operator.id, argv);
     let mut child: Box<dyn OperatorActuator> = match &operator.config.source {
         // 可执行文件
-        OperatorSource::ExeTarget(_) => Box::new(ExeTarget(operator.clone())),
-        OperatorSource::Shell(_) => Box::new(Shell(operator.clone())),
+        // url类型的source在这里异步下载，execute 中不再阻塞运行时
+        OperatorSource::ExeTarget(_) => Box::new(ExeTarget(
+            operator.clone(),
+            argv,
+            exe_target::program(operator).await?,
+        )),
+        OperatorSource::Shell(_) => Box::new(Shell(
+            operator.clone(),
+            argv,
+            io_runtime(node_id, operator, backend)?,
+        )),
+        // 内置operator在当前进程中运行
+        OperatorSource::Builtin(name) => Box::new(Builtin(
+            operator.clone(),
+            BuiltinOperator::parse(name, operator.config.params.as_ref())?,
+            Some(operator_runtime(node_id, operator, backend)?),
+        )),
         OperatorSource::PythonModule(_) => todo!(),
         OperatorSource::SharedLibrary(_) => todo!(),
         OperatorSource::WasmModule(_) => todo!(),
@@ -1,33 +1,30 @@
 use std::path::PathBuf;
 
-use crate::descriptor::descriptor::NormalOperatorDefinition;
+use crate::{
+    descriptor::descriptor::NormalOperatorDefinition,
+    runtime::{resources::apply as apply_resources, Runtime},
+    shell_command,
+};
 use anyhow::{anyhow, Context, Result};
 use log::debug;
-use tokio::process::Command;
+use std::process::Stdio;
 
-use super::OperatorActuator;
+use super::{forward_output, io::bridge, OperatorActuator};
 
 // 定义一个结构体来作为执行类型
-pub(crate) struct Shell(pub NormalOperatorDefinition);
+// 第二个字段是解析后的参数
+// 设置了io时，同时持有用于收发数据的运行时
+pub(crate) struct Shell(
+    pub NormalOperatorDefinition,
+    pub Vec<String>,
+    pub Option<Runtime>,
+);
 impl OperatorActuator for Shell {
     fn execute(&mut self, working_dir: &PathBuf) -> Result<tokio::task::JoinHandle<Result<()>>> {
         let operator_id = self.0.id.to_string();
         let shell = self.0.config.source.to_string();
-        let args = self
-            .0
-            .config
-            .args
-            .as_ref()
-            .map(String::as_str)
-            .unwrap_or_default();
-        let mut shell_cmd = Command::new(
-            if cfg!(target_os = "windows") {
-                "cmd"
-            } else {
-                "sh"
-            }
-        );
-        shell_cmd.args(["/C", shell.as_str(), args]);
+        // 通过平台的shell执行，参数原样传递给命令
+        let mut shell_cmd = shell_command(&shell, &self.1);
 
         if let Some(envs) = &self.0.config.envs {
             for (k, v) in envs.iter() {
@@ -37,11 +34,36 @@ impl OperatorActuator for Shell {
         shell_cmd.current_dir(working_dir);
         debug!("OperatorActuator Shell exec command {:?}", shell_cmd);
 
+        // 设置了io时，通过标准输入输出交换数据，否则标准输出和标准错误一样作为日志转发
+        if self.2.is_some() {
+            shell_cmd.stdin(Stdio::piped());
+        }
+        shell_cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+
+        // 应用资源限制，需要在启动进程之前设置
+        let resources = apply_resources(
+            &mut shell_cmd,
+            &operator_id,
+            self.0.config.resources.as_ref(),
+        )?;
         let mut child = shell_cmd
             .spawn()
             .with_context(|| format!("failed to run command `{}`", shell))?;
+        if let (Some(runtime), Some(io)) = (self.2.take(), self.0.config.io) {
+            let stdin = child.stdin.take().expect("failed to take stdin");
+            let stdout = child.stdout.take().expect("failed to take stdout");
+            bridge(runtime, io, stdin, stdout)
+                .with_context(|| format!("failed to connect io of operator `{operator_id}`"))?;
+        }
+        if let Some(stdout) = child.stdout.take() {
+            forward_output(operator_id.clone(), stdout, false, resources.watcher());
+        }
+        let stderr = child.stderr.take().expect("failed to take stderr");
+        forward_output(operator_id.clone(), stderr, true, resources.watcher());
         let result = tokio::spawn(async move {
             let status = child.wait().await.context("child process failed")?;
+            // 超出资源限制时返回可识别的错误
+            resources.finish(&status)?;
             if status.success() {
                 println!("operator {operator_id} finished");
                 Ok(())
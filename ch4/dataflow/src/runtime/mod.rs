@@ -1,15 +1,27 @@
 pub mod actuator;
 pub mod node;
+pub mod resources;
+pub mod signals;
 pub mod timer;
-
 use std::collections::BTreeMap;
 
 use crate::{
-    communication::{pub_sub::ZenohCommunicationLayer, PubSubCommunicationLayer, Publisher},
+    communication::{
+        Backend, BoxError, Envelope, PubSubCommunicationLayer, Publisher, Subscriber,
+    },
     descriptor::descriptor::{DataId, NodeRunConfig},
+    launch::health::NodeSignal,
+    metrics,
+    trace::{ActiveSpan, SpanKind, TraceContext},
 };
 use anyhow::{anyhow, Result};
 use log::debug;
+use timer::TIMER_NODE_ID;
+
+/// operator 报告就绪时在标准输出中输出的行
+pub const READY_LINE: &str = "@dataflow:ready";
+/// operator 发送心跳时在标准输出中输出的行，节点汇总后同样输出给launch
+pub const HEARTBEAT_LINE: &str = "@dataflow:heartbeat";
 
 /// 运行时
 pub struct Runtime {
@@ -34,19 +46,17 @@ impl Runtime {
     /// description: Operator description,
     /// envs: Operator Env vars,
     /// node_config: Operator node_config,
-    /// endpoints: Node Deploy endpoints,
-    /// mode: Node Deploy mode,
+    /// backend: Node communication backend,
     pub fn init(
         id: String,
         name: String,
         description: String,
         envs: BTreeMap<String, String>,
         node_config: NodeRunConfig,
-        endpoints: Vec<String>,
-        mode: String,
+        backend: &Backend,
     ) -> Result<Self> {
-        debug!("Node {:?} init at {:?}", id, endpoints);
-        let communication = Box::new(ZenohCommunicationLayer::init(endpoints, mode, id.clone())?);
+        debug!("Node {:?} init", id);
+        let communication = backend.connect(&id)?;
         Ok(Self {
             id,
             name,
@@ -71,17 +81,96 @@ impl Runtime {
             .dyn_clone())
     }
     /// 从当前节点向output发送数据
+    /// 发送者已经以节点id作为前缀，topic为 `<id>/<output>`
     pub fn send_output(&mut self, data_id: &DataId, data: &[u8]) -> Result<()> {
+        self.send_output_with_context(data_id, data, None)
+    }
+
+    /// 发送数据，开启追踪时记录发布的span，parent 为产生该数据的span
+    /// span 的上下文随消息的元数据传递给接收者
+    pub fn send_output_with_context(
+        &mut self,
+        data_id: &DataId,
+        data: &[u8],
+        parent: Option<TraceContext>,
+    ) -> Result<()> {
         let topic = format!("{self_id}/{data_id}", self_id = &self.id);
         if !self.node_config.outputs.contains(data_id) {
             return Err(anyhow!("send output failed ,unknown output {data_id}"));
         }
-        self.sender(&DataId::from(topic.clone()))?
-            .publish(data)
+        let mut attributes = self.span_attributes();
+        attributes.extend([
+            ("dataflow.data_id", data_id.as_str()),
+            ("dataflow.topic", &topic),
+        ]);
+        let span = ActiveSpan::start(
+            format!("publish {topic}"),
+            SpanKind::Producer,
+            parent,
+            &attributes,
+        );
+        let metadata = span.as_ref().map(ActiveSpan::metadata).unwrap_or_default();
+        self.sender(data_id)?
+            .publish_with_metadata(data, &metadata)
             .map_err(|e| anyhow!("send output to topic:{topic} failed,: {e}"))?;
+        metrics::inc(&metrics::MESSAGES_SENT, &[("topic", &topic)], 1);
+        metrics::inc(
+            &metrics::BYTES_SENT,
+            &[("topic", &topic)],
+            data.len() as u64,
+        );
         Ok(())
     }
 
+    /// 获取当前节点某个输入的接收者，topic为输入映射的来源
+    pub fn receiver(&mut self, input_id: &DataId) -> Result<Box<dyn Subscriber>> {
+        let input = self
+            .node_config
+            .inputs
+            .get(input_id)
+            .ok_or_else(|| anyhow!("unknown input {input_id} of node {}", self.id))?;
+        let topic = input.mapping.topic();
+        log::debug!("Node {:?} receiver {input_id} from {topic}", self.id);
+        let subscriber = self
+            .communication
+            .subscribe(&topic)
+            .map_err(|e| anyhow!("failed to subscribe topic:{topic} for input {input_id}: {e}"))?;
+        Ok(Box::new(MeteredSubscriber {
+            operator: self.id.clone(),
+            topic,
+            inner: subscriber,
+        }))
+    }
+
+    /// 报告当前operator已经就绪，节点设置了 `readiness.signal: sdk` 时launch会等待该信号
+    /// 节点中所有声明就绪的operator都报告之后，由节点转发给launch
+    pub fn report_ready(&self) {
+        signals::report(self.operator_id(), NodeSignal::Ready);
+    }
+
+    /// 发送一次心跳，节点设置了 `liveness` 时operator需要在处理循环中按间隔调用
+    /// operator卡住不再调用时，launch会报告节点失去响应
+    pub fn heartbeat(&self) {
+        signals::report(self.operator_id(), NodeSignal::Heartbeat);
+    }
+
+    /// 运行时所属的operator id，运行时id为 `<node>/<operator>`
+    fn operator_id(&self) -> &str {
+        self.id
+            .split_once('/')
+            .map_or(self.id.as_str(), |(_, operator)| operator)
+    }
+
+    /// span 的节点和operator属性，operator的运行时id为 `<node>/<operator>`
+    pub(crate) fn span_attributes(&self) -> Vec<(&'static str, &str)> {
+        match self.id.split_once('/') {
+            Some((node, operator)) if self.id != TIMER_NODE_ID => {
+                vec![("dataflow.node", node), ("dataflow.operator", operator)]
+            }
+            _ => vec![("dataflow.node", self.id.as_str())],
+        }
+    }
+
     /// 获取节点id
     pub fn id(&self) -> &String {
         &self.id
@@ -99,3 +188,34 @@ impl Runtime {
         &self.node_config
     }
 }
+
+/// 记录接收指标的订阅者：消息数、字节数、队列深度和端到端延迟
+struct MeteredSubscriber {
+    operator: String,
+    topic: String,
+    inner: Box<dyn Subscriber>,
+}
+
+impl Subscriber for MeteredSubscriber {
+    fn recv_envelope(&mut self) -> Result<Option<Envelope>, BoxError> {
+        let envelope = self.inner.recv_envelope()?;
+        if let Some(envelope) = &envelope {
+            let labels = [("operator", self.operator.as_str()), ("topic", &self.topic)];
+            metrics::inc(&metrics::MESSAGES_RECEIVED, &labels, 1);
+            metrics::inc(
+                &metrics::BYTES_RECEIVED,
+                &labels,
+                envelope.data.len() as u64,
+            );
+            metrics::set(&metrics::QUEUE_DEPTH, &labels, self.inner.pending() as i64);
+            if let Some(timestamp) = envelope.timestamp {
+                metrics::observe_latency(&labels, timestamp);
+            }
+        }
+        Ok(envelope)
+    }
+
+    fn pending(&self) -> usize {
+        self.inner.pending()
+    }
+}
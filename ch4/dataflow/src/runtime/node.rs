@@ -1,18 +1,125 @@
 use std::{collections::BTreeMap, path::PathBuf};
 
 use crate::{
-    descriptor::descriptor::{Deploy, NormalNode},
-    runtime::actuator::executor,
+    clock,
+    communication::Backend,
+    descriptor::descriptor::{ClockMode, Deploy, NormalNode},
+    event::Event,
+    metrics,
+    runtime::{
+        actuator::executor,
+        resources::{CgroupCleanup, ResourceLimitExceeded},
+        signals,
+    },
+    trace,
 };
-use anyhow::{Context, Result};
-use futures::{stream::FuturesUnordered, StreamExt};
+use anyhow::{anyhow, Context, Result};
+use futures::{stream::FuturesUnordered, FutureExt, StreamExt};
 use log::{error, info};
+use tokio::task::JoinHandle;
 
 /// 启动一个节点，拉起多个操作节点，并在此进行控制
 pub async fn start(node: &NormalNode, deploy: &Deploy, working_dir: &PathBuf) -> Result<()> {
     info!("Start Node {:#?} ", node.id);
 
-    let mut tasks = FuturesUnordered::new();
+    // 使用模拟时钟时，跟随定时器节点发布的模拟时间
+    if deploy.clock == Some(ClockMode::Simulated) {
+        let endpoints = deploy
+            .endpoints
+            .clone()
+            .ok_or_else(|| anyhow!("dataflow has no endpoints defined"))?;
+        clock::follow(endpoints, node.id.as_str())
+            .context("failed to follow the simulated clock")?;
+    }
+
+    // 开启了指标时，定时向launch汇报本进程的指标快照
+    if deploy.metrics.is_some() {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(metrics::REPORT_INTERVAL);
+            loop {
+                ticker.tick().await;
+                println!("{}", metrics::report_line());
+            }
+        });
+    }
+
+    // 开启了追踪时，定时向launch汇报结束的span
+    if deploy.tracing.is_some() {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(trace::EXPORT_INTERVAL);
+            loop {
+                ticker.tick().await;
+                if let Some(line) = trace::report_line() {
+                    println!("{line}");
+                }
+            }
+        });
+    }
+
+    let backend = Backend::from_deploy(deploy)?;
+    // 节点结束时删除为operator创建的cgroup
+    let _cgroups = CgroupCleanup;
+    // 汇总operator的就绪和心跳信号，需要在operator启动之前开始
+    let _signals = signals::watch(node);
+    let mut tasks: FuturesUnordered<_> = spawn_operators(node, &backend, working_dir)
+        .await?
+        .into_iter()
+        .map(|(operator_id, task)| task.map(move |result| (operator_id, result)))
+        .collect();
+    while let Some((operator_id, task_result)) = tasks.next().await {
+        signals::exited(&operator_id);
+        let outcome = match task_result {
+            Err(e) => {
+                error!(
+                    "start node failed to join one async task of operators: {}",
+                    e
+                );
+                "failure"
+            }
+            // 超出资源限制时转为事件，并汇报给launch
+            Ok(Err(e)) => match e.downcast_ref::<ResourceLimitExceeded>() {
+                Some(exceeded) => {
+                    println!("{}", exceeded.report_line());
+                    let event = Event::ResourceLimitExceeded(exceeded.clone());
+                    error!("node {} received {event}", node.id);
+                    "resource_limit_exceeded"
+                }
+                None => {
+                    error!("node {} operator failed: {e:#}", node.id);
+                    "failure"
+                }
+            },
+            Ok(Ok(())) => "success",
+        };
+        metrics::inc(
+            &metrics::PROCESS_EXITS,
+            &[
+                ("process", "operator"),
+                ("operator", &operator_id),
+                ("outcome", outcome),
+            ],
+            1,
+        );
+    }
+    // 最后一次汇报，包含所有operator的退出
+    if deploy.metrics.is_some() {
+        println!("{}", metrics::report_line());
+    }
+    if let Some(line) = trace::report_line() {
+        println!("{line}");
+    }
+    info!("Start Nodes Success");
+    Ok(())
+}
+
+/// 启动节点的所有operator，返回每个operator的id和运行任务
+/// operator继承节点的名称、描述、环境变量和资源限制
+pub(crate) async fn spawn_operators(
+    node: &NormalNode,
+    backend: &Backend,
+    working_dir: &PathBuf,
+) -> Result<Vec<(String, JoinHandle<Result<()>>)>> {
+    let mut tasks = vec![];
 
     for operator in &node.kind.operators {
         let mut operator_clone = operator.clone();
@@ -43,7 +150,15 @@ pub async fn start(node: &NormalNode, deploy: &Deploy, working_dir: &PathBuf) ->
                     .insert(k.clone(), v.clone());
             }
         }
-        let result = executor(&operator_clone, deploy, working_dir)
+        // operator的资源限制覆盖节点的资源限制
+        if let Some(resources) = &node.resources {
+            let merged = match &operator_clone.config.resources {
+                Some(operator_resources) => resources.merge(operator_resources),
+                None => resources.clone(),
+            };
+            operator_clone.config.resources = Some(merged);
+        }
+        let result = executor(&node.id, &operator_clone, backend, working_dir)
             .await
             .with_context(|| {
                 format!(
@@ -51,16 +166,7 @@ pub async fn start(node: &NormalNode, deploy: &Deploy, working_dir: &PathBuf) ->
                     operator_id = operator.id
                 )
             })?;
-        tasks.push(result);
-    }
-    while let Some(task_result) = tasks.next().await {
-        if let Err(e) = task_result {
-            error!(
-                "start node failed to join one async task of operators: {}",
-                e
-            );
-        }
+        tasks.push((operator.id.to_string(), result));
     }
-    info!("Start Nodes Success");
-    Ok(())
+    Ok(tasks)
 }
@@ -0,0 +1,540 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::descriptor::descriptor::Resources;
+use anyhow::{bail, Context, Result};
+use log::{debug, warn};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+/// cgroup v2 的挂载点
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+/// cpu.max 中使用的周期，单位为微秒
+const CPU_PERIOD: u64 = 100_000;
+/// 节点进程向launch汇报超出资源限制时输出的行前缀，后面是事件的json
+pub const LIMIT_LINE_PREFIX: &str = "@dataflow:limit ";
+
+/// 使用rlimit限制时，进程输出中表示内存分配失败(ENOMEM)的内容
+/// 只用于提示可能的原因，不作为超出限制的依据
+const MEMORY_MARKERS: &[&str] = &[
+    "Cannot allocate memory",
+    "memory allocation of",
+    "MemoryError",
+    "std::bad_alloc",
+    "out of memory",
+];
+/// 进程输出中表示打开的文件数超出限制(EMFILE)的内容
+const NOFILE_MARKERS: &[&str] = &["Too many open files"];
+
+/// operator 超出资源限制时返回的错误，监控者可以通过 downcast 识别
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceLimitExceeded {
+    /// operator的id
+    pub operator: String,
+    /// 超出的资源，如 memory
+    pub resource: String,
+    /// 设置的限制
+    pub limit: String,
+}
+
+impl fmt::Display for ResourceLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "operator {} was killed after exceeding its {} limit of {}",
+            self.operator, self.resource, self.limit
+        )
+    }
+}
+
+impl std::error::Error for ResourceLimitExceeded {}
+
+impl ResourceLimitExceeded {
+    /// 节点进程汇报给launch的一行
+    pub fn report_line(&self) -> String {
+        format!(
+            "{LIMIT_LINE_PREFIX}{}",
+            serde_json::to_string(self).unwrap()
+        )
+    }
+
+    /// 解析节点进程汇报的一行，不是资源限制汇报的行返回 None
+    pub(crate) fn parse_report(line: &str) -> Option<ResourceLimitExceeded> {
+        let json = line.trim().strip_prefix(LIMIT_LINE_PREFIX.trim_end())?;
+        serde_json::from_str(json)
+            .map_err(|e| warn!("ignored invalid resource limit report: {e}"))
+            .ok()
+    }
+}
+
+/// 已经应用到operator进程上的资源限制
+/// 进程结束后通过 `finish` 检查是否超出了限制，并清理cgroup
+#[derive(Debug, Default)]
+pub(crate) struct ResourceGuard {
+    operator: String,
+    resources: Resources,
+    /// 为该operator创建的cgroup
+    cgroup: Option<PathBuf>,
+    /// 通过rlimit限制的资源，从进程的输出中识别可能的超出
+    watcher: LimitWatcher,
+}
+
+/// 从operator的输出中识别可能由rlimit导致的失败，rlimit不会像cgroup一样记录超出的次数
+/// 输出中的内容只是猜测，进程失败时作为提示输出，不会汇报为超出限制
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LimitWatcher {
+    operator: String,
+    /// 通过rlimit限制的资源和限制的值
+    limits: Vec<(&'static str, String, &'static [&'static str])>,
+    /// 第一次识别到的可能超出的资源
+    suspected: Arc<Mutex<Option<(&'static str, String)>>>,
+}
+
+impl LimitWatcher {
+    /// 检查operator输出的一行
+    pub(crate) fn observe(&self, line: &str) {
+        for (resource, limit, markers) in &self.limits {
+            if !markers.iter().any(|marker| line.contains(marker)) {
+                continue;
+            }
+            let mut suspected = self.suspected.lock().unwrap();
+            if suspected.is_none() {
+                debug!(
+                    "operator {} may have hit its {resource} limit of {limit}: {line}",
+                    self.operator
+                );
+                *suspected = Some((resource, limit.clone()));
+            }
+            return;
+        }
+    }
+
+    /// 输出中第一次出现的可能超出的资源和限制
+    pub(crate) fn suspected(&self) -> Option<(&'static str, String)> {
+        self.suspected.lock().unwrap().clone()
+    }
+}
+
+/// 在启动进程之前应用资源限制
+/// cgroup v2 可用时，内存和cpu通过cgroup限制，否则内存退回到 RLIMIT_AS，cpu配额被忽略
+/// 文件数量和nice值在子进程exec之前设置
+pub(crate) fn apply(
+    command: &mut tokio::process::Command,
+    operator: &str,
+    resources: Option<&Resources>,
+) -> Result<ResourceGuard> {
+    let Some(resources) = resources.filter(|r| !r.is_empty()) else {
+        return Ok(ResourceGuard::default());
+    };
+    let cgroup = create_cgroup(operator, resources);
+    if cgroup.is_none() && resources.cpus.is_some() {
+        warn!("operator {operator}: cpu quota needs a writable cgroup v2, ignored");
+    }
+    let mut limits = vec![];
+    if let (None, Some(memory)) = (&cgroup, resources.memory) {
+        limits.push(("memory", memory.to_string(), MEMORY_MARKERS));
+    }
+    if let Some(nofile) = resources.nofile {
+        limits.push(("nofile", nofile.to_string(), NOFILE_MARKERS));
+    }
+    #[cfg(target_os = "linux")]
+    set_pre_exec(command, resources, cgroup.as_deref())?;
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = command;
+        warn!("operator {operator}: resource limits are only supported on linux, ignored");
+    }
+    Ok(ResourceGuard {
+        operator: operator.to_owned(),
+        resources: resources.clone(),
+        cgroup,
+        watcher: LimitWatcher {
+            operator: operator.to_owned(),
+            limits,
+            suspected: Default::default(),
+        },
+    })
+}
+
+impl ResourceGuard {
+    /// 用于检查operator输出的watcher，需要传给输出的转发
+    pub(crate) fn watcher(&self) -> LimitWatcher {
+        self.watcher.clone()
+    }
+
+    /// 进程结束后检查是否超出了资源限制，并清理cgroup
+    /// 只有确定的信号才汇报为超出限制：cgroup记录的 oom_kill，或者使用 RLIMIT_AS 时进程被 SIGKILL、SIGSEGV 结束
+    /// 输出中的错误信息只作为可能的原因提示
+    pub(crate) fn finish(self, status: &ExitStatus) -> Result<(), ResourceLimitExceeded> {
+        let memory_exceeded = match &self.cgroup {
+            Some(cgroup) => {
+                let oom_killed = read_counter(&cgroup.join("memory.events"), "oom_kill") > 0;
+                let throttled = read_counter(&cgroup.join("cpu.stat"), "nr_throttled");
+                if throttled > 0 {
+                    debug!(
+                        "operator {} was cpu throttled {throttled} times",
+                        self.operator
+                    );
+                }
+                if let Err(e) = std::fs::remove_dir(cgroup) {
+                    debug!("failed to remove cgroup {}: {e}", cgroup.display());
+                }
+                oom_killed
+            }
+            // 只有linux上设置了 RLIMIT_AS
+            None => cfg!(target_os = "linux") && killed_by_memory_signal(status),
+        };
+        match self.resources.memory {
+            Some(memory) if memory_exceeded && !status.success() => {
+                return Err(ResourceLimitExceeded {
+                    operator: self.operator.clone(),
+                    resource: "memory".to_owned(),
+                    limit: memory.to_string(),
+                });
+            }
+            _ => {}
+        }
+        if let (false, Some((resource, limit))) = (status.success(), self.watcher.suspected()) {
+            warn!(
+                "operator {} failed, its output suggests it hit its {resource} limit of {limit} \
+                 (suspected, not reported as a limit event)",
+                self.operator
+            );
+        }
+        Ok(())
+    }
+}
+
+/// 使用 RLIMIT_AS 时，分配失败的进程通常被 SIGKILL 或 SIGSEGV 结束
+#[cfg(unix)]
+fn killed_by_memory_signal(status: &ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    matches!(status.signal(), Some(libc::SIGKILL | libc::SIGSEGV))
+}
+
+#[cfg(not(unix))]
+fn killed_by_memory_signal(_: &ExitStatus) -> bool {
+    false
+}
+
+/// 读取cgroup统计文件中的计数，如 memory.events 中的 `oom_kill 1`
+fn read_counter(path: &Path, key: &str) -> u64 {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(' ')?;
+            (name == key).then(|| value.trim().parse().ok()).flatten()
+        })
+        .unwrap_or_default()
+}
+
+/// 为operator创建cgroup的父cgroup，只准备一次
+/// 不可用时记录原因，之后内存退回到 RLIMIT_AS
+static PARENT: OnceCell<Option<PathBuf>> = OnceCell::new();
+
+/// 节点进程所属的父cgroup的名字前缀，后面是进程id
+const PARENT_PREFIX: &str = "dataflow-";
+
+/// 在当前进程所在的cgroup下创建节点进程所属的父cgroup `dataflow-<pid>`，operator的cgroup都创建在其中
+/// 不修改当前cgroup的 subtree_control，当前cgroup没有向子cgroup开放内存和cpu控制器时不可用
+/// 父cgroup在 `CgroupCleanup` 析构时删除，节点进程被强制结束时留下的父cgroup在下次创建时清理
+fn prepare_parent() -> Result<PathBuf> {
+    // cgroup v2 中只有一行 `0::<path>`
+    let current =
+        std::fs::read_to_string("/proc/self/cgroup").context("failed to read /proc/self/cgroup")?;
+    let Some(current) = current.lines().find_map(|line| line.strip_prefix("0::")) else {
+        bail!("cgroup v2 is not available");
+    };
+    let current = Path::new(CGROUP_ROOT).join(current.trim_start_matches('/'));
+    if !current.join("cgroup.controllers").exists() {
+        bail!("cgroup v2 is not mounted at {CGROUP_ROOT}");
+    }
+    let controllers =
+        std::fs::read_to_string(current.join("cgroup.subtree_control")).unwrap_or_default();
+    let controllers: Vec<_> = controllers.split_whitespace().collect();
+    if !controllers.contains(&"memory") || !controllers.contains(&"cpu") {
+        bail!(
+            "memory and cpu controllers are not enabled for the children of {}",
+            current.display()
+        );
+    }
+    remove_stale_parents(&current);
+
+    let parent = current.join(format!("{PARENT_PREFIX}{}", std::process::id()));
+    if let Err(e) = std::fs::create_dir(&parent) {
+        if e.kind() != std::io::ErrorKind::AlreadyExists {
+            return Err(e).with_context(|| format!("failed to create cgroup {}", parent.display()));
+        }
+    }
+    // 父cgroup中没有进程，可以为operator的cgroup开启控制器
+    if let Err(e) = std::fs::write(parent.join("cgroup.subtree_control"), "+memory +cpu") {
+        let _ = std::fs::remove_dir(&parent);
+        return Err(e).with_context(|| {
+            format!(
+                "failed to enable memory and cpu controllers in {}",
+                parent.display()
+            )
+        });
+    }
+    debug!("operator cgroups are created in {}", parent.display());
+    Ok(parent)
+}
+
+/// 清理进程已经不存在的父cgroup
+fn remove_stale_parents(current: &Path) {
+    let Ok(entries) = std::fs::read_dir(current) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(pid) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(PARENT_PREFIX))
+            .and_then(|pid| pid.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if !Path::new("/proc").join(pid.to_string()).exists() {
+            debug!("removing stale cgroup {}", entry.path().display());
+            remove_cgroup_tree(&entry.path());
+        }
+    }
+}
+
+/// 结束cgroup中剩余的进程，然后从叶子开始删除整个cgroup
+fn remove_cgroup_tree(cgroup: &Path) {
+    // cgroup.kill 需要 linux 5.14
+    let _ = std::fs::write(cgroup.join("cgroup.kill"), "1");
+    if let Ok(entries) = std::fs::read_dir(cgroup) {
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                remove_cgroup_tree(&entry.path());
+            }
+        }
+    }
+    // 进程被结束之后才能删除，稍等一下
+    for _ in 0..50 {
+        match std::fs::remove_dir(cgroup) {
+            Ok(()) => return,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+        }
+    }
+    warn!("failed to remove cgroup {}", cgroup.display());
+}
+
+/// 析构时删除节点进程创建的所有cgroup，包括仍有进程的operator的cgroup
+/// 由节点在运行operator期间持有
+pub(crate) struct CgroupCleanup;
+
+impl Drop for CgroupCleanup {
+    fn drop(&mut self) {
+        if let Some(Some(parent)) = PARENT.get() {
+            remove_cgroup_tree(parent);
+        }
+    }
+}
+
+/// 在节点进程的父cgroup下为operator创建子cgroup，并写入内存和cpu的限制
+/// cgroup v2 不可用或者没有权限时返回None
+fn create_cgroup(operator: &str, resources: &Resources) -> Option<PathBuf> {
+    if !cfg!(target_os = "linux") || (resources.memory.is_none() && resources.cpus.is_none()) {
+        return None;
+    }
+    let parent = PARENT
+        .get_or_init(|| {
+            prepare_parent()
+                .map_err(|e| {
+                    warn!(
+                        "cgroup limits are unavailable, memory falls back to \
+                         RLIMIT_AS and cpu quota is ignored: {e:#}"
+                    )
+                })
+                .ok()
+        })
+        .clone()?;
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name: String = format!(
+        "dataflow-{operator}-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    )
+    .chars()
+    .map(|c| {
+        if c.is_ascii_alphanumeric() || c == '-' {
+            c
+        } else {
+            '_'
+        }
+    })
+    .collect();
+    let cgroup = parent.join(name);
+    if let Err(e) = std::fs::create_dir(&cgroup) {
+        debug!("failed to create cgroup {}: {e}", cgroup.display());
+        return None;
+    }
+    let mut limits = vec![];
+    if let Some(memory) = resources.memory {
+        limits.push(("memory.max", memory.0.to_string()));
+        // 不使用swap，超出限制时直接触发oom
+        limits.push(("memory.swap.max", "0".to_string()));
+    }
+    if let Some(cpus) = resources.cpus {
+        let quota = ((cpus * CPU_PERIOD as f64) as u64).max(1000);
+        limits.push(("cpu.max", format!("{quota} {CPU_PERIOD}")));
+    }
+    for (file, value) in limits {
+        // 没有swap时不存在 memory.swap.max
+        if file == "memory.swap.max" && !cgroup.join(file).exists() {
+            continue;
+        }
+        if let Err(e) = std::fs::write(cgroup.join(file), &value) {
+            debug!("failed to write {file} of cgroup {}: {e}", cgroup.display());
+            let _ = std::fs::remove_dir(&cgroup);
+            return None;
+        }
+    }
+    debug!("operator {operator} uses cgroup {}", cgroup.display());
+    Some(cgroup)
+}
+
+/// 在子进程exec之前加入cgroup，并设置rlimit和nice值
+/// pre_exec 中只调用 async-signal-safe 的系统调用
+#[cfg(target_os = "linux")]
+fn set_pre_exec(
+    command: &mut tokio::process::Command,
+    resources: &Resources,
+    cgroup: Option<&Path>,
+) -> Result<()> {
+    use anyhow::Context;
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let procs = cgroup
+        .map(|cgroup| CString::new(cgroup.join("cgroup.procs").as_os_str().as_bytes()))
+        .transpose()
+        .context("cgroup path contains a nul byte")?;
+    // cgroup 可用时内存由cgroup限制，否则使用 RLIMIT_AS
+    let address_space = if procs.is_none() {
+        resources.memory.map(|memory| memory.0)
+    } else {
+        None
+    };
+    let nofile = resources.nofile;
+    let nice = resources.nice;
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(procs) = &procs {
+                // 写入0表示将当前进程加入该cgroup
+                let fd = libc::open(procs.as_ptr(), libc::O_WRONLY);
+                if fd < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+                libc::close(fd);
+                if written < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            let limits = [
+                (libc::RLIMIT_AS, address_space),
+                (libc::RLIMIT_NOFILE, nofile),
+            ];
+            for (resource, limit) in limits {
+                if let Some(limit) = limit {
+                    let rlimit = libc::rlimit {
+                        rlim_cur: limit as libc::rlim_t,
+                        rlim_max: limit as libc::rlim_t,
+                    };
+                    if libc::setrlimit(resource, &rlimit) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+            }
+            if let Some(nice) = nice {
+                if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::descriptor::descriptor::ByteSize;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    /// 运行脚本，返回 finish 的结果以及输出中识别到的可能超出的资源
+    async fn run(
+        script: &str,
+        resources: &Resources,
+    ) -> (
+        Result<(), ResourceLimitExceeded>,
+        Option<(&'static str, String)>,
+    ) {
+        let mut command = tokio::process::Command::new("sh");
+        command
+            .args(["-c", script])
+            .stderr(std::process::Stdio::piped());
+        let guard = apply(&mut command, "opener", Some(resources)).unwrap();
+        let mut child = command.spawn().unwrap();
+        let watcher = guard.watcher();
+        let mut lines = BufReader::new(child.stderr.take().unwrap()).lines();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            watcher.observe(&line);
+        }
+        let status = child.wait().await.unwrap();
+        (guard.finish(&status), watcher.suspected())
+    }
+
+    #[tokio::test]
+    async fn test_limit_exceeded() {
+        // 输出中的错误只是猜测，不汇报为超出限制
+        let nofile = Resources {
+            nofile: Some(8),
+            ..Default::default()
+        };
+        let script = "for i in 3 4 5 6 7 8 9; do eval \"exec $i</dev/null\" || exit 1; done";
+        let (result, suspected) = run(script, &nofile).await;
+        assert!(result.is_ok());
+        assert_eq!(suspected, Some(("nofile", "8".to_owned())));
+
+        let memory = Resources {
+            memory: Some(ByteSize(256 << 20)),
+            ..Default::default()
+        };
+        let (result, suspected) = run("echo 'out of memory' >&2; exit 1", &memory).await;
+        assert!(result.is_ok());
+        // cgroup 可用时内存不通过 RLIMIT_AS 限制
+        match create_cgroup("probe", &memory) {
+            Some(probe) => std::fs::remove_dir(probe).unwrap(),
+            None => {
+                assert_eq!(suspected, Some(("memory", "256MiB".to_owned())));
+                let (result, _) = run("kill -SEGV $$", &memory).await;
+                assert_eq!(result.unwrap_err().resource, "memory");
+            }
+        }
+
+        // 通过标准输出汇报给launch
+        let exceeded = ResourceLimitExceeded {
+            operator: "opener".to_owned(),
+            resource: "memory".to_owned(),
+            limit: "256MiB".to_owned(),
+        };
+        let line = exceeded.report_line();
+        assert_eq!(ResourceLimitExceeded::parse_report(&line), Some(exceeded));
+        assert_eq!(ResourceLimitExceeded::parse_report("opener finished"), None);
+    }
+}
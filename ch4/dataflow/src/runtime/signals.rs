@@ -0,0 +1,197 @@
+use std::{collections::BTreeSet, sync::Mutex};
+
+use crate::{
+    descriptor::descriptor::{NormalNode, OperatorId},
+    launch::health::NodeSignal,
+    runtime::{HEARTBEAT_LINE, READY_LINE},
+};
+use once_cell::sync::Lazy;
+use tokio::{sync::mpsc, time::MissedTickBehavior};
+
+/// 节点进程中汇总operator信号的通道，没有汇总时为空
+static OPERATOR_SIGNALS: Lazy<Mutex<Option<mpsc::UnboundedSender<OperatorEvent>>>> =
+    Lazy::new(|| Mutex::new(None));
+
+/// 节点收到的operator事件
+#[derive(Debug)]
+enum OperatorEvent {
+    Signal(String, NodeSignal),
+    Exited(String),
+}
+
+/// 报告operator的信号，由节点汇总后发送给launch
+/// 没有在节点中运行时直接输出信号行
+pub(crate) fn report(operator_id: &str, signal: NodeSignal) {
+    if let Some(tx) = OPERATOR_SIGNALS.lock().unwrap().as_ref() {
+        let _ = tx.send(OperatorEvent::Signal(operator_id.to_owned(), signal));
+        return;
+    }
+    match signal {
+        NodeSignal::Ready => println!("{READY_LINE}"),
+        NodeSignal::Heartbeat => println!("{HEARTBEAT_LINE}"),
+    }
+}
+
+/// 报告operator已经退出，不再等待它的心跳
+pub(crate) fn exited(operator_id: &str) {
+    if let Some(tx) = OPERATOR_SIGNALS.lock().unwrap().as_ref() {
+        let _ = tx.send(OperatorEvent::Exited(operator_id.to_owned()));
+    }
+}
+
+/// 节点的就绪和心跳状态
+struct NodeHealth {
+    /// 还没有报告就绪的operator，为None时不检查就绪
+    pending: Option<BTreeSet<String>>,
+    /// 运行中需要发送心跳的operator，为None时不检查存活
+    running: Option<BTreeSet<String>>,
+    /// 上一次节点心跳之后已经发送过心跳的operator
+    beaten: BTreeSet<String>,
+}
+
+impl NodeHealth {
+    fn new(node: &NormalNode) -> Self {
+        let ids = |ids: BTreeSet<OperatorId>| -> BTreeSet<String> {
+            ids.iter().map(ToString::to_string).collect()
+        };
+        let pending = ids(node.ready_operators());
+        NodeHealth {
+            pending: (!pending.is_empty()).then_some(pending),
+            running: node
+                .liveness
+                .as_ref()
+                .map(|_| ids(node.heartbeat_operators())),
+            beaten: BTreeSet::new(),
+        }
+    }
+
+    /// 处理一个operator事件，返回需要发送给launch的信号
+    fn receive(&mut self, event: OperatorEvent) -> Option<NodeSignal> {
+        match event {
+            OperatorEvent::Signal(operator, NodeSignal::Ready) => {
+                let pending = self.pending.as_mut()?;
+                if pending.remove(&operator) && pending.is_empty() {
+                    return Some(NodeSignal::Ready);
+                }
+                None
+            }
+            OperatorEvent::Signal(operator, NodeSignal::Heartbeat) => {
+                if self.running.as_ref()?.contains(&operator) {
+                    self.beaten.insert(operator);
+                }
+                self.heartbeat()
+            }
+            // 退出的operator不再需要心跳，等其余operator的下一次心跳，避免转发过期的心跳
+            OperatorEvent::Exited(operator) => {
+                self.running.as_mut()?.remove(&operator);
+                self.beaten.remove(&operator);
+                None
+            }
+        }
+    }
+
+    /// 所有运行中的operator都发送过心跳时，节点发送一次心跳
+    fn heartbeat(&mut self) -> Option<NodeSignal> {
+        let running = self.running.as_ref()?;
+        if running.is_empty() || !self.beaten.is_superset(running) {
+            return None;
+        }
+        self.beaten.clear();
+        Some(NodeSignal::Heartbeat)
+    }
+
+    /// 没有需要发送心跳的operator时，由节点定时发送心跳
+    fn idle(&self) -> bool {
+        self.running.as_ref().is_some_and(BTreeSet::is_empty)
+    }
+}
+
+/// 汇总节点中operator的就绪和心跳信号，通过标准输出发送给launch
+/// 返回的guard被drop时停止汇总
+pub(crate) fn watch(node: &NormalNode) -> WatchGuard {
+    let mut health = NodeHealth::new(node);
+    if health.pending.is_none() && health.running.is_none() {
+        return WatchGuard;
+    }
+    let interval = node.liveness.clone().unwrap_or_default().interval();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    *OPERATOR_SIGNALS.lock().unwrap() = Some(tx);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            let signal = tokio::select! {
+                event = rx.recv() => match event {
+                    Some(event) => health.receive(event),
+                    None => break,
+                },
+                _ = ticker.tick(), if health.idle() => Some(NodeSignal::Heartbeat),
+            };
+            match signal {
+                Some(NodeSignal::Ready) => println!("{READY_LINE}"),
+                Some(NodeSignal::Heartbeat) => println!("{HEARTBEAT_LINE}"),
+                None => {}
+            }
+        }
+    });
+    WatchGuard
+}
+
+/// 停止汇总operator的信号
+pub(crate) struct WatchGuard;
+
+impl Drop for WatchGuard {
+    fn drop(&mut self) {
+        OPERATOR_SIGNALS.lock().unwrap().take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_health() {
+        let node: NormalNode = serde_yaml::from_str(
+            r#"
+id: node
+readiness: { signal: sdk }
+liveness: {}
+operators:
+  - id: a
+    shell: ./a
+  - id: b
+    shell: ./b
+  - id: sink
+    builtin: stdout_sink
+"#,
+        )
+        .unwrap();
+        let mut health = NodeHealth::new(&node);
+        let signal = |operator: &str, signal| OperatorEvent::Signal(operator.to_owned(), signal);
+        // 所有非内置operator都就绪后节点才就绪
+        assert_eq!(health.receive(signal("a", NodeSignal::Ready)), None);
+        assert_eq!(health.receive(signal("a", NodeSignal::Ready)), None);
+        assert_eq!(
+            health.receive(signal("b", NodeSignal::Ready)),
+            Some(NodeSignal::Ready)
+        );
+        // 一个operator停止心跳时节点也停止心跳
+        assert_eq!(health.receive(signal("a", NodeSignal::Heartbeat)), None);
+        assert_eq!(health.receive(signal("a", NodeSignal::Heartbeat)), None);
+        assert_eq!(
+            health.receive(signal("b", NodeSignal::Heartbeat)),
+            Some(NodeSignal::Heartbeat)
+        );
+        assert_eq!(health.receive(signal("b", NodeSignal::Heartbeat)), None);
+        // 退出的operator不再需要心跳
+        assert_eq!(health.receive(OperatorEvent::Exited("a".to_owned())), None);
+        assert_eq!(
+            health.receive(signal("b", NodeSignal::Heartbeat)),
+            Some(NodeSignal::Heartbeat)
+        );
+        assert!(!health.idle());
+        health.receive(OperatorEvent::Exited("b".to_owned()));
+        assert!(health.idle());
+    }
+}
@@ -1,27 +1,75 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use crate::descriptor::descriptor::{DataId, Deploy, FormattedDuration, NodeRunConfig, NormalNode};
-use anyhow::Result;
+use crate::{
+    clock::{self, AdvanceRequest, ClockUpdate, ADVANCE_TOPIC, CLOCK_OUTPUT},
+    communication::{Backend, Publisher},
+    descriptor::descriptor::{
+        ClockMode, DataId, Deploy, HumanDuration, MissedTick, NodeRunConfig, NormalNode, Timer,
+    },
+    metrics,
+    trace::{ActiveSpan, SpanKind},
+};
+use anyhow::{anyhow, Result};
 
-use futures::StreamExt;
-use log::{debug, info};
-use tokio_stream::wrappers::IntervalStream;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use tokio::{
+    task::JoinSet,
+    time::{Instant, MissedTickBehavior},
+};
 
 use super::Runtime;
 
-/// 启动定时器节点
-pub async fn start(nodes: &Vec<NormalNode>, deploy: &Deploy) -> Result<()> {
+/// timer 任务异常退出后重新启动之前等待的时间
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// 定时器节点通过 Zenoh 通信时使用的后端
+pub fn backend(deploy: &Deploy) -> Result<Backend> {
+    Ok(Backend::Zenoh {
+        endpoints: deploy
+            .endpoints
+            .clone()
+            .ok_or_else(|| anyhow!("dataflow has no endpoints defined"))?,
+        mode: TIMER_NODE_MODE.to_string(),
+    })
+}
+
+/// 启动定时器节点，直到 shutdown 完成时才退出
+pub async fn start(
+    nodes: &Vec<NormalNode>,
+    deploy: &Deploy,
+    backend: &Backend,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
     let timer_mapping = NormalNode::collect_timer_input_from_nodes(nodes);
+    if timer_mapping.is_empty() {
+        debug!("No timer in dataflow, skip TimerNode");
+        return Ok(());
+    }
     info!("Start TimerNode {:#?} ", timer_mapping);
     let mut timer_node = TimerNode::init(
         &NodeRunConfig {
             inputs: timer_mapping.clone(),
             outputs: timer_mapping.keys().cloned().collect(),
         },
-        deploy.endpoints.as_ref().clone().unwrap(),
+        backend,
     )?;
-    timer_node.run().await?;
-    info!("Start TimerNode success");
+    if deploy.clock == Some(ClockMode::Simulated) {
+        timer_node.run_simulated(shutdown).await?;
+    } else {
+        timer_node
+            .run(deploy.missed_tick.unwrap_or_default(), shutdown)
+            .await?;
+    }
+    info!("TimerNode stopped");
     Ok(())
 }
 
@@ -36,37 +84,169 @@ pub const TIMER_NODE_DESCRIPTION: &str = "Timer nodes used throughout the entire
 
 impl TimerNode {
     /// 初始化 Timer 节点
-    pub fn init(node_config: &NodeRunConfig, endpoints: &Vec<String>) -> Result<Self> {
+    pub fn init(node_config: &NodeRunConfig, backend: &Backend) -> Result<Self> {
         Ok(Self(Runtime::init(
             TIMER_NODE_ID.to_string(),
             TIMER_NODE_NAME.to_string(),
             TIMER_NODE_DESCRIPTION.to_string(),
             BTreeMap::new(),
             node_config.clone(),
-            endpoints.clone(),
-            TIMER_NODE_MODE.to_string(),
+            backend,
         )?))
     }
-    /// 运行节点
-    pub async fn run(&mut self) -> Result<()> {
+    /// 运行节点，每个timer是一个受监督的任务，异常退出时会重新启动
+    /// shutdown 完成时停止所有的timer
+    pub async fn run(
+        &mut self,
+        missed_tick: MissedTick,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<()> {
         debug!("Node {:?} run", self.id());
-        // 收集所有的timer
-        for duration in self.node_config().collect_input_timers().into_iter() {
-            // 转为duration，并且根据其获取发送者
-            let duration_output = FormattedDuration(duration);
-            let publisher = self.0.sender(&DataId::from(format!("{duration_output}")))?;
-            debug!("Node {:?} duration {}", self.id(), duration_output);
-            // 然后利用子线程定时的向topic(data_id) 推送消息
-            tokio::spawn(async move {
-                let mut stream = IntervalStream::new(tokio::time::interval(duration));
-                while let Some(_) = stream.next().await {
-                    publisher.dyn_clone().publish(&vec![]).expect(&format!(
-                        "timer {duration_output} failed to publish timer tick message"
-                    ));
-                    debug!("timer {} publish success", duration_output);
+        let mut tasks = JoinSet::new();
+        let mut timers = BTreeMap::new();
+        // 收集所有的timer，每个timer按照自己的计划向topic(data_id) 推送消息
+        for timer in self.node_config().collect_input_timers().into_iter() {
+            let output_id = timer.output_id();
+            let publisher = Arc::new(TickPublisher {
+                topic: format!("{TIMER_NODE_ID}/{output_id}"),
+                publisher: self.0.sender(&output_id)?,
+                output_id,
+                seq: AtomicU64::new(0),
+            });
+            debug!("Node {:?} timer {}", self.id(), timer);
+            let id = tasks
+                .spawn(run_timer(timer.clone(), missed_tick, publisher.clone()))
+                .id();
+            timers.insert(id, (timer, publisher));
+        }
+
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                joined = tasks.join_next_with_id() => {
+                    let (id, restart) = match joined {
+                        // 所有的timer都已经结束，如只有一次性的timer
+                        None => {
+                            info!("All timers finished");
+                            shutdown.await;
+                            break;
+                        }
+                        Some(Ok((id, ()))) => (id, false),
+                        Some(Err(e)) => {
+                            let restart = e.is_panic();
+                            (e.id(), restart)
+                        }
+                    };
+                    let Some((timer, publisher)) = timers.remove(&id) else {
+                        continue;
+                    };
+                    if !restart {
+                        debug!("timer {timer} finished");
+                        continue;
+                    }
+                    error!(
+                        "timer {timer} panicked, restarting it in {}",
+                        HumanDuration(RESTART_DELAY)
+                    );
+                    metrics::inc(
+                        &metrics::TIMER_RESTARTS,
+                        &[("timer", publisher.output_id.as_str())],
+                        1,
+                    );
+                    let task = run_timer(timer.clone(), missed_tick, publisher.clone());
+                    let id = tasks
+                        .spawn(async move {
+                            tokio::time::sleep(RESTART_DELAY).await;
+                            task.await
+                        })
+                        .id();
+                    timers.insert(id, (timer, publisher));
                 }
+            }
+        }
+        tasks.shutdown().await;
+        Ok(())
+    }
+
+    /// 使用模拟时钟运行节点，只在测试驱动请求推进时钟时按时间顺序发布tick
+    /// 每次推进都会发布新的模拟时间，处理完请求之后带上请求的id作为响应
+    pub async fn run_simulated(&mut self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        clock::simulate();
+        let timers: Vec<Timer> = self
+            .node_config()
+            .collect_input_timers()
+            .into_iter()
+            .collect();
+        let mut publishers = vec![];
+        for timer in &timers {
+            let output_id = timer.output_id();
+            publishers.push(TickPublisher {
+                topic: format!("{TIMER_NODE_ID}/{output_id}"),
+                publisher: self.0.sender(&output_id)?,
+                output_id,
+                seq: AtomicU64::new(0),
             });
         }
+        let clock_publisher = self.0.sender(&DataId::from(CLOCK_OUTPUT.to_owned()))?;
+        let publish_clock = |now: SystemTime, ack: Option<String>| {
+            let update = serde_json::to_vec(&ClockUpdate::new(now, ack)).unwrap();
+            if let Err(e) = clock_publisher.publish(&update) {
+                error!("timer failed to publish simulated clock: {e}");
+            }
+        };
+        // 阻塞的接收者在独立的线程中转发推进请求
+        let mut subscriber = self
+            .0
+            .communication
+            .subscribe(ADVANCE_TOPIC)
+            .map_err(|e| anyhow!("failed to subscribe topic:{ADVANCE_TOPIC}: {e}"))?;
+        let (tx, requests) = flume::unbounded();
+        std::thread::spawn(move || {
+            while let Ok(Some(data)) = subscriber.recv() {
+                if tx.send(data).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut schedule = SimulatedSchedule::new(timers, clock::now());
+        info!(
+            "Timers use the simulated clock starting at {}",
+            humantime::format_rfc3339(schedule.now)
+        );
+        publish_clock(schedule.now, None);
+        let mut handled = None;
+        tokio::pin!(shutdown);
+        loop {
+            let data = tokio::select! {
+                _ = &mut shutdown => break,
+                data = requests.recv_async() => match data {
+                    Ok(data) => data,
+                    Err(_) => break,
+                },
+            };
+            let request: AdvanceRequest = match serde_json::from_slice(&data) {
+                Ok(request) => request,
+                Err(e) => {
+                    warn!("invalid clock advance request: {e}");
+                    continue;
+                }
+            };
+            // 重发的请求不再推进，只再次响应
+            if handled.as_ref() != Some(&request.id) {
+                let to = schedule.now + request.by.0;
+                for (index, at) in schedule.advance(to) {
+                    clock::set(at);
+                    publish_clock(at, None);
+                    publishers[index].publish(at, 0);
+                }
+                clock::set(to);
+                debug!("simulated clock advanced by {}", request.by);
+                handled = Some(request.id.clone());
+            }
+            publish_clock(schedule.now, Some(request.id));
+        }
         Ok(())
     }
 
@@ -87,3 +267,252 @@ impl TimerNode {
         &self.0.node_config()
     }
 }
+
+/// 定时器每次触发时发布的数据
+#[derive(Debug, Serialize)]
+struct Tick {
+    /// 从0开始的触发序号
+    seq: u64,
+    /// 计划的触发时间
+    scheduled: String,
+    /// 实际的触发时间
+    actual: String,
+    /// 实际触发时已经错过的计划触发次数
+    missed: u64,
+}
+
+/// 一个timer的发布者，timer 任务重新启动后继续使用，tick 的序号保持连续
+struct TickPublisher {
+    output_id: DataId,
+    topic: String,
+    publisher: Box<dyn Publisher>,
+    seq: AtomicU64,
+}
+
+impl TickPublisher {
+    fn publish(&self, scheduled: SystemTime, missed: u64) {
+        let timer = self.output_id.as_str();
+        metrics::inc(&metrics::TIMER_TICKS, &[("timer", timer)], 1);
+        let tick = Tick {
+            seq: self.seq.fetch_add(1, Ordering::Relaxed),
+            scheduled: humantime::format_rfc3339_micros(scheduled).to_string(),
+            actual: humantime::format_rfc3339_micros(clock::now()).to_string(),
+            missed,
+        };
+        if missed > 0 {
+            metrics::inc(&metrics::TIMER_MISSED_TICKS, &[("timer", timer)], missed);
+            warn!("timer {timer} is late, missed {missed} ticks");
+        }
+        // 每次tick开始一个新的trace
+        let span = ActiveSpan::start(
+            format!("publish {}", self.topic),
+            SpanKind::Producer,
+            None,
+            &[
+                ("dataflow.node", TIMER_NODE_ID),
+                ("dataflow.data_id", timer),
+                ("dataflow.topic", &self.topic),
+            ],
+        );
+        let metadata = span.as_ref().map(ActiveSpan::metadata).unwrap_or_default();
+        let data = serde_json::to_vec(&tick).unwrap();
+        match self.publisher.publish_with_metadata(&data, &metadata) {
+            Ok(()) => {
+                metrics::inc(&metrics::MESSAGES_SENT, &[("topic", &self.topic)], 1);
+                debug!("timer {timer} publish success");
+            }
+            Err(e) => {
+                metrics::inc(
+                    &metrics::MESSAGES_DROPPED,
+                    &[("operator", TIMER_NODE_ID), ("reason", "publish_failed")],
+                    1,
+                );
+                error!("timer {timer} failed to publish timer tick message: {e}");
+            }
+        }
+    }
+}
+
+/// 按照timer的计划发布tick
+/// missed_tick 决定了tick延迟之后如何处理错过的tick
+async fn run_timer(timer: Timer, missed_tick: MissedTick, publisher: Arc<TickPublisher>) {
+    match timer {
+        Timer::Interval { interval, offset } => {
+            let start = match offset {
+                Some(offset) => Instant::now() + until_aligned(SystemTime::now(), interval, offset),
+                None => Instant::now(),
+            };
+            let mut ticker = tokio::time::interval_at(start, interval);
+            ticker.set_missed_tick_behavior(match missed_tick {
+                MissedTick::Burst => MissedTickBehavior::Burst,
+                MissedTick::Skip => MissedTickBehavior::Skip,
+                MissedTick::Delay => MissedTickBehavior::Delay,
+            });
+            loop {
+                let scheduled = ticker.tick().await;
+                let late = scheduled.elapsed();
+                let missed = (late.as_nanos() / interval.as_nanos()) as u64;
+                publisher.publish(SystemTime::now() - late, missed);
+            }
+        }
+        Timer::Once { delay } => {
+            let scheduled = SystemTime::now() + delay;
+            tokio::time::sleep(delay).await;
+            publisher.publish(scheduled, 0);
+        }
+        Timer::Cron(schedule) => {
+            let mut last: Option<SystemTime> = None;
+            loop {
+                // 避免时钟的微小误差导致同一个时间点触发两次
+                let now = SystemTime::now();
+                let after = last.map_or(now, |last| last.max(now));
+                let Some(scheduled) = schedule.next_after(after) else {
+                    warn!("cron timer `{schedule}` will never fire again");
+                    break;
+                };
+                let wait = scheduled.duration_since(now).unwrap_or_default();
+                tokio::time::sleep(wait).await;
+                // 收集实际触发之前错过的触发时间
+                let now = SystemTime::now();
+                let mut missed = vec![];
+                let mut next = scheduled;
+                while let Some(n) = schedule.next_after(next).filter(|n| *n <= now) {
+                    missed.push(n);
+                    next = n;
+                }
+                match missed_tick {
+                    // 依次补发所有错过的tick
+                    MissedTick::Burst => {
+                        publisher.publish(scheduled, missed.len() as u64);
+                        for (i, scheduled) in missed.iter().enumerate() {
+                            publisher.publish(*scheduled, (missed.len() - i - 1) as u64);
+                        }
+                    }
+                    // cron 按照日历触发，skip 和 delay 都只发送一次，下一次仍然按照计划
+                    MissedTick::Skip | MissedTick::Delay => {
+                        publisher.publish(scheduled, missed.len() as u64)
+                    }
+                }
+                last = Some(next);
+            }
+        }
+    }
+}
+
+/// 模拟时钟下所有timer的计划
+struct SimulatedSchedule {
+    now: SystemTime,
+    timers: Vec<Timer>,
+    /// 每个timer下一次触发的时间，不再触发时为空
+    next: Vec<Option<SystemTime>>,
+}
+
+impl SimulatedSchedule {
+    fn new(timers: Vec<Timer>, start: SystemTime) -> Self {
+        let next = timers
+            .iter()
+            .map(|timer| match timer {
+                Timer::Interval {
+                    interval,
+                    offset: Some(offset),
+                } => Some(start + until_aligned(start, *interval, *offset)),
+                Timer::Interval { offset: None, .. } => Some(start),
+                Timer::Once { delay } => Some(start + *delay),
+                Timer::Cron(schedule) => schedule.next_after(start),
+            })
+            .collect();
+        Self {
+            now: start,
+            timers,
+            next,
+        }
+    }
+
+    /// 推进到 to，按时间顺序返回其间触发的timer下标和触发时间，同时触发的按timer排序
+    fn advance(&mut self, to: SystemTime) -> Vec<(usize, SystemTime)> {
+        let mut fired = vec![];
+        while let Some((index, at)) = self
+            .next
+            .iter()
+            .enumerate()
+            .filter_map(|(index, next)| next.filter(|at| *at <= to).map(|at| (index, at)))
+            .min_by_key(|(index, at)| (*at, *index))
+        {
+            fired.push((index, at));
+            self.next[index] = match &self.timers[index] {
+                Timer::Interval { interval, .. } => Some(at + *interval),
+                Timer::Once { .. } => None,
+                Timer::Cron(schedule) => schedule.next_after(at),
+            };
+        }
+        self.now = self.now.max(to);
+        fired
+    }
+}
+
+/// 距离下一个 unix 时间上 interval 的整数倍加 offset 的时刻的时长
+fn until_aligned(now: SystemTime, interval: Duration, offset: Duration) -> Duration {
+    let interval = interval.as_nanos();
+    let since_epoch = now
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let phase = (since_epoch + interval - offset.as_nanos() % interval) % interval;
+    if phase == 0 {
+        Duration::ZERO
+    } else {
+        Duration::from_nanos((interval - phase) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulated_schedule() {
+        let start = UNIX_EPOCH + clock::SIMULATED_EPOCH;
+        let timers = vec![
+            Timer::Interval {
+                interval: Duration::from_millis(100),
+                offset: None,
+            },
+            Timer::Interval {
+                interval: Duration::from_secs(5),
+                offset: Some(Duration::from_millis(250)),
+            },
+            Timer::Once {
+                delay: Duration::from_millis(150),
+            },
+        ];
+        let mut schedule = SimulatedSchedule::new(timers, start);
+        let fired = schedule.advance(start + Duration::from_millis(300));
+        let millis: Vec<_> = fired
+            .iter()
+            .map(|(index, at)| (*index, at.duration_since(start).unwrap().as_millis()))
+            .collect();
+        assert_eq!(
+            millis,
+            vec![(0, 0), (0, 100), (2, 150), (0, 200), (1, 250), (0, 300)]
+        );
+        assert_eq!(schedule.advance(start + Duration::from_secs(10)).len(), 98);
+    }
+
+    #[test]
+    fn test_until_aligned() {
+        let now = UNIX_EPOCH + Duration::from_millis(60_000 * 1000 + 10_500);
+        let interval = Duration::from_secs(60);
+        assert_eq!(
+            until_aligned(now, interval, Duration::from_secs(15)),
+            Duration::from_millis(4_500)
+        );
+        assert_eq!(
+            until_aligned(now, interval, Duration::from_secs(5)),
+            Duration::from_millis(54_500)
+        );
+        assert_eq!(
+            until_aligned(now, interval, Duration::from_millis(10_500)),
+            Duration::ZERO
+        );
+    }
+}
@@ -0,0 +1,529 @@
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    clock,
+    communication::Metadata,
+    descriptor::{descriptor::TracingConfig, DataflowId},
+};
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+/// 节点进程向launch汇报span时输出的行前缀，后面是span列表的json
+pub const SPANS_LINE_PREFIX: &str = "@dataflow:spans ";
+/// 元数据中追踪上下文的键，值为 W3C traceparent 格式
+pub const TRACEPARENT: &str = "traceparent";
+/// 汇报和导出span的间隔
+pub const EXPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 开启追踪时的dataflow id，没有开启时不记录span
+static DATAFLOW_ID: OnceCell<DataflowId> = OnceCell::new();
+/// 已经结束还没有导出的span
+static FINISHED: Lazy<Mutex<Vec<Span>>> = Lazy::new(|| Mutex::new(vec![]));
+
+/// 开启当前进程的追踪
+pub fn enable(dataflow_id: DataflowId) {
+    let _ = DATAFLOW_ID.set(dataflow_id);
+}
+
+/// 传播的追踪上下文
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+}
+
+impl TraceContext {
+    /// 编码为 traceparent，如 `00-<trace id>-<span id>-01`
+    pub fn traceparent(&self) -> String {
+        format!("00-{:032x}-{:016x}-01", self.trace_id, self.span_id)
+    }
+
+    pub fn from_traceparent(traceparent: &str) -> Option<Self> {
+        let mut parts = traceparent.split('-');
+        let _version = parts.next()?;
+        let trace_id = u128::from_str_radix(parts.next()?, 16).ok()?;
+        let span_id = u64::from_str_radix(parts.next()?, 16).ok()?;
+        Some(Self { trace_id, span_id })
+    }
+
+    /// 从消息的元数据中读取追踪上下文
+    pub fn from_metadata(metadata: &Metadata) -> Option<Self> {
+        metadata
+            .get(TRACEPARENT)
+            .and_then(|traceparent| Self::from_traceparent(traceparent))
+    }
+}
+
+/// span 的类型，与 OTLP 的 SpanKind 一致
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpanKind {
+    Internal,
+    Producer,
+    Consumer,
+}
+
+/// 一个已经结束的span
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Span {
+    pub trace_id: String,
+    pub span_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_span_id: Option<String>,
+    pub name: String,
+    pub kind: SpanKind,
+    /// unix 时间，单位为纳秒
+    pub start: u64,
+    pub end: u64,
+    pub attributes: BTreeMap<String, String>,
+}
+
+/// 正在进行的span，drop 时结束
+pub struct ActiveSpan {
+    context: TraceContext,
+    parent: Option<u64>,
+    name: String,
+    kind: SpanKind,
+    start: SystemTime,
+    /// 设置了结束时间时，drop 时不再使用当前时间
+    end: Option<SystemTime>,
+    attributes: BTreeMap<String, String>,
+}
+
+impl ActiveSpan {
+    /// 开始一个span，没有父span时开始一个新的trace
+    /// 没有开启追踪时返回 None
+    pub fn start(
+        name: impl Into<String>,
+        kind: SpanKind,
+        parent: Option<TraceContext>,
+        attributes: &[(&str, &str)],
+    ) -> Option<Self> {
+        let dataflow_id = DATAFLOW_ID.get()?;
+        let (span_id, _) = Uuid::new_v4().as_u64_pair();
+        let trace_id = match parent {
+            Some(parent) => parent.trace_id,
+            None => Uuid::new_v4().as_u128(),
+        };
+        let mut span_attributes =
+            BTreeMap::from([("dataflow.id".to_owned(), dataflow_id.to_string())]);
+        for (k, v) in attributes {
+            span_attributes.insert(k.to_string(), v.to_string());
+        }
+        Some(Self {
+            context: TraceContext { trace_id, span_id },
+            parent: parent.map(|parent| parent.span_id),
+            name: name.into(),
+            kind,
+            start: clock::now(),
+            end: None,
+            attributes: span_attributes,
+        })
+    }
+
+    /// 修改开始时间，如接收的span从消息发布的时间开始
+    pub fn started_at(mut self, start: SystemTime) -> Self {
+        self.start = start.min(self.start);
+        self
+    }
+
+    /// 将结束时间设置为现在，span 之后drop时使用该时间
+    pub fn mark_end(&mut self) {
+        self.end = Some(clock::now());
+    }
+
+    pub fn context(&self) -> TraceContext {
+        self.context
+    }
+
+    /// 传递给下游的元数据
+    pub fn metadata(&self) -> Metadata {
+        Metadata::from([(TRACEPARENT.to_owned(), self.context.traceparent())])
+    }
+}
+
+impl Drop for ActiveSpan {
+    fn drop(&mut self) {
+        let span = Span {
+            trace_id: format!("{:032x}", self.context.trace_id),
+            span_id: format!("{:016x}", self.context.span_id),
+            parent_span_id: self.parent.map(|parent| format!("{parent:016x}")),
+            name: std::mem::take(&mut self.name),
+            kind: self.kind,
+            start: unix_nanos(self.start),
+            end: unix_nanos(self.end.unwrap_or_else(clock::now)),
+            attributes: std::mem::take(&mut self.attributes),
+        };
+        FINISHED.lock().unwrap().push(span);
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+/// 取出所有已经结束的span
+fn take_finished() -> Vec<Span> {
+    std::mem::take(&mut *FINISHED.lock().unwrap())
+}
+
+/// 节点进程汇报span的一行，没有新的span时为空
+pub fn report_line() -> Option<String> {
+    let spans = take_finished();
+    if spans.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "{SPANS_LINE_PREFIX}{}",
+        serde_json::to_string(&spans).unwrap()
+    ))
+}
+
+/// 接收节点进程汇报的span，和launch自己的span一起导出
+/// 不是span汇报的行返回 false
+pub(crate) fn receive_report(node: &str, line: &str) -> bool {
+    let Some(json) = line.trim().strip_prefix(SPANS_LINE_PREFIX.trim_end()) else {
+        return false;
+    };
+    match serde_json::from_str::<Vec<Span>>(json) {
+        Ok(spans) => FINISHED.lock().unwrap().extend(spans),
+        Err(e) => warn!("ignored invalid spans report of node {node}: {e}"),
+    }
+    true
+}
+
+/// 定时导出所有的span到OTLP和trace文件
+/// file 为相对路径时相对于工作目录
+pub async fn export(config: &TracingConfig, working_dir: &Path) -> Result<()> {
+    let mut file = match &config.file {
+        Some(path) => {
+            let path = working_dir.join(path);
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            let mut file = tokio::fs::File::create(&path)
+                .await
+                .with_context(|| format!("failed to create trace file {}", path.display()))?;
+            // 结尾的 `]` 可以省略，trace viewer 可以读取还在写入的文件
+            file.write_all(b"[\n").await?;
+            Some(ChromeTrace {
+                file,
+                ids: ChromeIds::default(),
+            })
+        }
+        None => None,
+    };
+    let otlp = config.otlp.as_ref().map(|endpoint| {
+        let endpoint = endpoint.trim_end_matches('/');
+        if endpoint.ends_with("/v1/traces") {
+            endpoint.to_owned()
+        } else {
+            format!("{endpoint}/v1/traces")
+        }
+    });
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        let mut ticker = tokio::time::interval(EXPORT_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let spans = take_finished();
+            if spans.is_empty() {
+                continue;
+            }
+            debug!("Export {} spans", spans.len());
+            if let Some(file) = &mut file {
+                if let Err(e) = file.write(&spans).await {
+                    warn!("failed to write spans to trace file: {e}");
+                }
+            }
+            if let Some(endpoint) = &otlp {
+                let body = serde_json::to_vec(&otlp_request(&spans)).unwrap();
+                let result = client
+                    .post(endpoint)
+                    .header("content-type", "application/json")
+                    .body(body)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                if let Err(e) = result {
+                    warn!("failed to export {} spans to {endpoint}: {e}", spans.len());
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// span 所属的节点和operator
+fn span_source(span: &Span) -> (&str, &str) {
+    let node = span
+        .attributes
+        .get("dataflow.node")
+        .map(String::as_str)
+        .unwrap_or("dataflow");
+    let operator = span
+        .attributes
+        .get("dataflow.operator")
+        .map(String::as_str)
+        .unwrap_or(node);
+    (node, operator)
+}
+
+/// OTLP/HTTP 的json请求，每个节点作为一个服务
+fn otlp_request(spans: &[Span]) -> Value {
+    let mut by_node: BTreeMap<&str, Vec<Value>> = BTreeMap::new();
+    for span in spans {
+        let kind = match span.kind {
+            SpanKind::Internal => 1,
+            SpanKind::Producer => 4,
+            SpanKind::Consumer => 5,
+        };
+        let attributes: Vec<Value> = span
+            .attributes
+            .iter()
+            .map(|(k, v)| json!({"key": k, "value": {"stringValue": v}}))
+            .collect();
+        by_node.entry(span_source(span).0).or_default().push(json!({
+            "traceId": span.trace_id,
+            "spanId": span.span_id,
+            "parentSpanId": span.parent_span_id.clone().unwrap_or_default(),
+            "name": span.name,
+            "kind": kind,
+            "startTimeUnixNano": span.start.to_string(),
+            "endTimeUnixNano": span.end.to_string(),
+            "attributes": attributes,
+        }));
+    }
+    let resource_spans: Vec<Value> = by_node
+        .into_iter()
+        .map(|(node, spans)| {
+            json!({
+                "resource": {
+                    "attributes": [{"key": "service.name", "value": {"stringValue": node}}]
+                },
+                "scopeSpans": [{"scope": {"name": "dataflow"}, "spans": spans}]
+            })
+        })
+        .collect();
+    json!({ "resourceSpans": resource_spans })
+}
+
+/// chrome trace event 格式的trace文件，可以在 Perfetto 或 chrome://tracing 中查看
+/// 每个节点为一个进程，每个operator为一个线程，消息的发布和接收之间用flow事件连接
+struct ChromeTrace {
+    file: tokio::fs::File,
+    ids: ChromeIds,
+}
+
+impl ChromeTrace {
+    async fn write(&mut self, spans: &[Span]) -> Result<()> {
+        let mut out = String::new();
+        for span in spans {
+            for event in chrome_events(&mut self.ids, span) {
+                out.push_str(&event.to_string());
+                out.push_str(",\n");
+            }
+        }
+        self.file.write_all(out.as_bytes()).await?;
+        self.file.flush().await?;
+        Ok(())
+    }
+}
+
+/// 节点和operator对应的pid和tid，trace viewer 只接受数字的id
+#[derive(Default)]
+struct ChromeIds {
+    pids: BTreeMap<String, usize>,
+    tids: BTreeMap<(String, String), usize>,
+}
+
+impl ChromeIds {
+    /// 获取节点和operator的id，第一次出现时在 events 中加入进程名和线程名
+    fn get(&mut self, node: &str, operator: &str, events: &mut Vec<Value>) -> (usize, usize) {
+        let next_pid = self.pids.len() + 1;
+        let pid = *self.pids.entry(node.to_owned()).or_insert_with(|| {
+            events.push(json!({
+                "ph": "M", "name": "process_name", "pid": next_pid, "args": {"name": node}
+            }));
+            next_pid
+        });
+        let next_tid = self.tids.len() + 1;
+        let tid = *self
+            .tids
+            .entry((node.to_owned(), operator.to_owned()))
+            .or_insert_with(|| {
+                events.push(json!({
+                    "ph": "M", "name": "thread_name", "pid": pid, "tid": next_tid,
+                    "args": {"name": operator}
+                }));
+                next_tid
+            });
+        (pid, tid)
+    }
+}
+
+/// 一个span对应的chrome trace事件
+fn chrome_events(ids: &mut ChromeIds, span: &Span) -> Vec<Value> {
+    let mut events = vec![];
+    let (node, operator) = span_source(span);
+    let (pid, tid) = ids.get(node, operator, &mut events);
+    let ts = span.start as f64 / 1000.0;
+    let mut args = json!(span.attributes);
+    args["trace_id"] = json!(span.trace_id);
+    args["span_id"] = json!(span.span_id);
+    events.push(json!({
+        "name": span.name,
+        "cat": span.kind,
+        "ph": "X",
+        "ts": ts,
+        "dur": span.end.saturating_sub(span.start) as f64 / 1000.0,
+        "pid": pid,
+        "tid": tid,
+        "args": args,
+    }));
+    // 发布开始flow，接收结束flow，flow的id为发布span的id
+    match (span.kind, &span.parent_span_id) {
+        (SpanKind::Producer, _) => events.push(json!({
+            "name": "message", "cat": "flow", "ph": "s", "id": span.span_id,
+            "ts": ts, "pid": pid, "tid": tid,
+        })),
+        (SpanKind::Consumer, Some(parent)) => events.push(json!({
+            "name": "message", "cat": "flow", "ph": "f", "bp": "e", "id": parent,
+            "ts": ts, "pid": pid, "tid": tid,
+        })),
+        _ => {}
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traceparent() {
+        let context = TraceContext {
+            trace_id: 0x4bf92f3577b34da6a3ce929d0e0e4736,
+            span_id: 0x00f067aa0ba902b7,
+        };
+        let traceparent = context.traceparent();
+        assert_eq!(
+            traceparent,
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+        assert_eq!(TraceContext::from_traceparent(&traceparent), Some(context));
+        assert_eq!(TraceContext::from_traceparent("garbage"), None);
+
+        // 发布的span通过flow事件连接到接收的span
+        let span = |kind, span_id: &str, parent: Option<&str>| Span {
+            trace_id: "t".to_owned(),
+            span_id: span_id.to_owned(),
+            parent_span_id: parent.map(str::to_owned),
+            name: "span".to_owned(),
+            kind,
+            start: 2_000,
+            end: 5_000,
+            attributes: BTreeMap::from([("dataflow.node".to_owned(), "a".to_owned())]),
+        };
+        let request = otlp_request(&[span(SpanKind::Producer, "p", None)]);
+        assert_eq!(
+            request["resourceSpans"][0]["scopeSpans"][0]["spans"][0]["kind"],
+            4
+        );
+        let mut ids = ChromeIds::default();
+        let events = chrome_events(&mut ids, &span(SpanKind::Producer, "p", None));
+        assert_eq!(events[0]["name"], "process_name");
+        assert_eq!(events[1]["name"], "thread_name");
+        assert_eq!(events[2]["dur"], 3.0);
+        assert_eq!(events[3]["ph"], "s");
+        let events = chrome_events(&mut ids, &span(SpanKind::Consumer, "c", Some("p")));
+        assert_eq!(events[1]["id"], "p");
+    }
+
+    /// 读取一个http请求，返回请求行和body
+    async fn read_request(stream: &mut tokio::net::TcpStream) -> (String, Value) {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).await.unwrap();
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).await.unwrap();
+            if header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).await.unwrap();
+        (request_line, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_export_otlp() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        // 节点汇报的span由launch导出
+        let span = Span {
+            trace_id: "4bf92f3577b34da6a3ce929d0e0e4736".to_owned(),
+            span_id: "00f067aa0ba902b7".to_owned(),
+            parent_span_id: None,
+            name: "publish data".to_owned(),
+            kind: SpanKind::Producer,
+            start: 1_000,
+            end: 2_000,
+            attributes: BTreeMap::from([("dataflow.node".to_owned(), "camera".to_owned())]),
+        };
+        let line = format!(
+            "{SPANS_LINE_PREFIX}{}",
+            serde_json::to_string(&[&span]).unwrap()
+        );
+        assert!(receive_report("camera", &line));
+
+        let config = TracingConfig {
+            otlp: Some(format!("http://{address}")),
+            file: None,
+        };
+        export(&config, Path::new(".")).await.unwrap();
+        let (mut stream, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        let (request_line, body) = read_request(&mut stream).await;
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        assert!(
+            request_line.starts_with("POST /v1/traces "),
+            "{request_line}"
+        );
+        let resource = &body["resourceSpans"][0];
+        assert_eq!(
+            resource["resource"]["attributes"][0]["value"]["stringValue"],
+            "camera"
+        );
+        let spans = &resource["scopeSpans"][0]["spans"];
+        assert_eq!(spans[0]["name"], "publish data");
+        assert_eq!(spans[0]["traceId"], span.trace_id);
+        assert_eq!(spans[0]["spanId"], span.span_id);
+    }
+}